@@ -0,0 +1,6 @@
+-- 成功も含めた target ごとの送信結果を残す
+ALTER TABLE deliveries RENAME COLUMN last_error TO result;
+ALTER TABLE deliveries ALTER COLUMN result TYPE JSONB USING result::JSONB;
+ALTER TABLE deliveries ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+CREATE INDEX deliveries_message_id_idx ON deliveries (message_id);
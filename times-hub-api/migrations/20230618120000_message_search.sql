@@ -0,0 +1,10 @@
+-- 英語などの単語検索は tsvector で, 分かち書きされない日本語は trigram で検索する
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+ALTER TABLE messages
+    ADD COLUMN text_tsv TSVECTOR GENERATED ALWAYS AS (to_tsvector('simple', text)) STORED;
+
+CREATE INDEX messages_text_tsv_idx ON messages USING GIN (text_tsv);
+CREATE INDEX messages_text_trgm_idx ON messages USING GIN (text gin_trgm_ops);
+CREATE INDEX messages_created_at_idx ON messages (created_at);
+CREATE INDEX deliveries_workspace_id_idx ON deliveries (workspace_id);
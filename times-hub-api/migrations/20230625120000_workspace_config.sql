@@ -0,0 +1,2 @@
+-- webhook_url 以外の送信先ごとの設定 (bot token, chat id など)
+ALTER TABLE workspaces ADD COLUMN config JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,5 @@
+-- 送信済みの message を編集・削除するため, 送信先が返した message の参照を残す
+ALTER TABLE deliveries ADD COLUMN remote JSONB;
+
+ALTER TABLE messages ADD COLUMN edited_at TIMESTAMPTZ;
+ALTER TABLE messages ADD COLUMN deleted_at TIMESTAMPTZ;
@@ -0,0 +1,4 @@
+-- thread の返信の場合の親 message
+ALTER TABLE messages ADD COLUMN parent_id INTEGER REFERENCES messages (id) ON DELETE SET NULL;
+
+CREATE INDEX messages_parent_id_idx ON messages (parent_id);
@@ -0,0 +1,5 @@
+-- 送信先に表示される送信者の名前とアイコン. message の値は workspace の値より優先する
+ALTER TABLE workspaces ADD COLUMN display_name TEXT;
+ALTER TABLE workspaces ADD COLUMN avatar_url TEXT;
+ALTER TABLE messages ADD COLUMN display_name TEXT;
+ALTER TABLE messages ADD COLUMN avatar_url TEXT;
@@ -0,0 +1,11 @@
+-- message に添付されたファイル. 添付に対応していない送信先には GET /attachments/:key の link を送る
+CREATE TABLE attachments (
+    id SERIAL PRIMARY KEY,
+    message_id INTEGER NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+    key TEXT NOT NULL UNIQUE,
+    filename TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    data BYTEA NOT NULL
+);
+
+CREATE INDEX attachments_message_id_idx ON attachments (message_id);
@@ -0,0 +1,2 @@
+-- text と一緒に送る構造化された本文 (entity::Layout の JSON)
+ALTER TABLE messages ADD COLUMN layout JSONB;
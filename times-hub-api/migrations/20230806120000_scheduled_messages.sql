@@ -0,0 +1,4 @@
+-- 予約送信する message の送信時刻. send_at までは deliveries の next_attempt_at も send_at にして, outbox の worker に送らせる
+ALTER TABLE messages ADD COLUMN send_at TIMESTAMPTZ;
+
+CREATE INDEX messages_send_at_idx ON messages (send_at) WHERE send_at IS NOT NULL;
@@ -0,0 +1,39 @@
+-- cron 式で定期的に送る message
+CREATE TABLE recurring_messages (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    cron TEXT NOT NULL,
+    timezone TEXT NOT NULL,
+    -- workspace が削除されても定期送信を残すため workspaces への外部キーは張らない
+    targets INTEGER[] NOT NULL,
+    text TEXT NOT NULL,
+    display_name TEXT,
+    avatar_url TEXT,
+    layout JSONB,
+    -- API が止まっている間に過ぎた送信時刻の扱い (skip | catch_up)
+    missed_runs TEXT NOT NULL DEFAULT 'skip',
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    next_run_at TIMESTAMPTZ NOT NULL,
+    -- 更新のたびに増やす
+    version INTEGER NOT NULL DEFAULT 0,
+    -- scheduler が送信中の間は, 他の scheduler が取り出さない
+    locked_until TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX recurring_messages_next_run_at_idx ON recurring_messages (next_run_at) WHERE enabled;
+
+-- 定期送信の送信時刻ごとの実行結果
+CREATE TABLE recurring_runs (
+    id SERIAL PRIMARY KEY,
+    recurring_id INTEGER NOT NULL REFERENCES recurring_messages (id) ON DELETE CASCADE,
+    scheduled_at TIMESTAMPTZ NOT NULL,
+    status TEXT NOT NULL,
+    message_id INTEGER REFERENCES messages (id) ON DELETE SET NULL,
+    error TEXT,
+    ran_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    -- 同じ送信時刻を 2 回送らない
+    UNIQUE (recurring_id, scheduled_at)
+);
+
+CREATE INDEX recurring_runs_recurring_id_idx ON recurring_runs (recurring_id, id);
@@ -1,3 +1,8 @@
+use ::once_cell::sync::Lazy;
+use ::regex::Regex;
+use ::serde::{Deserialize, Serialize};
+use ::validator::Validate;
+
 pub type WorkspaceIdTypeAlias = i32;
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
@@ -24,8 +29,34 @@ impl std::fmt::Display for WorkspaceId {
 pub enum WorkspaceType {
     #[strum(serialize = "slack")]
     Slack,
+    #[strum(serialize = "slack_bot")]
+    SlackBot,
     #[strum(serialize = "discord")]
     Discord,
+    #[strum(serialize = "teams")]
+    Teams,
+    #[strum(serialize = "mattermost")]
+    Mattermost,
+    #[strum(serialize = "rocketchat")]
+    RocketChat,
+    #[strum(serialize = "telegram")]
+    Telegram,
+    #[strum(serialize = "matrix")]
+    Matrix,
+    #[strum(serialize = "google_chat")]
+    GoogleChat,
+    #[strum(serialize = "zulip")]
+    Zulip,
+    #[strum(serialize = "mastodon")]
+    Mastodon,
+    #[strum(serialize = "misskey")]
+    Misskey,
+    #[strum(serialize = "bluesky")]
+    Bluesky,
+    #[strum(serialize = "email")]
+    Email,
+    #[strum(serialize = "generic")]
+    Generic,
 }
 
 // DBの各Rowに対応した構造体
@@ -34,5 +65,280 @@ pub struct Workspace {
     pub id: WorkspaceId,
     pub name: String,
     pub ws_type: WorkspaceType,
+    // webhook を使わない WorkspaceType では空文字
     pub webhook_url: String,
+    // WorkspaceType ごとの設定 (JSON object)
+    pub config: serde_json::Value,
+    pub profile: Profile,
+}
+
+// 送信先に表示される送信者の名前とアイコン. 未設定の場合は送信先の既定のものになる
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Profile {
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
+impl Profile {
+    /// 未設定の項目を `fallback` で補う
+    pub fn or(self, fallback: Profile) -> Profile {
+        Profile {
+            display_name: self.display_name.or(fallback.display_name),
+            avatar_url: self.avatar_url.or(fallback.avatar_url),
+        }
+    }
+}
+
+pub type MessageIdTypeAlias = i32;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct MessageId {
+    id: MessageIdTypeAlias,
+}
+
+impl MessageId {
+    pub fn new(id: MessageIdTypeAlias) -> Self {
+        Self { id }
+    }
+    pub fn to_raw(&self) -> MessageIdTypeAlias {
+        self.id
+    }
+}
+
+impl std::fmt::Display for MessageId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.id.fmt(f) // delegate to i32
+    }
+}
+
+pub type DeliveryIdTypeAlias = i32;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct DeliveryId {
+    id: DeliveryIdTypeAlias,
+}
+
+impl DeliveryId {
+    pub fn new(id: DeliveryIdTypeAlias) -> Self {
+        Self { id }
+    }
+    pub fn to_raw(&self) -> DeliveryIdTypeAlias {
+        self.id
+    }
+}
+
+impl std::fmt::Display for DeliveryId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.id.fmt(f) // delegate to i32
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, strum::Display, strum::EnumString)]
+pub enum DeliveryState {
+    #[strum(serialize = "pending")]
+    Pending,
+    #[strum(serialize = "sent")]
+    Sent,
+    #[strum(serialize = "failed")]
+    Failed,
+}
+
+// outbox の deliveries の各Rowに, 送信する message の text を加えた構造体
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delivery {
+    pub id: DeliveryId,
+    pub message_id: MessageId,
+    pub workspace_id: WorkspaceId,
+    pub text: String,
+    // これまでの送信試行回数 (今回の試行を含む)
+    pub attempts: i32,
+    // thread の返信の場合の親 message
+    pub parent: Option<DeliveryParent>,
+    // message ごとに指定された送信者 (workspace の設定より優先する)
+    pub profile: Profile,
+    pub attachments: Vec<Attachment>,
+    pub layout: Option<Layout>,
+    // 前回までの試行で送信先に投稿済みの部分の参照. 再送ではこの続きから送る
+    pub remote: Option<serde_json::Value>,
+}
+
+// message に添付されたファイル
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    // GET /attachments/:key で公開するための推測できない key
+    pub key: String,
+    pub filename: String,
+    pub content_type: String,
+    // 送信先ごとの送信や再試行で複製しないように共有する
+    pub data: bytes::Bytes,
+}
+
+// text と一緒に送る構造化された本文. 対応していない送信先では text に加えて送る
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize, Validate)]
+pub struct Layout {
+    #[validate(length(min = 1, max = 150, message = "title must be 1 to 150 characters"))]
+    #[serde(default)]
+    pub title: Option<String>,
+    #[validate(length(max = 10, message = "fields must be at most 10"))]
+    #[validate]
+    #[serde(default)]
+    pub fields: Vec<LayoutField>,
+    // #RRGGBB
+    #[validate(regex(path = "COLOR", message = "color must be #RRGGBB"))]
+    #[serde(default)]
+    pub color: Option<String>,
+    #[validate(length(min = 1, max = 150, message = "footer must be 1 to 150 characters"))]
+    #[serde(default)]
+    pub footer: Option<String>,
+    #[validate(url(message = "image_url must be a URL"))]
+    #[serde(default)]
+    pub image_url: Option<String>,
+    #[validate(length(max = 5, message = "buttons must be at most 5"))]
+    #[validate]
+    #[serde(default)]
+    pub buttons: Vec<LayoutButton>,
+}
+
+static COLOR: Lazy<Regex> = Lazy::new(|| Regex::new(r"^#[0-9a-fA-F]{6}$").unwrap());
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Validate)]
+pub struct LayoutField {
+    #[validate(length(min = 1, max = 150, message = "field name must be 1 to 150 characters"))]
+    pub name: String,
+    #[validate(length(
+        min = 1,
+        max = 1000,
+        message = "field value must be 1 to 1000 characters"
+    ))]
+    pub value: String,
+}
+
+// link を開くだけの button
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Validate)]
+pub struct LayoutButton {
+    #[validate(length(min = 1, max = 75, message = "button label must be 1 to 75 characters"))]
+    pub label: String,
+    #[validate(url(message = "button url must be a URL"))]
+    pub url: String,
+}
+
+// 返信先の親 message と, 同じ送信先に送った親 message の参照
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeliveryParent {
+    pub text: String,
+    // 親 message が未送信か, 送信先が参照を返さない場合は None
+    pub remote: Option<serde_json::Value>,
+}
+
+// 送信履歴としての message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub id: MessageId,
+    pub text: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    // thread の返信の場合の親 message
+    pub parent_id: Option<MessageId>,
+    pub edited_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub layout: Option<Layout>,
+    // 予約送信の場合の送信時刻
+    pub send_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub deliveries: Vec<DeliveryRecord>,
+}
+
+// 送信履歴としての delivery
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeliveryRecord {
+    pub workspace_id: WorkspaceId,
+    pub state: DeliveryState,
+    pub attempts: i32,
+    // 最後の送信結果 (未送信の場合は None)
+    pub result: Option<serde_json::Value>,
+    // 送信先が返した message の参照 (編集・削除に使う)
+    pub remote: Option<serde_json::Value>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+pub type RecurringMessageIdTypeAlias = i32;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct RecurringMessageId {
+    id: RecurringMessageIdTypeAlias,
+}
+
+impl RecurringMessageId {
+    pub fn new(id: RecurringMessageIdTypeAlias) -> Self {
+        Self { id }
+    }
+    pub fn to_raw(&self) -> RecurringMessageIdTypeAlias {
+        self.id
+    }
+}
+
+impl std::fmt::Display for RecurringMessageId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.id.fmt(f) // delegate to i32
+    }
+}
+
+// API が止まっている間に過ぎた送信時刻の扱い
+#[derive(
+    Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize, strum::Display, strum::EnumString,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum MissedRunPolicy {
+    // 送らない
+    #[default]
+    #[strum(serialize = "skip")]
+    Skip,
+    // 再開した時に 1 回だけ送る
+    #[strum(serialize = "catch_up")]
+    CatchUp,
+}
+
+// cron 式で定期的に送る message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecurringMessage {
+    pub id: RecurringMessageId,
+    pub name: String,
+    // 5 つの field (分 時 日 月 曜日) の cron 式
+    pub cron: String,
+    // cron 式を解釈する IANA の timezone (e.g. Asia/Tokyo)
+    pub timezone: String,
+    pub targets: Vec<WorkspaceId>,
+    pub text: String,
+    pub profile: Profile,
+    pub layout: Option<Layout>,
+    pub missed_runs: MissedRunPolicy,
+    pub enabled: bool,
+    // 次の送信時刻
+    pub next_run_at: chrono::DateTime<chrono::Utc>,
+    // 更新のたびに増える. 送信中に更新された定期送信を, 更新前の内容で上書きしないために使う
+    pub version: i32,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, strum::Display, strum::EnumString)]
+pub enum RunState {
+    // 送信中. 送信中に API が止まった場合はこのまま残り, 再送しない
+    #[strum(serialize = "sending")]
+    Sending,
+    #[strum(serialize = "sent")]
+    Sent,
+    // missed_runs が skip で, 送信時刻を過ぎていた場合
+    #[strum(serialize = "skipped")]
+    Skipped,
+    #[strum(serialize = "failed")]
+    Failed,
+}
+
+// 定期送信の送信時刻ごとの実行結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecurringRun {
+    pub scheduled_at: chrono::DateTime<chrono::Utc>,
+    pub state: RunState,
+    // 送った message (送信履歴)
+    pub message_id: Option<MessageId>,
+    pub error: Option<String>,
+    pub ran_at: chrono::DateTime<chrono::Utc>,
 }
@@ -1,9 +1,11 @@
 mod entity;
 mod message;
+mod recurring;
 mod workspace;
 
 use ::anyhow::{Context, Result};
-use ::axum::routing::{get, post};
+use ::axum::extract::DefaultBodyLimit;
+use ::axum::routing::{get, patch, post};
 use ::axum::Extension;
 use ::axum::Router;
 use ::dotenv::dotenv;
@@ -14,12 +16,27 @@ use ::std::env;
 use ::std::net::SocketAddr;
 use ::std::str::FromStr;
 use ::std::sync::Arc;
+use ::std::time::Duration;
 use ::tower_http::cors::{AllowOrigin, Any, CorsLayer};
-use message::handler::send_message;
+use message::handler::{
+    all_messages, cancel_scheduled, delete_message, edit_message, find_attachment, find_message,
+    reply_message, scheduled_messages, search_messages, send_message, update_scheduled,
+    MAX_UPLOAD_BYTES,
+};
+use message::outbox;
+use message::repository::MessageRepository;
+use message::service::{validate_config, DeliveryConfig};
+use recurring::handler::{
+    all_recurring, create_recurring, delete_recurring, find_recurring, recurring_runs,
+    update_recurring,
+};
+use recurring::repository::RecurringRepository;
+use recurring::service::{spawn_scheduler, RecurringConfig};
 use workspace::handler::{
     all_workspaces, create_workspace, delete_workspace, find_workspace, update_workspace,
 };
 use workspace::repository;
+use workspace::service::ValidateConfig;
 
 fn init_logging() {
     let log_level = env::var("RUST_LOG").unwrap_or("info".to_string());
@@ -33,6 +50,8 @@ struct Config {
     host_ip: String,
     host_port: u16,
     allow_origins: Option<Vec<HeaderValue>>,
+    delivery: DeliveryConfig,
+    recurring: RecurringConfig,
 }
 
 impl Config {
@@ -48,10 +67,69 @@ impl Config {
             Ok(s) => Some(vec![HeaderValue::from_str(s.as_str())?]),
             Err(_) => None,
         };
+        let mut delivery = DeliveryConfig::default();
+        if let Ok(s) = env::var("TIMES_HUB_MESSAGE_CONCURRENCY") {
+            delivery.concurrency = s
+                .parse()
+                .context("invalid [TIMES_HUB_MESSAGE_CONCURRENCY]")?;
+        }
+        if let Ok(s) = env::var("TIMES_HUB_MESSAGE_TIMEOUT_SECS") {
+            delivery.timeout = Duration::from_secs(
+                s.parse()
+                    .context("invalid [TIMES_HUB_MESSAGE_TIMEOUT_SECS]")?,
+            );
+        }
+        if let Ok(s) = env::var("TIMES_HUB_MESSAGE_MAX_RETRIES") {
+            delivery.retry.max_retries = s
+                .parse()
+                .context("invalid [TIMES_HUB_MESSAGE_MAX_RETRIES]")?;
+        }
+        if let Ok(s) = env::var("TIMES_HUB_MESSAGE_RETRY_BASE_MS") {
+            delivery.retry.base_backoff = Duration::from_millis(
+                s.parse()
+                    .context("invalid [TIMES_HUB_MESSAGE_RETRY_BASE_MS]")?,
+            );
+        }
+        if let Ok(s) = env::var("TIMES_HUB_MESSAGE_RETRY_MAX_MS") {
+            delivery.retry.max_backoff = Duration::from_millis(
+                s.parse()
+                    .context("invalid [TIMES_HUB_MESSAGE_RETRY_MAX_MS]")?,
+            );
+        }
+        if let Ok(s) = env::var("TIMES_HUB_OUTBOX_POLL_SECS") {
+            delivery.outbox.poll_interval =
+                Duration::from_secs(s.parse().context("invalid [TIMES_HUB_OUTBOX_POLL_SECS]")?);
+        }
+        if let Ok(s) = env::var("TIMES_HUB_OUTBOX_MAX_RETRIES") {
+            delivery.outbox.retry.max_retries = s
+                .parse()
+                .context("invalid [TIMES_HUB_OUTBOX_MAX_RETRIES]")?;
+        }
+        delivery.public_url = env::var("TIMES_HUB_PUBLIC_URL").ok();
+        let mut recurring = RecurringConfig::default();
+        if let Ok(s) = env::var("TIMES_HUB_RECURRING_POLL_SECS") {
+            recurring.poll_interval = Duration::from_secs(
+                s.parse()
+                    .context("invalid [TIMES_HUB_RECURRING_POLL_SECS]")?,
+            );
+        }
+        if let Ok(s) = env::var("TIMES_HUB_RECURRING_GRACE_SECS") {
+            recurring.grace = Duration::from_secs(
+                s.parse()
+                    .context("invalid [TIMES_HUB_RECURRING_GRACE_SECS]")?,
+            );
+        }
+        // 猶予が poll の間隔より短いと, 時間どおりに動いていても送信時刻を過ぎたものとして扱ってしまう
+        anyhow::ensure!(
+            recurring.grace >= recurring.poll_interval,
+            "[TIMES_HUB_RECURRING_GRACE_SECS] must not be shorter than [TIMES_HUB_RECURRING_POLL_SECS]"
+        );
         Ok(Self {
             host_ip,
             host_port,
             allow_origins,
+            delivery,
+            recurring,
         })
     }
 }
@@ -80,13 +158,41 @@ async fn main() {
         tracing::debug!("start connecting to database: {}", database_url);
         let pool = PgPool::connect(database_url)
             .await
-            .expect(&format!("failed to connect to database: {}", database_url));
+            .unwrap_or_else(|_| panic!("failed to connect to database: {}", database_url));
 
-        let repo = repository::pg::WorkspaceRepositoryForDB::new(pool);
-        app = create_app(repo, &config);
+        let repo = repository::pg::WorkspaceRepositoryForDB::new(pool.clone());
+        let outbox = message::repository::pg::MessageRepositoryForDB::new(pool.clone());
+        let recurring = recurring::repository::pg::RecurringRepositoryForDB::new(pool);
+        outbox::spawn_worker(
+            Arc::new(repo.clone()),
+            Arc::new(outbox.clone()),
+            config.delivery.clone(),
+        );
+        spawn_scheduler(
+            Arc::new(repo.clone()),
+            Arc::new(outbox.clone()),
+            Arc::new(recurring.clone()),
+            config.delivery.clone(),
+            config.recurring.clone(),
+        );
+        app = create_app(repo, outbox, recurring, &config);
     } else {
         let repo = repository::test_utils::WorkspaceRepositoryForMemory::new();
-        app = create_app(repo, &config);
+        let outbox = message::repository::test_utils::MessageRepositoryForMemory::new();
+        let recurring = recurring::repository::test_utils::RecurringRepositoryForMemory::new();
+        outbox::spawn_worker(
+            Arc::new(repo.clone()),
+            Arc::new(outbox.clone()),
+            config.delivery.clone(),
+        );
+        spawn_scheduler(
+            Arc::new(repo.clone()),
+            Arc::new(outbox.clone()),
+            Arc::new(recurring.clone()),
+            config.delivery.clone(),
+            config.recurring.clone(),
+        );
+        app = create_app(repo, outbox, recurring, &config);
     }
 
     let addr =
@@ -110,9 +216,11 @@ async fn main() {
         .unwrap();
 }
 
-fn create_app<T>(repo: T, config: &Config) -> Router
+fn create_app<T, M, R>(repo: T, outbox: M, recurring: R, config: &Config) -> Router
 where
     T: repository::WorkspaceRepository,
+    M: MessageRepository,
+    R: RecurringRepository,
 {
     let mut cors_layer = CorsLayer::new()
         .allow_methods(Any)
@@ -137,8 +245,44 @@ where
                 .patch(update_workspace::<T>)
                 .delete(delete_workspace::<T>),
         )
-        .route("/message", post(send_message::<T>))
+        .route(
+            "/message",
+            post(send_message::<T, M>).layer(DefaultBodyLimit::max(MAX_UPLOAD_BYTES)),
+        )
+        .route("/messages", get(all_messages::<M>))
+        .route("/messages/search", get(search_messages::<M>))
+        .route(
+            "/messages/:id",
+            get(find_message::<M>)
+                .patch(edit_message::<T, M>)
+                .delete(delete_message::<T, M>),
+        )
+        .route(
+            "/messages/:id/replies",
+            post(reply_message::<T, M>).layer(DefaultBodyLimit::max(MAX_UPLOAD_BYTES)),
+        )
+        .route("/attachments/:key", get(find_attachment::<M>))
+        .route("/scheduled", get(scheduled_messages::<M>))
+        .route(
+            "/scheduled/:id",
+            patch(update_scheduled::<T, M>).delete(cancel_scheduled::<M>),
+        )
+        .route(
+            "/recurring",
+            post(create_recurring::<T, R>).get(all_recurring::<R>),
+        )
+        .route(
+            "/recurring/:id",
+            get(find_recurring::<R>)
+                .patch(update_recurring::<T, R>)
+                .delete(delete_recurring::<R>),
+        )
+        .route("/recurring/:id/runs", get(recurring_runs::<R>))
         .layer(Extension(Arc::new(repo)))
+        .layer(Extension(validate_config as ValidateConfig))
+        .layer(Extension(Arc::new(outbox)))
+        .layer(Extension(Arc::new(recurring)))
+        .layer(Extension(Arc::new(config.delivery.clone())))
         .layer(cors_layer)
 }
 
@@ -1,85 +1,903 @@
 use crate::entity;
-use crate::message::service::get_sender;
-use crate::message::service::Sender;
+use crate::message::repository::{
+    MessageRepository, MessageRepositoryError, NewMessage, SearchCondition,
+};
+use crate::message::service::{self, MessageError};
 use crate::workspace::handler::{repository_error_to_status_code, ValidatedJson};
 use crate::workspace::repository::WorkspaceRepository;
 
+use ::axum::async_trait;
+use ::axum::body::Bytes;
 use ::axum::extract::Extension;
+use ::axum::extract::FromRequest;
+use ::axum::extract::Multipart;
+use ::axum::extract::Path;
+use ::axum::extract::Query;
+use ::axum::http::header;
 use ::axum::http::StatusCode;
+use ::axum::response::IntoResponse;
+use ::axum::BoxError;
+use ::axum::Json;
+use ::chrono::{DateTime, Utc};
+use ::http::Request;
+use ::serde::de::DeserializeOwned;
 use ::serde::Deserialize;
 use ::serde::Serialize;
-use ::std::collections::HashSet;
 use ::std::sync::Arc;
 use ::validator::Validate;
 
+// POST /message などの request body の上限 (添付ファイルを含む)
+pub const MAX_UPLOAD_BYTES: usize = 50 * 1024 * 1024;
+
+// JSON か, `payload` に JSON を入れた multipart/form-data の request body.
+// multipart の場合は file 名のある part を添付ファイルとして受け取る
+#[derive(Debug)]
+pub struct MessageForm<T> {
+    pub payload: T,
+    pub attachments: Vec<entity::Attachment>,
+}
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for MessageForm<T>
+where
+    T: DeserializeOwned + Validate + Send,
+    // same as axum::Json::from_request and axum::extract::Multipart::from_request
+    B: http_body::Body + Send + 'static,
+    B::Data: Send + Into<Bytes>,
+    B::Error: Into<BoxError>,
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let is_multipart = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("multipart/form-data"));
+        if !is_multipart {
+            let ValidatedJson(payload) = ValidatedJson::<T>::from_request(req, state).await?;
+            return Ok(Self {
+                payload,
+                attachments: vec![],
+            });
+        }
+
+        let bad_request = |message: String| (StatusCode::BAD_REQUEST, message);
+        let mut multipart = Multipart::from_request(req, state)
+            .await
+            .map_err(|e| bad_request(format!("Multipart parse error: [{}]", e)))?;
+        let mut payload = None;
+        let mut attachments = vec![];
+        while let Some(field) = multipart
+            .next_field()
+            .await
+            .map_err(|e| bad_request(format!("Multipart parse error: [{}]", e)))?
+        {
+            let name = field.name().map(str::to_string);
+            match field.file_name().map(str::to_string) {
+                Some(filename) => {
+                    let content_type = field
+                        .content_type()
+                        .and_then(|c| c.parse::<mime::Mime>().ok())
+                        .unwrap_or(mime::APPLICATION_OCTET_STREAM)
+                        .to_string();
+                    let data = field
+                        .bytes()
+                        .await
+                        .map_err(|e| bad_request(format!("Multipart parse error: [{}]", e)))?;
+                    attachments.push(entity::Attachment {
+                        key: uuid::Uuid::new_v4().to_string(),
+                        filename,
+                        content_type,
+                        data,
+                    });
+                }
+                None if name.as_deref() == Some("payload") => {
+                    let text = field
+                        .text()
+                        .await
+                        .map_err(|e| bad_request(format!("Multipart parse error: [{}]", e)))?;
+                    let value = serde_json::from_str::<T>(&text)
+                        .map_err(|e| bad_request(format!("Json parse error: [{}]", e)))?;
+                    payload = Some(value);
+                }
+                None => {}
+            }
+        }
+        let payload = payload.ok_or_else(|| bad_request("payload is required".to_string()))?;
+        payload.validate().map_err(|rejection| {
+            let message = format!("Validation error: [{}]", rejection).replace('\n', ", ");
+            bad_request(message)
+        })?;
+        Ok(Self {
+            payload,
+            attachments,
+        })
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Validate)]
 pub struct MessagePayload {
     pub targets: Vec<entity::WorkspaceIdTypeAlias>,
     pub text: String,
+    // workspace の送信者の名前とアイコンをこの message だけ上書きする
+    #[validate(length(min = 1, max = 80, message = "display_name must be 1 to 80 characters"))]
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[validate(url(message = "avatar_url must be a URL"))]
+    #[serde(default)]
+    pub avatar_url: Option<String>,
+    // Slack の blocks や Discord の embed として送る構造化された本文
+    #[validate]
+    #[serde(default)]
+    pub layout: Option<entity::Layout>,
+    // 指定した場合はすぐには送らず, この時刻に送る
+    #[serde(default)]
+    pub send_at: Option<DateTime<Utc>>,
 }
 
-// TODO: ビジネスロジックの分離
-pub fn send_message<T>(
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Validate)]
+pub struct EditMessagePayload {
+    pub text: String,
+}
+
+// PATCH /scheduled/:id の request body. 指定した項目だけ変える
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Validate)]
+pub struct UpdateScheduledPayload {
+    #[serde(default)]
+    pub send_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Validate)]
+pub struct ReplyMessagePayload {
+    pub text: String,
+    // workspace の送信者の名前とアイコンをこの返信だけ上書きする
+    #[validate(length(min = 1, max = 80, message = "display_name must be 1 to 80 characters"))]
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[validate(url(message = "avatar_url must be a URL"))]
+    #[serde(default)]
+    pub avatar_url: Option<String>,
+    // Slack の blocks や Discord の embed として送る構造化された本文
+    #[validate]
+    #[serde(default)]
+    pub layout: Option<entity::Layout>,
+}
+
+pub async fn send_message<T, M>(
     Extension(repo): Extension<Arc<T>>,
-    ValidatedJson(payload): ValidatedJson<MessagePayload>,
-) -> impl std::future::Future<Output = StatusCode> + Send + 'static
+    Extension(outbox): Extension<Arc<M>>,
+    Extension(config): Extension<Arc<service::DeliveryConfig>>,
+    MessageForm {
+        payload,
+        attachments,
+    }: MessageForm<MessagePayload>,
+) -> Result<impl IntoResponse, StatusCode>
 where
     T: WorkspaceRepository,
+    M: MessageRepository,
 {
-    async move {
-        // db から一覧取得
-        let r = repo.all().await.map_err(repository_error_to_status_code);
-        let ws_vec;
-        match r {
-            Ok(w) => {
-                ws_vec = w;
-            }
-            Err(e) => return e,
-        }
+    let message = NewMessage {
+        profile: entity::Profile {
+            display_name: payload.display_name,
+            avatar_url: payload.avatar_url,
+        },
+        attachments,
+        layout: payload.layout,
+        send_at: payload.send_at,
+        ..NewMessage::new(payload.text.as_str())
+    };
+    if message.send_at.is_some() {
+        let res = service::schedule_message(repo, outbox, &config, payload.targets, message)
+            .await
+            .map_err(message_error_to_status_code)?;
+        return Ok((StatusCode::ACCEPTED, Json(res)).into_response());
+    }
+    let res = service::send_message(repo, outbox, &config, payload.targets, message)
+        .await
+        .map_err(message_error_to_status_code)?;
+    Ok((res.status_code(), Json(res)).into_response())
+}
 
-        // 一覧から targets に含まれるものを抽出
-        let mut target_ws_ids = HashSet::new();
-        for id in payload.targets {
-            target_ws_ids.insert(id);
-        }
+// GET /messages の query
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListMessagesQuery {
+    pub limit: Option<i64>,
+    pub before: Option<entity::MessageIdTypeAlias>,
+}
+
+pub async fn all_messages<M>(
+    Extension(repo): Extension<Arc<M>>,
+    Query(query): Query<ListMessagesQuery>,
+) -> Result<impl IntoResponse, StatusCode>
+where
+    M: MessageRepository,
+{
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let before = query.before.map(entity::MessageId::new);
+    let messages = service::all_messages(repo, limit, before)
+        .await
+        .map_err(message_error_to_status_code)?;
+    Ok((StatusCode::OK, Json(messages)))
+}
+
+// GET /messages/search の query
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchMessagesQuery {
+    pub q: String,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub workspace: Option<entity::WorkspaceIdTypeAlias>,
+    pub limit: Option<i64>,
+    pub before: Option<entity::MessageIdTypeAlias>,
+}
+
+pub async fn search_messages<M>(
+    Extension(repo): Extension<Arc<M>>,
+    Query(query): Query<SearchMessagesQuery>,
+) -> Result<impl IntoResponse, StatusCode>
+where
+    M: MessageRepository,
+{
+    let keyword = query.q.trim();
+    if keyword.is_empty() {
+        tracing::warn!("error: empty search keyword");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let condition = SearchCondition {
+        keyword: keyword.to_string(),
+        from: query.from,
+        to: query.to,
+        workspace_id: query.workspace.map(entity::WorkspaceId::new),
+    };
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let before = query.before.map(entity::MessageId::new);
+    let messages = service::search_messages(repo, condition, limit, before)
+        .await
+        .map_err(message_error_to_status_code)?;
+    Ok((StatusCode::OK, Json(messages)))
+}
+
+pub async fn find_message<M>(
+    Extension(repo): Extension<Arc<M>>,
+    Path(id): Path<entity::MessageIdTypeAlias>,
+) -> Result<impl IntoResponse, StatusCode>
+where
+    M: MessageRepository,
+{
+    let id = entity::MessageId::new(id);
+    let message = service::find_message(repo, id)
+        .await
+        .map_err(message_error_to_status_code)?;
+    Ok((StatusCode::OK, Json(message)))
+}
+
+pub async fn scheduled_messages<M>(
+    Extension(repo): Extension<Arc<M>>,
+) -> Result<impl IntoResponse, StatusCode>
+where
+    M: MessageRepository,
+{
+    let messages = service::scheduled_messages(repo)
+        .await
+        .map_err(message_error_to_status_code)?;
+    Ok((StatusCode::OK, Json(messages)))
+}
+
+pub async fn update_scheduled<T, M>(
+    Extension(repo): Extension<Arc<T>>,
+    Extension(outbox): Extension<Arc<M>>,
+    Extension(config): Extension<Arc<service::DeliveryConfig>>,
+    Path(id): Path<entity::MessageIdTypeAlias>,
+    ValidatedJson(payload): ValidatedJson<UpdateScheduledPayload>,
+) -> Result<impl IntoResponse, StatusCode>
+where
+    T: WorkspaceRepository,
+    M: MessageRepository,
+{
+    let id = entity::MessageId::new(id);
+    let (send_at, text) = (payload.send_at, payload.text.as_deref());
+    let message = service::update_scheduled(repo, outbox, &config, id, send_at, text)
+        .await
+        .map_err(message_error_to_status_code)?;
+    Ok((StatusCode::OK, Json(message)))
+}
+
+pub async fn cancel_scheduled<M>(
+    Extension(repo): Extension<Arc<M>>,
+    Path(id): Path<entity::MessageIdTypeAlias>,
+) -> StatusCode
+where
+    M: MessageRepository,
+{
+    let id = entity::MessageId::new(id);
+    service::cancel_scheduled(repo, id)
+        .await
+        .map(|_| StatusCode::NO_CONTENT)
+        .map_err(message_error_to_status_code)
+        .unwrap_or_else(|e| e)
+}
+
+// 添付に対応していない送信先に送った link から参照される.
+// 誰でも取得できるため, client が指定した content type は信用せず, 中身から判定した画像だけを browser で開かせる
+pub async fn find_attachment<M>(
+    Extension(repo): Extension<Arc<M>>,
+    Path(key): Path<String>,
+) -> Result<impl IntoResponse, StatusCode>
+where
+    M: MessageRepository,
+{
+    let attachment = repo
+        .find_attachment(&key)
+        .await
+        .map_err(message_error_to_status_code)?;
+    let (disposition, content_type) = match raster_image_type(&attachment.data) {
+        Some(content_type) => ("inline", content_type.to_string()),
+        None => ("attachment", attachment.content_type),
+    };
+    // header に入れられない文字は置き換える
+    let filename = attachment
+        .filename
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("{}; filename=\"{}\"", disposition, filename),
+            ),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+            (header::CONTENT_SECURITY_POLICY, "sandbox".to_string()),
+        ],
+        attachment.data,
+    ))
+}
 
-        let ws_vec: Vec<entity::Workspace> = ws_vec
-            .into_iter()
-            .filter(|ws| target_ws_ids.contains(&ws.id.to_raw()))
+// 先頭の bytes から判定した, script を含められない画像の content type
+fn raster_image_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(b"\xff\xd8\xff") {
+        Some("image/jpeg")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+pub async fn edit_message<T, M>(
+    Extension(repo): Extension<Arc<T>>,
+    Extension(outbox): Extension<Arc<M>>,
+    Extension(config): Extension<Arc<service::DeliveryConfig>>,
+    Path(id): Path<entity::MessageIdTypeAlias>,
+    ValidatedJson(payload): ValidatedJson<EditMessagePayload>,
+) -> Result<impl IntoResponse, StatusCode>
+where
+    T: WorkspaceRepository,
+    M: MessageRepository,
+{
+    let id = entity::MessageId::new(id);
+    let res = service::edit_message(repo, outbox, &config, id, payload.text.as_str())
+        .await
+        .map_err(message_error_to_status_code)?;
+    Ok((res.status_code(), Json(res)))
+}
+
+pub async fn reply_message<T, M>(
+    Extension(repo): Extension<Arc<T>>,
+    Extension(outbox): Extension<Arc<M>>,
+    Extension(config): Extension<Arc<service::DeliveryConfig>>,
+    Path(id): Path<entity::MessageIdTypeAlias>,
+    MessageForm {
+        payload,
+        attachments,
+    }: MessageForm<ReplyMessagePayload>,
+) -> Result<impl IntoResponse, StatusCode>
+where
+    T: WorkspaceRepository,
+    M: MessageRepository,
+{
+    let id = entity::MessageId::new(id);
+    let message = NewMessage {
+        profile: entity::Profile {
+            display_name: payload.display_name,
+            avatar_url: payload.avatar_url,
+        },
+        attachments,
+        layout: payload.layout,
+        ..NewMessage::new(payload.text.as_str())
+    };
+    let res = service::reply_message(repo, outbox, &config, id, message)
+        .await
+        .map_err(message_error_to_status_code)?;
+    Ok((res.status_code(), Json(res)))
+}
+
+pub async fn delete_message<T, M>(
+    Extension(repo): Extension<Arc<T>>,
+    Extension(outbox): Extension<Arc<M>>,
+    Extension(config): Extension<Arc<service::DeliveryConfig>>,
+    Path(id): Path<entity::MessageIdTypeAlias>,
+) -> Result<impl IntoResponse, StatusCode>
+where
+    T: WorkspaceRepository,
+    M: MessageRepository,
+{
+    let id = entity::MessageId::new(id);
+    let res = service::delete_message(repo, outbox, &config, id)
+        .await
+        .map_err(message_error_to_status_code)?;
+    Ok((res.status_code(), Json(res)))
+}
+
+/// message の error を status code にする. それ以外は workspace の error として扱う
+pub fn message_error_to_status_code(e: anyhow::Error) -> StatusCode {
+    let status = match e.downcast_ref::<MessageError>() {
+        Some(MessageError::TooLong(_) | MessageError::PastSendAt(_)) => StatusCode::BAD_REQUEST,
+        _ => match e.downcast_ref::<MessageRepositoryError>() {
+            Some(MessageRepositoryError::NotFound(_))
+            | Some(MessageRepositoryError::AttachmentNotFound(_)) => StatusCode::NOT_FOUND,
+            _ => return repository_error_to_status_code(e),
+        },
+    };
+    tracing::error!("error: {}", e);
+    status
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message::repository::test_utils::MessageRepositoryForMemory;
+    use crate::message::service::{DeliveryResult, DeliveryStatus, MessageResponse};
+    use crate::workspace::repository::test_utils::WorkspaceRepositoryForMemory;
+
+    use ::axum::body::Body;
+    use ::axum::routing::{get, patch, post};
+    use ::axum::Router;
+    use ::http::Request;
+    use ::hyper::header::CONTENT_TYPE;
+    use ::tower::ServiceExt;
+
+    fn create_app() -> Router {
+        Router::new()
+            .route(
+                "/message",
+                post(send_message::<WorkspaceRepositoryForMemory, MessageRepositoryForMemory>),
+            )
+            .route("/messages", get(all_messages::<MessageRepositoryForMemory>))
+            .route(
+                "/messages/search",
+                get(search_messages::<MessageRepositoryForMemory>),
+            )
+            .route(
+                "/messages/:id",
+                get(find_message::<MessageRepositoryForMemory>)
+                    .patch(edit_message::<WorkspaceRepositoryForMemory, MessageRepositoryForMemory>)
+                    .delete(
+                        delete_message::<WorkspaceRepositoryForMemory, MessageRepositoryForMemory>,
+                    ),
+            )
+            .route(
+                "/messages/:id/replies",
+                post(reply_message::<WorkspaceRepositoryForMemory, MessageRepositoryForMemory>),
+            )
+            .route(
+                "/attachments/:key",
+                get(find_attachment::<MessageRepositoryForMemory>),
+            )
+            .route(
+                "/scheduled",
+                get(scheduled_messages::<MessageRepositoryForMemory>),
+            )
+            .route(
+                "/scheduled/:id",
+                patch(update_scheduled::<WorkspaceRepositoryForMemory, MessageRepositoryForMemory>)
+                    .delete(cancel_scheduled::<MessageRepositoryForMemory>),
+            )
+            .layer(Extension(Arc::new(WorkspaceRepositoryForMemory::new())))
+            .layer(Extension(Arc::new(MessageRepositoryForMemory::new())))
+            .layer(Extension(Arc::new(service::DeliveryConfig::default())))
+    }
+
+    async fn get_json<R: serde::de::DeserializeOwned>(
+        app: &Router,
+        uri: &str,
+    ) -> (StatusCode, Option<R>) {
+        let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        let status = res.status();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).ok())
+    }
+
+    #[tokio::test]
+    async fn send_message_reports_unknown_targets() {
+        let app = create_app();
+
+        let req = Request::builder()
+            .method("POST")
+            .uri("/message")
+            .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(r#"{"targets": [2, 1, 2], "text": "hello"}"#))
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: MessageResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body.results,
+            vec![
+                DeliveryResult {
+                    target: 2,
+                    status: DeliveryStatus::UnknownWorkspace,
+                    will_retry: false,
+                },
+                DeliveryResult {
+                    target: 1,
+                    status: DeliveryStatus::UnknownWorkspace,
+                    will_retry: false,
+                },
+            ]
+        );
+
+        // 送信履歴にも残る
+        let (status, message) =
+            get_json::<service::ResponseMessage>(&app, &format!("/messages/{}", body.message_id))
+                .await;
+        assert_eq!(status, StatusCode::OK);
+        let message = message.unwrap();
+        assert_eq!(message.text, "hello");
+        let deliveries = message
+            .deliveries
+            .iter()
+            .map(|d| (d.target, d.state.as_str(), d.result.clone()))
             .collect::<Vec<_>>();
+        assert_eq!(
+            deliveries,
+            vec![
+                (2, "failed", Some(DeliveryStatus::UnknownWorkspace)),
+                (1, "failed", Some(DeliveryStatus::UnknownWorkspace)),
+            ]
+        );
 
-        let mut err_msgs: Vec<String> = vec![];
+        let (status, list) = get_json::<service::ResponseMessageList>(&app, "/messages").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(list.unwrap().messages, vec![message]);
 
-        for ws in ws_vec {
-            tracing::info!("send to webhook");
+        let (status, _) = get_json::<service::ResponseMessage>(&app, "/messages/99").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
 
-            let sender: Arc<Box<dyn Sender>>;
-            match get_sender(ws.ws_type, ws.webhook_url.as_str(), payload.text.as_str()) {
-                Ok(s) => {
-                    sender = Arc::new(s);
-                }
-                Err(e) => {
-                    err_msgs.push(e.to_string());
-                    continue;
-                }
-            }
+    #[tokio::test]
+    async fn reply_message_to_thread_root() {
+        let app = create_app();
+        let post_json = |uri: &str, body: &str| {
+            Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
 
-            match sender.send().await {
-                Ok(_) => {
-                    tracing::info!("success");
-                }
-                Err(e) => {
-                    err_msgs.push(e.to_string());
+        let res = app
+            .clone()
+            .oneshot(post_json(
+                "/message",
+                r#"{"targets": [1, 2], "text": "root"}"#,
+            ))
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let root: MessageResponse = serde_json::from_slice(&body).unwrap();
+
+        let res = app
+            .clone()
+            .oneshot(post_json(
+                &format!("/messages/{}/replies", root.message_id),
+                r#"{"text": "reply"}"#,
+            ))
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let reply: MessageResponse = serde_json::from_slice(&body).unwrap();
+        // 親 message と同じ target に送る
+        let targets = reply.results.iter().map(|r| r.target).collect::<Vec<_>>();
+        assert_eq!(targets, vec![1, 2]);
+
+        // 返信への返信は thread の先頭の message への返信になる
+        let res = app
+            .clone()
+            .oneshot(post_json(
+                &format!("/messages/{}/replies", reply.message_id),
+                r#"{"text": "reply to reply"}"#,
+            ))
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let nested: MessageResponse = serde_json::from_slice(&body).unwrap();
+        let (_, message) =
+            get_json::<service::ResponseMessage>(&app, &format!("/messages/{}", nested.message_id))
+                .await;
+        assert_eq!(message.unwrap().parent_id, Some(root.message_id));
+
+        let res = app
+            .clone()
+            .oneshot(post_json("/messages/99/replies", r#"{"text": "reply"}"#))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn search_messages_by_keyword_and_workspace() {
+        let app = create_app();
+        for (targets, text) in [
+            ("[1]", "今日は Rust を書いた"),
+            ("[2]", "今日は TypeScript を書いた"),
+            ("[1, 2]", "明日も rust を書く"),
+        ] {
+            let req = Request::builder()
+                .method("POST")
+                .uri("/message")
+                .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(Body::from(format!(
+                    r#"{{"targets": {}, "text": "{}"}}"#,
+                    targets, text
+                )))
+                .unwrap();
+            app.clone().oneshot(req).await.unwrap();
+        }
+
+        let texts = |list: Option<service::ResponseMessageList>| {
+            list.unwrap()
+                .messages
+                .into_iter()
+                .map(|m| m.text)
+                .collect::<Vec<_>>()
+        };
+
+        let (status, list) =
+            get_json::<service::ResponseMessageList>(&app, "/messages/search?q=RUST").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            texts(list),
+            vec!["明日も rust を書く", "今日は Rust を書いた"]
+        );
+
+        let (_, list) = get_json::<service::ResponseMessageList>(
+            &app,
+            "/messages/search?q=%E4%BB%8A%E6%97%A5&workspace=2", // 今日
+        )
+        .await;
+        assert_eq!(texts(list), vec!["今日は TypeScript を書いた"]);
+
+        let (status, _) =
+            get_json::<service::ResponseMessageList>(&app, "/messages/search?q=%20").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn schedule_reschedule_and_cancel_message() {
+        let app = create_app();
+        let request = |method: &str, uri: &str, body: String| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let send_at = (Utc::now() + chrono::Duration::hours(1)).to_rfc3339();
+
+        let res = app
+            .clone()
+            .oneshot(request(
+                "POST",
+                "/message",
+                format!(
+                    r#"{{"targets": [1], "text": "good night", "send_at": "{}"}}"#,
+                    send_at
+                ),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let message: service::ResponseMessage = serde_json::from_slice(&body).unwrap();
+        assert_eq!(message.deliveries[0].state, "pending");
+
+        // 送信時刻までは送信履歴ではなく予約の一覧に出る
+        let (_, scheduled) = get_json::<Vec<service::ResponseMessage>>(&app, "/scheduled").await;
+        assert_eq!(scheduled.unwrap(), vec![message.clone()]);
+        let (_, list) = get_json::<service::ResponseMessageList>(&app, "/messages").await;
+        assert!(list.unwrap().messages.is_empty());
+
+        let uri = format!("/scheduled/{}", message.id);
+        let send_at = Utc::now() + chrono::Duration::hours(2);
+        let res = app
+            .clone()
+            .oneshot(request(
+                "PATCH",
+                &uri,
+                format!(
+                    r#"{{"send_at": "{}", "text": "good night!"}}"#,
+                    send_at.to_rfc3339()
+                ),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let updated: service::ResponseMessage = serde_json::from_slice(&body).unwrap();
+        assert_eq!(updated.text, "good night!");
+        assert_eq!(updated.send_at, Some(send_at));
+        assert!(updated.edited_at.is_none());
+
+        // 過去の送信時刻は受け付けない
+        let past = (Utc::now() - chrono::Duration::hours(1)).to_rfc3339();
+        let res = app
+            .clone()
+            .oneshot(request(
+                "PATCH",
+                &uri,
+                format!(r#"{{"send_at": "{}"}}"#, past),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let res = app
+            .clone()
+            .oneshot(request(
+                "POST",
+                "/message",
+                format!(
+                    r#"{{"targets": [1], "text": "hello", "send_at": "{}"}}"#,
+                    past
+                ),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let res = app
+            .clone()
+            .oneshot(request("DELETE", &uri, String::new()))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let res = app
+            .clone()
+            .oneshot(request("DELETE", &uri, String::new()))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let (_, scheduled) = get_json::<Vec<service::ResponseMessage>>(&app, "/scheduled").await;
+        assert!(scheduled.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn send_message_with_multipart_attachments() {
+        let app = create_app();
+        let boundary = "times-hub-boundary";
+        let multipart = |parts: &[(&str, Option<&str>, &str)]| {
+            let mut body = String::new();
+            for (name, filename, content) in parts {
+                body.push_str(&format!("--{}\r\n", boundary));
+                match filename {
+                    Some(filename) => body.push_str(&format!(
+                        "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: text/plain\r\n\r\n",
+                        name, filename
+                    )),
+                    None => body.push_str(&format!(
+                        "Content-Disposition: form-data; name=\"{}\"\r\n\r\n",
+                        name
+                    )),
                 }
+                body.push_str(content);
+                body.push_str("\r\n");
             }
-        }
+            body.push_str(&format!("--{}--\r\n", boundary));
+            Request::builder()
+                .method("POST")
+                .uri("/message")
+                .header(
+                    CONTENT_TYPE,
+                    format!("multipart/form-data; boundary={}", boundary),
+                )
+                .body(Body::from(body))
+                .unwrap()
+        };
 
-        // TODO: エラーの詳細を返す
-        if err_msgs.len() > 0 {
-            tracing::error!("error: {:?}", err_msgs);
-            return StatusCode::INTERNAL_SERVER_ERROR;
-        }
+        let req = multipart(&[
+            ("payload", None, r#"{"targets": [1], "text": "hello"}"#),
+            ("files", Some("memo.txt"), "memo"),
+        ]);
+        let res = app.clone().oneshot(req).await.unwrap();
+        // 存在しない workspace 宛なので送信には失敗するが, 受け付けられる
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+
+        // payload がない場合
+        let req = multipart(&[("files", Some("memo.txt"), "memo")]);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let (status, _) = get_json::<serde_json::Value>(&app, "/attachments/unknown").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn attachments_are_served_inline_only_for_raster_images() {
+        let outbox = MessageRepositoryForMemory::new();
+        let app = Router::new()
+            .route(
+                "/attachments/:key",
+                get(find_attachment::<MessageRepositoryForMemory>),
+            )
+            .layer(Extension(Arc::new(outbox.clone())));
+        let attachment = |key: &str, content_type: &str, data: &[u8]| entity::Attachment {
+            key: key.to_string(),
+            filename: key.to_string(),
+            content_type: content_type.to_string(),
+            data: bytes::Bytes::copy_from_slice(data),
+        };
+        let message = NewMessage {
+            attachments: vec![
+                attachment("png", "application/octet-stream", b"\x89PNG\r\n\x1a\n...."),
+                attachment(
+                    "svg",
+                    "image/svg+xml",
+                    br#"<svg xmlns="http://www.w3.org/2000/svg"><script>alert(1)</script></svg>"#,
+                ),
+            ],
+            ..NewMessage::new("hello")
+        };
+        let (message_id, _) = outbox
+            .enqueue(&message, &[], std::time::Duration::from_secs(60))
+            .await
+            .unwrap();
+
+        let get_headers = |key: &str| {
+            let req = Request::builder()
+                .uri(format!("/attachments/{}", key))
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+        let res = get_headers("png").await.unwrap();
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "image/png");
+        assert_eq!(
+            res.headers()[header::CONTENT_DISPOSITION],
+            "inline; filename=\"png\""
+        );
+
+        // client が画像と指定しても, 中身から判定できなければ download させる
+        let res = get_headers("svg").await.unwrap();
+        assert_eq!(
+            res.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"svg\""
+        );
+        assert_eq!(res.headers()[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(res.headers()[header::CONTENT_SECURITY_POLICY], "sandbox");
 
-        StatusCode::OK
+        // 削除した message の添付は公開しない
+        outbox
+            .mark_deleted(&message_id, &serde_json::json!({}))
+            .await
+            .unwrap();
+        let res = get_headers("png").await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
     }
 }
@@ -0,0 +1,744 @@
+use ::once_cell::sync::Lazy;
+use ::pulldown_cmark::{
+    CodeBlockKind, CowStr, Event, HeadingLevel, Options, Parser, Tag, TagEnd, TextMergeStream,
+};
+use ::regex::Regex;
+use ::unicode_segmentation::UnicodeSegmentation;
+
+// 送信先ごとの記法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Markup {
+    // https://api.slack.com/reference/surfaces/formatting
+    Slack,
+    // https://support.discord.com/hc/en-us/articles/210298617
+    Discord,
+    // https://core.telegram.org/bots/api#markdownv2-style
+    TelegramMarkdownV2,
+    // https://core.telegram.org/bots/api#html-style
+    TelegramHtml,
+    // email や Matrix の formatted_body 向け
+    Html,
+    Plain,
+}
+
+/// message の text (CommonMark) を parse したもの. 送信先ごとに `render` で変換する
+#[derive(Debug, Clone)]
+pub struct Document {
+    events: Vec<Event<'static>>,
+}
+
+impl Document {
+    pub fn parse(text: &str) -> Self {
+        // escape された文字の前後で分かれた text は 1 つにまとめる (e.g. `1\. a` は `1` と `. a` になる)
+        let parser = Parser::new_ext(text, Options::ENABLE_STRIKETHROUGH);
+        let events = TextMergeStream::new(parser)
+            .map(|event| match event {
+                // 生の HTML は送信先で解釈されないよう text として扱う
+                Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+                // chat では改行をそのまま改行として表示する
+                Event::SoftBreak => Event::HardBreak,
+                event => event,
+            })
+            .map(Event::into_static)
+            .collect();
+        Self { events }
+    }
+
+    pub fn render(&self, markup: Markup) -> String {
+        match markup {
+            Markup::Html => {
+                let mut html = String::new();
+                pulldown_cmark::html::push_html(&mut html, self.events.iter().cloned());
+                html.trim_end().to_string()
+            }
+            markup => {
+                let mut renderer = Renderer::new(markup);
+                for event in self.events.iter() {
+                    renderer.event(event);
+                }
+                renderer.out.trim_end().to_string()
+            }
+        }
+    }
+}
+
+/// plain text を, CommonMark として parse しても同じ text になるように escape する
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\`*_[]<>#~|".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// MarkdownV2 で特別な意味を持つ文字を escape する
+pub fn escape_telegram_markdown_v2(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "_*[]()~`>#+-=|{}.!\\".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// HTML で特別な意味を持つ文字を escape する
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// link の URL を, `[label](url)` の `(url)` を閉じずに書けるよう percent-encode する
+pub fn escape_link_url(url: &str) -> String {
+    let mut escaped = String::with_capacity(url.len());
+    for c in url.chars() {
+        match c {
+            ' ' => escaped.push_str("%20"),
+            '(' => escaped.push_str("%28"),
+            ')' => escaped.push_str("%29"),
+            '<' => escaped.push_str("%3C"),
+            '>' => escaped.push_str("%3E"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// text を `max_chars` 文字以内の chunk に分ける.
+/// なるべく段落, 行, 文, 空白の順に区切りのよい位置で分け, grapheme の途中では分けない.
+/// code block の途中で分ける場合は, fence を閉じて次の chunk で開き直す.
+pub fn split_text(text: &str, max_chars: usize) -> Vec<String> {
+    let max_chars = max_chars.max(1);
+    let mut chunks = vec![];
+    let mut rest = text.trim().to_string();
+    while rest.chars().count() > max_chars {
+        let cut = best_cut(&rest, window_end(&rest, max_chars));
+        let (chunk, next) = match open_fence(&rest[..cut]) {
+            // code block の前で分けられる場合は, code block ごと次の chunk に送る
+            Some(fence) if fence.start > 0 => {
+                (&rest[..fence.start], rest[fence.start..].to_string())
+            }
+            // code block が上限より長い場合は, 閉じる fence の分を空けて行の区切りで分ける
+            Some(fence) if max_chars > fence.line.chars().count() + fence.marker.len() + 8 => {
+                let end = window_end(&rest, max_chars - fence.marker.len() - 1);
+                let cut = rest[..end]
+                    .rfind('\n')
+                    .filter(|&i| i > fence.line.len())
+                    .unwrap_or(end);
+                let chunk = format!("{}\n{}", rest[..cut].trim_end(), fence.marker);
+                chunks.push(chunk);
+                rest = format!("{}\n{}", fence.line, rest[cut..].trim_start_matches('\n'));
+                continue;
+            }
+            _ => (&rest[..cut], rest[cut..].to_string()),
+        };
+        chunks.push(chunk.trim_end().to_string());
+        rest = next.trim_start().to_string();
+    }
+    chunks.push(rest);
+    chunks
+}
+
+// 先頭から `max_chars` 文字以内に収まる最後の grapheme の境界. 最初の grapheme だけで超える場合はその終わり
+fn window_end(text: &str, max_chars: usize) -> usize {
+    let mut chars = 0;
+    let mut end = 0;
+    for (i, grapheme) in text.grapheme_indices(true) {
+        chars += grapheme.chars().count();
+        if chars > max_chars {
+            break;
+        }
+        end = i + grapheme.len();
+    }
+    match end {
+        0 => text.graphemes(true).next().map_or(text.len(), str::len),
+        end => end,
+    }
+}
+
+// text[..end] の中で区切りのよい位置. 短すぎる chunk にならないよう, 後半の区切りだけを使う
+fn best_cut(text: &str, end: usize) -> usize {
+    let head = &text[..end];
+    let min = (end / 2).max(1);
+    for separator in ["\n\n", "\n"] {
+        if let Some(i) = head.rfind(separator).filter(|&i| i >= min) {
+            return i;
+        }
+    }
+    // 文の終わり. 半角の記号は "3.14" などで分けないよう, 後ろが空白の場合だけにする
+    let sentence = head
+        .char_indices()
+        .map(|(i, c)| (i + c.len_utf8(), c))
+        .rev()
+        .find(|&(i, c)| {
+            i >= min
+                && match c {
+                    '。' | '！' | '？' => true,
+                    '.' | '!' | '?' => text[i..].starts_with(char::is_whitespace),
+                    _ => false,
+                }
+        });
+    if let Some((i, _)) = sentence {
+        return i;
+    }
+    head.rfind(char::is_whitespace)
+        .filter(|&i| i >= min)
+        .unwrap_or(end)
+}
+
+// 閉じられていない code block の fence
+struct OpenFence<'a> {
+    // fence の行の先頭の位置
+    start: usize,
+    // 開く fence の行 (言語の指定を含む)
+    line: &'a str,
+    // 閉じる fence
+    marker: &'a str,
+}
+
+fn open_fence(text: &str) -> Option<OpenFence<'_>> {
+    let mut open: Option<OpenFence> = None;
+    let mut start = 0;
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim();
+        let marker_len = trimmed
+            .find(|c| !matches!(c, '`' | '~'))
+            .unwrap_or(trimmed.len());
+        let marker = &trimmed[..marker_len];
+        let is_fence =
+            marker.len() >= 3 && marker.chars().all(|c| c == marker.as_bytes()[0] as char);
+        match &open {
+            None if is_fence => {
+                open = Some(OpenFence {
+                    start,
+                    line: trimmed,
+                    marker,
+                })
+            }
+            Some(fence) if is_fence && marker.starts_with(fence.marker) && marker == trimmed => {
+                open = None
+            }
+            _ => {}
+        }
+        start += line.len();
+    }
+    open
+}
+
+// Discord で escape すると URL として認識されなくなるので, URL はそのまま残す
+static URL: Lazy<Regex> = Lazy::new(|| Regex::new(r"https?://\S+").unwrap());
+
+// `line_start` は text が行頭から始まる場合に true
+fn escape_discord(text: &str, line_start: bool) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            escaped.push('\n');
+        }
+        let line = match i > 0 || line_start {
+            true => escape_discord_line_start(line, &mut escaped),
+            false => line,
+        };
+        let mut last = 0;
+        for url in URL.find_iter(line) {
+            escape_discord_chars(&line[last..url.start()], &mut escaped);
+            escaped.push_str(url.as_str());
+            last = url.end();
+        }
+        escape_discord_chars(&line[last..], &mut escaped);
+    }
+    escaped
+}
+
+// 行頭の # > - + と 1. 1) は見出し, 引用, list として表示されるので escape する.
+// 残りの部分を返す
+fn escape_discord_line_start<'a>(line: &'a str, escaped: &mut String) -> &'a str {
+    let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let marker = match line[digits..].chars().next() {
+        Some('.' | ')') if digits > 0 => digits,
+        Some('#' | '>' | '-' | '+') if digits == 0 => 0,
+        _ => return line,
+    };
+    escaped.push_str(&line[..marker]);
+    escaped.push('\\');
+    escaped.push_str(&line[marker..marker + 1]);
+    &line[marker + 1..]
+}
+
+fn escape_discord_chars(text: &str, escaped: &mut String) {
+    for c in text.chars() {
+        if "\\*_~`|[]".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+}
+
+// MarkdownV2 の code と link の URL の中では ` と \ (URL では ) と \) だけを escape する
+fn escape_telegram_code(text: &str, special: char) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c == special || c == '\\' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Html 以外の記法への変換. 記法にない要素 (見出しなど) は近いもので代用する
+struct Renderer {
+    markup: Markup,
+    out: String,
+    // 何も書いていない行の先頭にいる場合に true
+    fresh: bool,
+    quote_depth: usize,
+    // 入れ子の list ごとの次の番号 (番号なしの list は None)
+    lists: Vec<Option<u64>>,
+    // link の text を書き始めた位置
+    links: Vec<(CowStr<'static>, usize)>,
+    // 書いている途中の code block の (言語, 中身)
+    code: Option<(String, String)>,
+}
+
+impl Renderer {
+    fn new(markup: Markup) -> Self {
+        Self {
+            markup,
+            out: String::new(),
+            fresh: true,
+            quote_depth: 0,
+            lists: vec![],
+            links: vec![],
+            code: None,
+        }
+    }
+
+    fn escape(&self, text: &str) -> String {
+        match self.markup {
+            Markup::Slack | Markup::TelegramHtml | Markup::Html => escape_html(text),
+            Markup::Discord => escape_discord(text, self.fresh),
+            Markup::TelegramMarkdownV2 => escape_telegram_markdown_v2(text),
+            Markup::Plain => text.to_string(),
+        }
+    }
+
+    fn push(&mut self, s: &str) {
+        self.out.push_str(s);
+        self.fresh = false;
+    }
+
+    fn quote_prefix(&self) -> &'static str {
+        match self.markup {
+            _ if self.quote_depth == 0 => "",
+            Markup::TelegramMarkdownV2 => ">",
+            Markup::TelegramHtml | Markup::Html => "",
+            _ => "> ",
+        }
+    }
+
+    fn newline(&mut self) {
+        self.out.push('\n');
+        self.out.push_str(self.quote_prefix());
+        self.fresh = true;
+    }
+
+    // 複数行の text を, 引用の中でも各行に prefix が付くように書く
+    fn push_lines(&mut self, text: &str) {
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.newline();
+            }
+            self.push(line);
+        }
+    }
+
+    // block の前で改行する. list の外では空行を空ける
+    fn separate(&mut self) {
+        if self.out.is_empty() || self.fresh {
+            return;
+        }
+        self.newline();
+        if self.lists.is_empty() {
+            self.newline();
+        }
+    }
+
+    // 記法ごとの (開始, 終了) の記号
+    fn delimiters(&self, tag: &Tag) -> (&'static str, &'static str) {
+        match (self.markup, tag) {
+            (Markup::Plain, _) => ("", ""),
+            (Markup::Slack, Tag::Emphasis) => ("_", "_"),
+            (Markup::Slack, Tag::Strong) => ("*", "*"),
+            (Markup::Slack, Tag::Strikethrough) => ("~", "~"),
+            (Markup::Discord, Tag::Emphasis) => ("*", "*"),
+            (Markup::Discord, Tag::Strong) => ("**", "**"),
+            (Markup::Discord, Tag::Strikethrough) => ("~~", "~~"),
+            (Markup::TelegramMarkdownV2, Tag::Emphasis) => ("_", "_"),
+            (Markup::TelegramMarkdownV2, Tag::Strong) => ("*", "*"),
+            (Markup::TelegramMarkdownV2, Tag::Strikethrough) => ("~", "~"),
+            (_, Tag::Emphasis) => ("<i>", "</i>"),
+            (_, Tag::Strong) => ("<b>", "</b>"),
+            (_, Tag::Strikethrough) => ("<s>", "</s>"),
+            _ => ("", ""),
+        }
+    }
+
+    fn heading(&self, level: HeadingLevel) -> (&'static str, &'static str) {
+        match (self.markup, level) {
+            (Markup::Discord, HeadingLevel::H1) => ("# ", ""),
+            (Markup::Discord, HeadingLevel::H2) => ("## ", ""),
+            (Markup::Discord, HeadingLevel::H3) => ("### ", ""),
+            // 見出しがない記法では太字にする
+            _ => self.delimiters(&Tag::Strong),
+        }
+    }
+
+    fn event(&mut self, event: &Event<'static>) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => {
+                // code block の中身は閉じる時にまとめて書く
+                if let Some((_, code)) = &mut self.code {
+                    code.push_str(text);
+                    return;
+                }
+                let text = self.escape(text);
+                self.push_lines(&text);
+            }
+            Event::Code(code) => {
+                let code = match self.markup {
+                    Markup::Slack => format!("`{}`", escape_html(code)),
+                    Markup::Discord if code.contains('`') => format!("`` {} ``", code),
+                    Markup::Discord => format!("`{}`", code),
+                    Markup::TelegramMarkdownV2 => format!("`{}`", escape_telegram_code(code, '`')),
+                    Markup::TelegramHtml | Markup::Html => {
+                        format!("<code>{}</code>", escape_html(code))
+                    }
+                    Markup::Plain => code.to_string(),
+                };
+                self.push(&code);
+            }
+            Event::SoftBreak | Event::HardBreak => self.newline(),
+            Event::Rule => {
+                self.separate();
+                let rule = self.escape("---");
+                self.push(&rule);
+            }
+            _ => {}
+        }
+    }
+
+    fn start(&mut self, tag: &Tag<'static>) {
+        match tag {
+            Tag::Paragraph | Tag::HtmlBlock => self.separate(),
+            Tag::Heading { level, .. } => {
+                self.separate();
+                let (open, _) = self.heading(*level);
+                self.push(open);
+            }
+            Tag::BlockQuote(_) => {
+                self.separate();
+                self.quote_depth += 1;
+                match self.markup {
+                    Markup::TelegramHtml => {
+                        self.push("<blockquote>");
+                        self.fresh = true;
+                    }
+                    // 引用の入れ子に対応していない記法が多いので, 最初の段だけ prefix を付ける
+                    _ if self.quote_depth == 1 => {
+                        let prefix = self.quote_prefix();
+                        self.out.push_str(prefix);
+                    }
+                    _ => {}
+                }
+            }
+            Tag::CodeBlock(kind) => {
+                self.separate();
+                let lang = match kind {
+                    CodeBlockKind::Fenced(lang) => lang.split_whitespace().next().unwrap_or(""),
+                    CodeBlockKind::Indented => "",
+                };
+                self.code = Some((lang.to_string(), String::new()));
+            }
+            Tag::List(start) => {
+                self.separate();
+                self.lists.push(*start);
+            }
+            Tag::Item => {
+                self.separate();
+                let indent = "  ".repeat(self.lists.len().saturating_sub(1));
+                let marker = match self.lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        match self.markup {
+                            Markup::TelegramMarkdownV2 => format!("{}\\. ", *n - 1),
+                            _ => format!("{}. ", *n - 1),
+                        }
+                    }
+                    _ => match self.markup {
+                        Markup::Discord | Markup::Plain => "- ".to_string(),
+                        _ => "• ".to_string(),
+                    },
+                };
+                self.push(&format!("{}{}", indent, marker));
+                self.fresh = true;
+            }
+            Tag::Emphasis | Tag::Strong | Tag::Strikethrough => {
+                let (open, _) = self.delimiters(tag);
+                self.push(open);
+            }
+            Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. } => {
+                self.links.push((dest_url.clone(), self.out.len()));
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: &TagEnd) {
+        match tag {
+            TagEnd::Heading(level) => {
+                let (_, close) = self.heading(*level);
+                self.push(close);
+            }
+            TagEnd::BlockQuote(_) => {
+                if self.markup == Markup::TelegramHtml {
+                    self.push("</blockquote>");
+                }
+                self.quote_depth -= 1;
+            }
+            TagEnd::CodeBlock => {
+                let Some((lang, code)) = self.code.take() else {
+                    return;
+                };
+                let code = code.strip_suffix('\n').unwrap_or(&code);
+                let (open, close) = match self.markup {
+                    // Slack は言語の指定に対応していない
+                    Markup::Slack => ("```".to_string(), "```"),
+                    Markup::Discord | Markup::TelegramMarkdownV2 => (format!("```{}", lang), "```"),
+                    Markup::TelegramHtml | Markup::Html if lang.is_empty() => {
+                        ("<pre>".to_string(), "</pre>")
+                    }
+                    Markup::TelegramHtml | Markup::Html => (
+                        format!("<pre><code class=\"language-{}\">", escape_html(&lang)),
+                        "</code></pre>",
+                    ),
+                    Markup::Plain => (String::new(), ""),
+                };
+                let code = match self.markup {
+                    Markup::Slack | Markup::TelegramHtml | Markup::Html => escape_html(code),
+                    Markup::TelegramMarkdownV2 => escape_telegram_code(code, '`'),
+                    Markup::Discord | Markup::Plain => code.to_string(),
+                };
+                // fence は前後の行と分ける. HTML の pre はそのまま囲む
+                let fenced = close == "```";
+                self.push(&open);
+                if fenced {
+                    self.newline();
+                }
+                self.push_lines(&code);
+                if fenced {
+                    self.newline();
+                }
+                self.push(close);
+            }
+            TagEnd::List(_) => {
+                self.lists.pop();
+            }
+            TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough => {
+                let tag = match tag {
+                    TagEnd::Emphasis => Tag::Emphasis,
+                    TagEnd::Strong => Tag::Strong,
+                    _ => Tag::Strikethrough,
+                };
+                let (_, close) = self.delimiters(&tag);
+                self.push(close);
+            }
+            TagEnd::Link | TagEnd::Image => {
+                let Some((url, start)) = self.links.pop() else {
+                    return;
+                };
+                let label = self.out.split_off(start);
+                let link = self.link(&url, &label);
+                self.push(&link);
+            }
+            _ => {}
+        }
+    }
+
+    // label は escape 済み
+    fn link(&self, url: &str, label: &str) -> String {
+        let bare = label.is_empty() || label == self.escape(url);
+        match self.markup {
+            Markup::Slack if bare => format!("<{}>", escape_html(url)),
+            Markup::Slack => format!("<{}|{}>", escape_html(url), label),
+            Markup::Discord | Markup::Plain if bare => url.to_string(),
+            Markup::Discord => format!("[{}]({})", label, escape_link_url(url)),
+            Markup::TelegramMarkdownV2 if bare => escape_telegram_markdown_v2(url),
+            Markup::TelegramMarkdownV2 => {
+                format!("[{}]({})", label, escape_telegram_code(url, ')'))
+            }
+            Markup::TelegramHtml | Markup::Html => {
+                let label = if label.is_empty() {
+                    escape_html(url)
+                } else {
+                    label.to_string()
+                };
+                format!(
+                    "<a href=\"{}\">{}</a>",
+                    escape_html(url).replace('"', "&quot;"),
+                    label
+                )
+            }
+            Markup::Plain => format!("{} ({})", label, url),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn render(text: &str, markup: Markup) -> String {
+        Document::parse(text).render(markup)
+    }
+
+    const TEXT: &str = "# Daily\n\n**bold** *italic* ~~gone~~ `a<b` [docs](https://example.com/a_b) 1+1 & <x>\n\n> quote\n> line\n\n- one\n- two\n\n```rust\nlet x = 1 < 2;\n```";
+
+    #[test]
+    fn render_slack() {
+        assert_eq!(
+            render(TEXT, Markup::Slack),
+            "*Daily*\n\n*bold* _italic_ ~gone~ `a&lt;b` <https://example.com/a_b|docs> 1+1 &amp; &lt;x&gt;\n\n> quote\n> line\n\n• one\n• two\n\n```\nlet x = 1 &lt; 2;\n```"
+        );
+        assert_eq!(
+            render("<https://example.com>", Markup::Slack),
+            "<https://example.com>"
+        );
+    }
+
+    #[test]
+    fn render_discord() {
+        assert_eq!(
+            render(TEXT, Markup::Discord),
+            "# Daily\n\n**bold** *italic* ~~gone~~ `a<b` [docs](https://example.com/a_b) 1+1 & <x>\n\n> quote\n> line\n\n- one\n- two\n\n```rust\nlet x = 1 < 2;\n```"
+        );
+        assert_eq!(
+            render(r"snake\_case see https://example.com/a_b", Markup::Discord),
+            r"snake\_case see https://example.com/a_b"
+        );
+        // 行頭で見出し, 引用, list にならないように escape する
+        assert_eq!(
+            render(
+                "\\# not heading\n\\> not quote\n\\- not list\n1\\. not list\n- \\# item\nnot #1 - x > y",
+                Markup::Discord
+            ),
+            "\\# not heading\n\\> not quote\n\\- not list\n1\\. not list\n\n- \\# item\nnot #1 - x > y"
+        );
+    }
+
+    #[test]
+    fn render_telegram_markdown_v2() {
+        assert_eq!(
+            render(TEXT, Markup::TelegramMarkdownV2),
+            "*Daily*\n\n*bold* _italic_ ~gone~ `a<b` [docs](https://example.com/a_b) 1\\+1 & <x\\>\n\n>quote\n>line\n\n• one\n• two\n\n```rust\nlet x = 1 < 2;\n```"
+        );
+        assert_eq!(
+            render("1. (done).", Markup::TelegramMarkdownV2),
+            r"1\. \(done\)\."
+        );
+    }
+
+    #[test]
+    fn render_telegram_html() {
+        assert_eq!(
+            render(TEXT, Markup::TelegramHtml),
+            "<b>Daily</b>\n\n<b>bold</b> <i>italic</i> <s>gone</s> <code>a&lt;b</code> <a href=\"https://example.com/a_b\">docs</a> 1+1 &amp; &lt;x&gt;\n\n<blockquote>quote\nline</blockquote>\n\n• one\n• two\n\n<pre><code class=\"language-rust\">let x = 1 &lt; 2;</code></pre>"
+        );
+    }
+
+    #[test]
+    fn render_html() {
+        assert_eq!(
+            render("**a** & <script>\nb", Markup::Html),
+            "<p><strong>a</strong> &amp; &lt;script&gt;<br />\nb</p>"
+        );
+    }
+
+    #[test]
+    fn split_text_keeps_short_text() {
+        assert_eq!(split_text("  hello\n", 10), vec!["hello"]);
+    }
+
+    #[test]
+    fn split_text_prefers_paragraph_line_and_sentence() {
+        assert_eq!(
+            split_text("first paragraph\n\nsecond one", 20),
+            vec!["first paragraph", "second one"]
+        );
+        assert_eq!(
+            split_text("line one\nline two\nline three", 20),
+            vec!["line one\nline two", "line three"]
+        );
+        assert_eq!(
+            split_text("Pi is 3.14 or so. That is all.", 20),
+            vec!["Pi is 3.14 or so.", "That is all."]
+        );
+        assert_eq!(
+            split_text("今日は晴れ。明日は雨。明後日は雪。", 8),
+            vec!["今日は晴れ。", "明日は雨。", "明後日は雪。"]
+        );
+    }
+
+    #[test]
+    fn split_text_keeps_graphemes() {
+        // 👨‍👩‍👧 は 5 文字で 1 grapheme
+        let family = "👨\u{200d}👩\u{200d}👧";
+        let chunks = split_text(&family.repeat(3), 12);
+        assert_eq!(chunks, vec![family.repeat(2), family.to_string()]);
+        assert!(split_text(&"あ".repeat(25), 10)
+            .iter()
+            .all(|c| c.chars().count() <= 10));
+    }
+
+    #[test]
+    fn split_text_on_code_fences() {
+        let text = "intro\n\n```rust\nlet a = 1;\n```\n\nafter";
+        assert_eq!(
+            split_text(text, 25),
+            vec!["intro", "```rust\nlet a = 1;\n```", "after"]
+        );
+
+        let code = (1..=6)
+            .map(|i| format!("let x{} = {};", i, i))
+            .collect::<Vec<_>>();
+        let text = format!("```rust\n{}\n```", code.join("\n"));
+        let chunks = split_text(&text, 50);
+        assert_eq!(
+            chunks,
+            vec![
+                format!("```rust\n{}\n```", code[..3].join("\n")),
+                format!("```rust\n{}\n```", code[3..].join("\n")),
+            ]
+        );
+        assert!(chunks.iter().all(|c| c.chars().count() <= 50));
+    }
+
+    #[test]
+    fn render_plain() {
+        assert_eq!(
+            render(TEXT, Markup::Plain),
+            "Daily\n\nbold italic gone a<b docs (https://example.com/a_b) 1+1 & <x>\n\n> quote\n> line\n\n- one\n- two\n\nlet x = 1 < 2;"
+        );
+        assert_eq!(
+            render("1. a\n2. b\n   - c", Markup::Plain),
+            "1. a\n2. b\n  - c"
+        );
+    }
+}
@@ -1,2 +1,6 @@
 pub(crate) mod handler;
+pub(crate) mod markup;
+pub(crate) mod outbox;
+pub(crate) mod repository;
+pub(crate) mod retry;
 pub(crate) mod service;
@@ -0,0 +1,191 @@
+use crate::entity;
+use crate::message::repository::MessageRepository;
+use crate::message::retry::RetryPolicy;
+use crate::message::service::{
+    apply_to_workspace, save_remote, send_to_workspace, DeliveryConfig, DeliveryStatus,
+    RemoteMessages, RemoteOperation, RemoteOperationStatus,
+};
+use crate::workspace::repository::{RepositoryError, WorkspaceRepository};
+
+use ::anyhow::Result;
+use ::futures::stream;
+use ::futures::StreamExt;
+use ::std::sync::Arc;
+use ::std::time::Duration;
+use ::tokio::task::JoinHandle;
+
+// outbox から再送する worker の設定
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboxConfig {
+    pub poll_interval: Duration,
+    pub batch_size: i64,
+    // max_retries は delivery ごとの最大再送回数
+    pub retry: RetryPolicy,
+}
+
+impl Default for OutboxConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(5),
+            batch_size: 32,
+            retry: RetryPolicy {
+                max_retries: 10,
+                base_backoff: Duration::from_secs(30),
+                max_backoff: Duration::from_secs(60 * 60),
+            },
+        }
+    }
+}
+
+/// 送信中の delivery を他の worker が取り出さないようにする期間
+pub fn lease(config: &DeliveryConfig) -> Duration {
+    config.timeout * 2
+}
+
+/// 送信結果と送信先の message の参照を outbox に記録し, 再送する場合は true を返す.
+/// 失敗した場合も, 途中まで投稿できた部分の参照を再送のために記録する
+pub async fn record<T, M>(
+    repo: &T,
+    outbox: &M,
+    config: &DeliveryConfig,
+    delivery: &entity::Delivery,
+    status: &DeliveryStatus,
+    remote: &RemoteMessages,
+) -> bool
+where
+    T: WorkspaceRepository,
+    M: MessageRepository,
+{
+    let retries = delivery.attempts.max(1) as u32 - 1;
+    let result = serde_json::to_value(status).unwrap_or_default();
+    let value = match remote.is_empty() {
+        true => None,
+        false => serde_json::to_value(remote).ok(),
+    };
+    let (res, will_retry) = if status.is_sent() {
+        let res = match outbox
+            .mark_sent(&delivery.id, &result, value.as_ref())
+            .await
+        {
+            Ok(recorded) if remote.is_complete() => {
+                reconcile(repo, outbox, config, delivery, remote, recorded).await
+            }
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        };
+        (res, false)
+    } else {
+        let retry_after = if status.is_retriable() && retries < config.outbox.retry.max_retries {
+            Some(config.outbox.retry.backoff(retries))
+        } else {
+            None
+        };
+        (
+            outbox
+                .mark_failed(&delivery.id, &result, retry_after, value.as_ref())
+                .await,
+            retry_after.is_some(),
+        )
+    };
+    if let Err(e) = res {
+        tracing::error!("failed to record delivery {}: {}", delivery.id, e);
+    }
+    will_retry
+}
+
+/// 送信中に message が削除・編集されていた場合に, 送信先の message にも反映する.
+/// `recorded` は送信結果を記録できたか (削除で取り消されていなかったか)
+async fn reconcile<T, M>(
+    repo: &T,
+    outbox: &M,
+    config: &DeliveryConfig,
+    delivery: &entity::Delivery,
+    remote: &RemoteMessages,
+    recorded: bool,
+) -> Result<()>
+where
+    T: WorkspaceRepository,
+    M: MessageRepository,
+{
+    let message = outbox.find(delivery.message_id.clone()).await?;
+    let operation = match (recorded, message.deleted_at.is_some()) {
+        (false, true) => RemoteOperation::Delete,
+        (true, false) if message.text != delivery.text => RemoteOperation::Edit,
+        // 記録できた後の削除・編集は, 削除・編集した側が送信先に反映する
+        _ => return Ok(()),
+    };
+    tracing::info!(
+        "{:?} message of delivery {} changed while sending",
+        operation,
+        delivery.id
+    );
+    let content = (message.text.as_str(), message.layout.as_ref());
+    let ws_id = &delivery.workspace_id;
+    let mut remote = remote.clone();
+    let status = apply_to_workspace(repo, config, ws_id, content, &mut remote, operation).await;
+    save_remote(outbox, &delivery.message_id, ws_id, &remote).await;
+    if let RemoteOperationStatus::Failed { result } = status {
+        tracing::warn!(
+            "failed to {:?} delivery {}: {:?}",
+            operation,
+            delivery.id,
+            result
+        );
+    }
+    Ok(())
+}
+
+/// outbox の pending な delivery を再送し続ける worker を起動する
+pub fn spawn_worker<T, M>(repo: Arc<T>, outbox: Arc<M>, config: DeliveryConfig) -> JoinHandle<()>
+where
+    T: WorkspaceRepository,
+    M: MessageRepository,
+{
+    tokio::spawn(async move {
+        loop {
+            match drain(repo.as_ref(), outbox.as_ref(), &config).await {
+                // 取り出した件数が batch_size に満たなければしばらく待つ
+                Ok(n) if n as i64 >= config.outbox.batch_size => continue,
+                Ok(_) => {}
+                Err(e) => tracing::error!("failed to drain outbox: {}", e),
+            }
+            tokio::time::sleep(config.outbox.poll_interval).await;
+        }
+    })
+}
+
+/// 再送時刻を過ぎた delivery を 1 batch 分送信し, 取り出した件数を返す
+pub async fn drain<T, M>(repo: &T, outbox: &M, config: &DeliveryConfig) -> Result<usize>
+where
+    T: WorkspaceRepository,
+    M: MessageRepository,
+{
+    let deliveries = outbox
+        .claim(config.outbox.batch_size, lease(config))
+        .await?;
+    let n = deliveries.len();
+    if n > 0 {
+        tracing::info!("redeliver {} messages from outbox", n);
+    }
+
+    stream::iter(deliveries)
+        .for_each_concurrent(config.concurrency.max(1), |delivery| async move {
+            let (status, remote) = match repo.find(delivery.workspace_id.clone()).await {
+                Ok(ws) => send_to_workspace(ws, &delivery, config).await,
+                Err(e) => {
+                    let status = match e.downcast_ref::<RepositoryError>() {
+                        Some(RepositoryError::NotFound(_)) => DeliveryStatus::UnknownWorkspace,
+                        _ => DeliveryStatus::TransportError {
+                            error: e.to_string(),
+                        },
+                    };
+                    // 取得できなかった場合も, 投稿済みの部分の参照は残す
+                    (status, RemoteMessages::from_value(delivery.remote.clone()))
+                }
+            };
+            record(repo, outbox, config, &delivery, &status, &remote).await;
+        })
+        .await;
+
+    Ok(n)
+}
@@ -0,0 +1,1502 @@
+use crate::entity;
+
+use ::anyhow::Result;
+use ::axum::async_trait;
+use ::chrono::{DateTime, Utc};
+use ::sqlx::postgres::PgPool;
+use ::sqlx::FromRow;
+use ::std::collections::HashMap;
+use ::std::str::FromStr;
+use ::std::time::Duration;
+use ::thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum MessageRepositoryError {
+    #[error("Unexpected Error: {0}")]
+    Unexpected(String),
+    #[error("Message NotFound! ID is {0}")]
+    NotFound(entity::MessageId),
+    #[error("Attachment NotFound! key is {0}")]
+    AttachmentNotFound(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+pub struct MessageDBRow {
+    pub id: entity::MessageIdTypeAlias,
+    pub text: String,
+    pub created_at: DateTime<Utc>,
+    pub parent_id: Option<entity::MessageIdTypeAlias>,
+    pub edited_at: Option<DateTime<Utc>>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub layout: Option<serde_json::Value>,
+    pub send_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+pub struct DeliveryDBRow {
+    pub id: entity::DeliveryIdTypeAlias,
+    pub message_id: entity::MessageIdTypeAlias,
+    pub workspace_id: entity::WorkspaceIdTypeAlias,
+    pub text: String,
+    pub attempts: i32,
+    // 返信の場合の親 message の text と, 同じ workspace に送った親 message の参照
+    pub parent_text: Option<String>,
+    pub parent_remote: Option<serde_json::Value>,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub layout: Option<serde_json::Value>,
+    pub remote: Option<serde_json::Value>,
+}
+
+// messages.layout の JSON を entity::Layout にする.
+// 読めない layout で text まで送れなくならないよう, 警告を残して layout なしとして扱う
+fn layout(
+    message_id: entity::MessageIdTypeAlias,
+    value: Option<serde_json::Value>,
+) -> Option<entity::Layout> {
+    match value.map(serde_json::from_value).transpose() {
+        Ok(layout) => layout,
+        Err(e) => {
+            tracing::warn!("invalid layout of message {}: {}", message_id, e);
+            None
+        }
+    }
+}
+
+impl From<DeliveryDBRow> for entity::Delivery {
+    fn from(row: DeliveryDBRow) -> Self {
+        Self {
+            id: entity::DeliveryId::new(row.id),
+            message_id: entity::MessageId::new(row.message_id),
+            workspace_id: entity::WorkspaceId::new(row.workspace_id),
+            text: row.text,
+            attempts: row.attempts,
+            parent: row.parent_text.map(|text| entity::DeliveryParent {
+                text,
+                remote: row.parent_remote,
+            }),
+            profile: entity::Profile {
+                display_name: row.display_name,
+                avatar_url: row.avatar_url,
+            },
+            // attachments は別の query で取得する
+            attachments: vec![],
+            layout: layout(row.message_id, row.layout),
+            remote: row.remote,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+pub struct AttachmentDBRow {
+    pub message_id: entity::MessageIdTypeAlias,
+    pub key: String,
+    pub filename: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+impl From<AttachmentDBRow> for entity::Attachment {
+    fn from(row: AttachmentDBRow) -> Self {
+        Self {
+            key: row.key,
+            filename: row.filename,
+            content_type: row.content_type,
+            data: row.data.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+pub struct DeliveryRecordDBRow {
+    pub message_id: entity::MessageIdTypeAlias,
+    pub workspace_id: entity::WorkspaceIdTypeAlias,
+    pub status: String,
+    pub attempts: i32,
+    pub result: Option<serde_json::Value>,
+    pub remote: Option<serde_json::Value>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl TryFrom<DeliveryRecordDBRow> for entity::DeliveryRecord {
+    type Error = anyhow::Error;
+
+    fn try_from(row: DeliveryRecordDBRow) -> Result<Self> {
+        Ok(Self {
+            workspace_id: entity::WorkspaceId::new(row.workspace_id),
+            state: entity::DeliveryState::from_str(row.status.as_str())?,
+            attempts: row.attempts,
+            result: row.result,
+            remote: row.remote,
+            updated_at: row.updated_at,
+        })
+    }
+}
+
+// outbox に登録する message
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct NewMessage {
+    pub text: String,
+    // thread の返信先
+    pub parent_id: Option<entity::MessageId>,
+    pub profile: entity::Profile,
+    pub attachments: Vec<entity::Attachment>,
+    pub layout: Option<entity::Layout>,
+    // 予約送信の送信時刻
+    pub send_at: Option<DateTime<Utc>>,
+}
+
+impl NewMessage {
+    pub fn new(text: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+// 送信履歴の検索条件
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SearchCondition {
+    pub keyword: String,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub workspace_id: Option<entity::WorkspaceId>,
+}
+
+// 送信待ちの message を永続化する outbox 兼送信履歴
+#[async_trait]
+pub trait MessageRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
+    /// message と target ごとの delivery を登録する.
+    /// 登録した delivery は呼び出し元が送信するため, `lease` の間は `claim` で取り出されない.
+    /// `send_at` がある場合は, `send_at` まで取り出されない.
+    async fn enqueue(
+        &self,
+        message: &NewMessage,
+        targets: &[entity::WorkspaceId],
+        lease: Duration,
+    ) -> Result<(entity::MessageId, Vec<entity::Delivery>)>;
+
+    /// 送信時刻を過ぎた pending の delivery を最大 `limit` 件取り出し, `lease` の間は再度取り出されないようにする
+    async fn claim(&self, limit: i64, lease: Duration) -> Result<Vec<entity::Delivery>>;
+
+    /// pending の delivery を sent にする. `remote` は送信先が返した message の参照.
+    /// 送信中に message が削除されるなどして pending でなくなっていた場合は何もせず false を返す
+    async fn mark_sent(
+        &self,
+        id: &entity::DeliveryId,
+        result: &serde_json::Value,
+        remote: Option<&serde_json::Value>,
+    ) -> Result<bool>;
+
+    /// pending の delivery だけを更新する. `retry_after` が None の場合は再送せずに failed とする.
+    /// `remote` は失敗するまでに送信先に投稿できた部分の参照
+    async fn mark_failed(
+        &self,
+        id: &entity::DeliveryId,
+        result: &serde_json::Value,
+        retry_after: Option<Duration>,
+        remote: Option<&serde_json::Value>,
+    ) -> Result<()>;
+
+    /// 送信済みの delivery の送信先の message の参照を置き換える
+    async fn update_remote(
+        &self,
+        message_id: &entity::MessageId,
+        workspace_id: &entity::WorkspaceId,
+        remote: Option<&serde_json::Value>,
+    ) -> Result<()>;
+
+    /// 新しい順に `before` より前の message を最大 `limit` 件返す
+    async fn all(
+        &self,
+        limit: i64,
+        before: Option<entity::MessageId>,
+    ) -> Result<Vec<entity::Message>>;
+
+    async fn find(&self, id: entity::MessageId) -> Result<entity::Message>;
+
+    /// 削除済みの message の添付は見つからないものとする
+    async fn find_attachment(&self, key: &str) -> Result<entity::Attachment>;
+
+    /// message の text を置き換える. 再送待ちの delivery は置き換えた text で送られる
+    async fn update_text(&self, id: &entity::MessageId, text: &str) -> Result<()>;
+
+    /// message を削除済みにし, 再送待ちの delivery は `result` を付けて failed とする
+    async fn mark_deleted(&self, id: &entity::MessageId, result: &serde_json::Value) -> Result<()>;
+
+    /// `condition` に一致する message を `all` と同じ順序で返す
+    async fn search(
+        &self,
+        condition: &SearchCondition,
+        limit: i64,
+        before: Option<entity::MessageId>,
+    ) -> Result<Vec<entity::Message>>;
+
+    /// 送信時刻を過ぎていない予約送信の message を送信時刻の順に返す
+    async fn scheduled(&self) -> Result<Vec<entity::Message>>;
+
+    /// 予約送信の message の送信時刻と text をまとめて変える. None の項目は変えない.
+    /// まだ送信していないので edited_at は付けない. 送信時刻を過ぎていれば `MessageNotFound` とする
+    async fn update_scheduled(
+        &self,
+        id: &entity::MessageId,
+        send_at: Option<DateTime<Utc>>,
+        text: Option<&str>,
+    ) -> Result<()>;
+
+    /// 予約送信の message を送信せずに削除する. 送信時刻を過ぎていれば `MessageNotFound` とする
+    async fn cancel_scheduled(&self, id: &entity::MessageId) -> Result<()>;
+}
+
+pub mod pg {
+    use super::*;
+    use axum::async_trait;
+
+    #[derive(Debug, Clone)]
+    pub struct MessageRepositoryForDB {
+        pool: PgPool,
+    }
+
+    impl MessageRepositoryForDB {
+        pub fn new(pool: PgPool) -> Self {
+            Self { pool }
+        }
+    }
+
+    #[async_trait]
+    impl MessageRepository for MessageRepositoryForDB {
+        async fn enqueue(
+            &self,
+            message: &NewMessage,
+            targets: &[entity::WorkspaceId],
+            lease: Duration,
+        ) -> Result<(entity::MessageId, Vec<entity::Delivery>)> {
+            let mut tx = self.pool.begin().await?;
+
+            let (message_id,): (entity::MessageIdTypeAlias,) = sqlx::query_as(
+                r#"
+INSERT INTO messages (text, parent_id, display_name, avatar_url, layout, send_at)
+VALUES ($1, $2, $3, $4, $5, $6)
+RETURNING id
+            "#,
+            )
+            .bind(message.text.as_str())
+            .bind(message.parent_id.as_ref().map(|id| id.to_raw()))
+            .bind(message.profile.display_name.as_deref())
+            .bind(message.profile.avatar_url.as_deref())
+            .bind(
+                message
+                    .layout
+                    .as_ref()
+                    .map(serde_json::to_value)
+                    .transpose()?,
+            )
+            .bind(message.send_at)
+            .fetch_one(&mut tx)
+            .await?;
+
+            for attachment in &message.attachments {
+                sqlx::query(
+                    r#"
+INSERT INTO attachments (message_id, key, filename, content_type, data)
+VALUES ($1, $2, $3, $4, $5)
+                "#,
+                )
+                .bind(message_id)
+                .bind(attachment.key.as_str())
+                .bind(attachment.filename.as_str())
+                .bind(attachment.content_type.as_str())
+                .bind(attachment.data.as_ref())
+                .execute(&mut tx)
+                .await?;
+            }
+
+            let rows = sqlx::query_as::<_, DeliveryDBRow>(
+                r#"
+WITH d AS (
+    INSERT INTO deliveries (message_id, workspace_id, status, attempts, next_attempt_at)
+    SELECT $1, workspace_id, 'pending',
+        CASE WHEN $4::TIMESTAMPTZ IS NULL THEN 1 ELSE 0 END,
+        COALESCE($4, now() + make_interval(secs => $3))
+    FROM unnest($2::INTEGER[]) AS t (workspace_id)
+    RETURNING id, message_id, workspace_id, attempts, remote
+)
+SELECT d.id, d.message_id, d.workspace_id, m.text, d.attempts,
+    p.text AS parent_text, pd.remote AS parent_remote, m.display_name, m.avatar_url, m.layout,
+    d.remote
+FROM d
+JOIN messages AS m ON m.id = d.message_id
+LEFT JOIN messages AS p ON p.id = m.parent_id
+LEFT JOIN deliveries AS pd ON pd.message_id = p.id AND pd.workspace_id = d.workspace_id
+ORDER BY d.id
+            "#,
+            )
+            .bind(message_id)
+            .bind(targets.iter().map(|id| id.to_raw()).collect::<Vec<_>>())
+            .bind(lease.as_secs_f64())
+            .bind(message.send_at)
+            .fetch_all(&mut tx)
+            .await?;
+
+            tx.commit().await?;
+
+            Ok((
+                entity::MessageId::new(message_id),
+                rows.into_iter()
+                    .map(|row| entity::Delivery {
+                        attachments: message.attachments.clone(),
+                        ..entity::Delivery::from(row)
+                    })
+                    .collect(),
+            ))
+        }
+
+        async fn claim(&self, limit: i64, lease: Duration) -> Result<Vec<entity::Delivery>> {
+            let rows = sqlx::query_as::<_, DeliveryDBRow>(
+                r#"
+UPDATE deliveries AS d
+SET attempts = d.attempts + 1, next_attempt_at = now() + make_interval(secs => $2)
+FROM messages AS m
+WHERE m.id = d.message_id AND d.id IN (
+    SELECT id FROM deliveries
+    WHERE status = 'pending' AND next_attempt_at <= now()
+    ORDER BY next_attempt_at
+    LIMIT $1
+    FOR UPDATE SKIP LOCKED
+)
+RETURNING d.id, d.message_id, d.workspace_id, m.text, d.attempts,
+    (SELECT p.text FROM messages AS p WHERE p.id = m.parent_id) AS parent_text,
+    (
+        SELECT pd.remote FROM deliveries AS pd
+        WHERE pd.message_id = m.parent_id AND pd.workspace_id = d.workspace_id
+    ) AS parent_remote,
+    m.display_name, m.avatar_url, m.layout, d.remote
+            "#,
+            )
+            .bind(limit)
+            .bind(lease.as_secs_f64())
+            .fetch_all(&self.pool)
+            .await?;
+
+            let attachments = self
+                .attachments(rows.iter().map(|row| row.message_id).collect())
+                .await?;
+            Ok(rows
+                .into_iter()
+                .map(|row| entity::Delivery {
+                    attachments: attachments
+                        .get(&row.message_id)
+                        .cloned()
+                        .unwrap_or_default(),
+                    ..entity::Delivery::from(row)
+                })
+                .collect())
+        }
+
+        async fn mark_sent(
+            &self,
+            id: &entity::DeliveryId,
+            result: &serde_json::Value,
+            remote: Option<&serde_json::Value>,
+        ) -> Result<bool> {
+            let res = sqlx::query(
+                r#"
+UPDATE deliveries
+SET status = $2, result = $3, remote = $4, updated_at = now()
+WHERE id = $1 AND status = $5
+            "#,
+            )
+            .bind(id.to_raw())
+            .bind(entity::DeliveryState::Sent.to_string())
+            .bind(result)
+            .bind(remote)
+            .bind(entity::DeliveryState::Pending.to_string())
+            .execute(&self.pool)
+            .await?;
+            Ok(res.rows_affected() > 0)
+        }
+
+        async fn mark_failed(
+            &self,
+            id: &entity::DeliveryId,
+            result: &serde_json::Value,
+            retry_after: Option<Duration>,
+            remote: Option<&serde_json::Value>,
+        ) -> Result<()> {
+            let state = match retry_after {
+                Some(_) => entity::DeliveryState::Pending,
+                None => entity::DeliveryState::Failed,
+            };
+            sqlx::query(
+                r#"
+UPDATE deliveries
+SET status = $2,
+    result = $3,
+    next_attempt_at = COALESCE(now() + make_interval(secs => $4), next_attempt_at),
+    remote = $6,
+    updated_at = now()
+WHERE id = $1 AND status = $5
+            "#,
+            )
+            .bind(id.to_raw())
+            .bind(state.to_string())
+            .bind(result)
+            .bind(retry_after.map(|d| d.as_secs_f64()))
+            .bind(entity::DeliveryState::Pending.to_string())
+            .bind(remote)
+            .execute(&self.pool)
+            .await?;
+            Ok(())
+        }
+
+        async fn update_remote(
+            &self,
+            message_id: &entity::MessageId,
+            workspace_id: &entity::WorkspaceId,
+            remote: Option<&serde_json::Value>,
+        ) -> Result<()> {
+            sqlx::query(
+                r#"
+UPDATE deliveries SET remote = $3
+WHERE message_id = $1 AND workspace_id = $2 AND status = $4
+            "#,
+            )
+            .bind(message_id.to_raw())
+            .bind(workspace_id.to_raw())
+            .bind(remote)
+            .bind(entity::DeliveryState::Sent.to_string())
+            .execute(&self.pool)
+            .await?;
+            Ok(())
+        }
+
+        async fn all(
+            &self,
+            limit: i64,
+            before: Option<entity::MessageId>,
+        ) -> Result<Vec<entity::Message>> {
+            let rows = sqlx::query_as::<_, MessageDBRow>(
+                r#"
+SELECT id, text, created_at, parent_id, edited_at, deleted_at, layout, send_at
+FROM messages
+WHERE ($2::INTEGER IS NULL OR id < $2) AND (send_at IS NULL OR send_at <= now())
+ORDER BY id DESC
+LIMIT $1
+            "#,
+            )
+            .bind(limit)
+            .bind(before.map(|id| id.to_raw()))
+            .fetch_all(&self.pool)
+            .await?;
+
+            self.with_deliveries(rows).await
+        }
+
+        async fn find(&self, id: entity::MessageId) -> Result<entity::Message> {
+            let row = sqlx::query_as::<_, MessageDBRow>(
+                r#"
+SELECT id, text, created_at, parent_id, edited_at, deleted_at, layout, send_at
+FROM messages
+WHERE id = $1
+            "#,
+            )
+            .bind(id.to_raw())
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => MessageRepositoryError::NotFound(id),
+                _ => MessageRepositoryError::Unexpected(e.to_string()),
+            })?;
+
+            let mut messages = self.with_deliveries(vec![row]).await?;
+            Ok(messages.remove(0))
+        }
+
+        async fn find_attachment(&self, key: &str) -> Result<entity::Attachment> {
+            let row = sqlx::query_as::<_, AttachmentDBRow>(
+                r#"
+SELECT a.message_id, a.key, a.filename, a.content_type, a.data
+FROM attachments a
+JOIN messages m ON m.id = a.message_id
+WHERE a.key = $1 AND m.deleted_at IS NULL
+            "#,
+            )
+            .bind(key)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => {
+                    MessageRepositoryError::AttachmentNotFound(key.to_string())
+                }
+                _ => MessageRepositoryError::Unexpected(e.to_string()),
+            })?;
+            Ok(entity::Attachment::from(row))
+        }
+
+        async fn update_text(&self, id: &entity::MessageId, text: &str) -> Result<()> {
+            let res = sqlx::query(
+                r#"
+UPDATE messages
+SET text = $2, edited_at = now()
+WHERE id = $1 AND deleted_at IS NULL
+            "#,
+            )
+            .bind(id.to_raw())
+            .bind(text)
+            .execute(&self.pool)
+            .await?;
+            if res.rows_affected() == 0 {
+                return Err(MessageRepositoryError::NotFound(id.clone()).into());
+            }
+            Ok(())
+        }
+
+        async fn mark_deleted(
+            &self,
+            id: &entity::MessageId,
+            result: &serde_json::Value,
+        ) -> Result<()> {
+            let mut tx = self.pool.begin().await?;
+
+            let res = sqlx::query(
+                r#"
+UPDATE messages
+SET deleted_at = COALESCE(deleted_at, now())
+WHERE id = $1
+            "#,
+            )
+            .bind(id.to_raw())
+            .execute(&mut tx)
+            .await?;
+            if res.rows_affected() == 0 {
+                return Err(MessageRepositoryError::NotFound(id.clone()).into());
+            }
+
+            sqlx::query(
+                r#"
+UPDATE deliveries
+SET status = $2, result = $3, updated_at = now()
+WHERE message_id = $1 AND status = $4
+            "#,
+            )
+            .bind(id.to_raw())
+            .bind(entity::DeliveryState::Failed.to_string())
+            .bind(result)
+            .bind(entity::DeliveryState::Pending.to_string())
+            .execute(&mut tx)
+            .await?;
+
+            tx.commit().await?;
+            Ok(())
+        }
+
+        async fn search(
+            &self,
+            condition: &SearchCondition,
+            limit: i64,
+            before: Option<entity::MessageId>,
+        ) -> Result<Vec<entity::Message>> {
+            // 日本語は tsvector で単語に分割されないため, ILIKE (trigram index) でも検索する
+            let pattern = format!("%{}%", escape_like(condition.keyword.as_str()));
+            let rows = sqlx::query_as::<_, MessageDBRow>(
+                r#"
+SELECT m.id, m.text, m.created_at, m.parent_id, m.edited_at, m.deleted_at, m.layout, m.send_at
+FROM messages AS m
+WHERE (m.text_tsv @@ websearch_to_tsquery('simple', $1) OR m.text ILIKE $2)
+    AND ($3::TIMESTAMPTZ IS NULL OR m.created_at >= $3)
+    AND ($4::TIMESTAMPTZ IS NULL OR m.created_at < $4)
+    AND ($5::INTEGER IS NULL OR EXISTS (
+        SELECT 1 FROM deliveries AS d WHERE d.message_id = m.id AND d.workspace_id = $5
+    ))
+    AND ($6::INTEGER IS NULL OR m.id < $6)
+    AND (m.send_at IS NULL OR m.send_at <= now())
+ORDER BY m.id DESC
+LIMIT $7
+            "#,
+            )
+            .bind(condition.keyword.as_str())
+            .bind(pattern)
+            .bind(condition.from)
+            .bind(condition.to)
+            .bind(condition.workspace_id.as_ref().map(|id| id.to_raw()))
+            .bind(before.map(|id| id.to_raw()))
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
+
+            self.with_deliveries(rows).await
+        }
+
+        async fn scheduled(&self) -> Result<Vec<entity::Message>> {
+            let rows = sqlx::query_as::<_, MessageDBRow>(
+                r#"
+SELECT id, text, created_at, parent_id, edited_at, deleted_at, layout, send_at
+FROM messages
+WHERE send_at > now() AND deleted_at IS NULL
+ORDER BY send_at, id
+            "#,
+            )
+            .fetch_all(&self.pool)
+            .await?;
+
+            self.with_deliveries(rows).await
+        }
+
+        async fn update_scheduled(
+            &self,
+            id: &entity::MessageId,
+            send_at: Option<DateTime<Utc>>,
+            text: Option<&str>,
+        ) -> Result<()> {
+            let mut tx = self.pool.begin().await?;
+
+            let res = sqlx::query(
+                r#"
+UPDATE messages
+SET send_at = COALESCE($2, send_at), text = COALESCE($3, text)
+WHERE id = $1 AND send_at > now() AND deleted_at IS NULL
+            "#,
+            )
+            .bind(id.to_raw())
+            .bind(send_at)
+            .bind(text)
+            .execute(&mut tx)
+            .await?;
+            if res.rows_affected() == 0 {
+                return Err(MessageRepositoryError::NotFound(id.clone()).into());
+            }
+            let Some(send_at) = send_at else {
+                tx.commit().await?;
+                return Ok(());
+            };
+
+            sqlx::query(
+                r#"
+UPDATE deliveries
+SET next_attempt_at = $2, updated_at = now()
+WHERE message_id = $1 AND status = $3
+            "#,
+            )
+            .bind(id.to_raw())
+            .bind(send_at)
+            .bind(entity::DeliveryState::Pending.to_string())
+            .execute(&mut tx)
+            .await?;
+
+            tx.commit().await?;
+            Ok(())
+        }
+
+        async fn cancel_scheduled(&self, id: &entity::MessageId) -> Result<()> {
+            // deliveries と attachments は ON DELETE CASCADE で消える
+            let res = sqlx::query(
+                r#"
+DELETE FROM messages
+WHERE id = $1 AND send_at > now() AND deleted_at IS NULL
+            "#,
+            )
+            .bind(id.to_raw())
+            .execute(&self.pool)
+            .await?;
+            if res.rows_affected() == 0 {
+                return Err(MessageRepositoryError::NotFound(id.clone()).into());
+            }
+            Ok(())
+        }
+    }
+
+    // LIKE の pattern で特別な意味を持つ文字を escape する
+    fn escape_like(s: &str) -> String {
+        s.replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    }
+
+    impl MessageRepositoryForDB {
+        // message ごとの attachments を取得する
+        async fn attachments(
+            &self,
+            message_ids: Vec<entity::MessageIdTypeAlias>,
+        ) -> Result<HashMap<entity::MessageIdTypeAlias, Vec<entity::Attachment>>> {
+            let rows = sqlx::query_as::<_, AttachmentDBRow>(
+                r#"
+SELECT message_id, key, filename, content_type, data
+FROM attachments
+WHERE message_id = ANY($1)
+ORDER BY id
+            "#,
+            )
+            .bind(message_ids)
+            .fetch_all(&self.pool)
+            .await?;
+
+            let mut attachments: HashMap<_, Vec<_>> = HashMap::new();
+            for row in rows {
+                attachments
+                    .entry(row.message_id)
+                    .or_default()
+                    .push(entity::Attachment::from(row));
+            }
+            Ok(attachments)
+        }
+
+        // message の各Rowに deliveries を紐付ける
+        async fn with_deliveries(&self, rows: Vec<MessageDBRow>) -> Result<Vec<entity::Message>> {
+            let records = sqlx::query_as::<_, DeliveryRecordDBRow>(
+                r#"
+SELECT message_id, workspace_id, status, attempts, result, remote, updated_at
+FROM deliveries
+WHERE message_id = ANY($1)
+ORDER BY id
+            "#,
+            )
+            .bind(rows.iter().map(|row| row.id).collect::<Vec<_>>())
+            .fetch_all(&self.pool)
+            .await?;
+
+            let mut records_map: HashMap<entity::MessageIdTypeAlias, Vec<entity::DeliveryRecord>> =
+                HashMap::new();
+            for record in records {
+                records_map
+                    .entry(record.message_id)
+                    .or_default()
+                    .push(entity::DeliveryRecord::try_from(record)?);
+            }
+
+            Ok(rows
+                .into_iter()
+                .map(|row| entity::Message {
+                    id: entity::MessageId::new(row.id),
+                    text: row.text,
+                    created_at: row.created_at,
+                    parent_id: row.parent_id.map(entity::MessageId::new),
+                    edited_at: row.edited_at,
+                    deleted_at: row.deleted_at,
+                    layout: layout(row.id, row.layout),
+                    send_at: row.send_at,
+                    deliveries: records_map.remove(&row.id).unwrap_or_default(),
+                })
+                .collect())
+        }
+    }
+}
+
+// #[cfg(test)]
+pub mod test_utils {
+    use super::*;
+    use axum::async_trait;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::sync::RwLock;
+    use std::sync::RwLockReadGuard;
+    use std::sync::RwLockWriteGuard;
+    use std::time::Instant;
+
+    #[derive(Debug, Clone)]
+    struct MessageOnMemory {
+        text: String,
+        profile: entity::Profile,
+        attachments: Vec<entity::Attachment>,
+        layout: Option<entity::Layout>,
+        send_at: Option<DateTime<Utc>>,
+        created_at: DateTime<Utc>,
+        parent_id: Option<entity::MessageId>,
+        edited_at: Option<DateTime<Utc>>,
+        deleted_at: Option<DateTime<Utc>>,
+    }
+
+    #[derive(Debug, Clone)]
+    struct DeliveryOnMemory {
+        delivery: entity::Delivery,
+        state: entity::DeliveryState,
+        result: Option<serde_json::Value>,
+        remote: Option<serde_json::Value>,
+        next_attempt_at: Instant,
+        updated_at: DateTime<Utc>,
+    }
+
+    #[derive(Debug, Default)]
+    struct MessageDBOnMemory {
+        messages: HashMap<entity::MessageId, MessageOnMemory>,
+        deliveries: HashMap<entity::DeliveryId, DeliveryOnMemory>,
+    }
+
+    impl MessageDBOnMemory {
+        fn parent(
+            &self,
+            message_id: &entity::MessageId,
+            workspace_id: &entity::WorkspaceId,
+        ) -> Option<entity::DeliveryParent> {
+            let parent_id = self.messages.get(message_id)?.parent_id.as_ref()?;
+            let parent = self.messages.get(parent_id)?;
+            let remote = self
+                .deliveries
+                .values()
+                .find(|d| {
+                    &d.delivery.message_id == parent_id && &d.delivery.workspace_id == workspace_id
+                })
+                .and_then(|d| d.remote.clone());
+            Some(entity::DeliveryParent {
+                text: parent.text.clone(),
+                remote,
+            })
+        }
+
+        // 新しい順に `before` より前で `pred` を満たす message を最大 `limit` 件返す
+        fn filter_messages<F>(
+            &self,
+            limit: i64,
+            before: Option<entity::MessageId>,
+            pred: F,
+        ) -> Vec<entity::Message>
+        where
+            F: Fn(&entity::Message) -> bool,
+        {
+            let mut ids = self
+                .messages
+                .keys()
+                .filter(|id| before.as_ref().is_none_or(|before| *id < before))
+                .collect::<Vec<_>>();
+            ids.sort_by(|a, b| b.cmp(a));
+
+            ids.into_iter()
+                .filter_map(|id| self.to_message(id))
+                .filter(|m| pred(m))
+                .take(limit.max(0) as usize)
+                .collect()
+        }
+
+        fn to_message(&self, id: &entity::MessageId) -> Option<entity::Message> {
+            let message = self.messages.get(id)?;
+            let mut deliveries = self
+                .deliveries
+                .values()
+                .filter(|d| &d.delivery.message_id == id)
+                .collect::<Vec<_>>();
+            deliveries.sort_by_key(|d| d.delivery.id.clone());
+
+            Some(entity::Message {
+                id: id.clone(),
+                text: message.text.clone(),
+                created_at: message.created_at,
+                parent_id: message.parent_id.clone(),
+                edited_at: message.edited_at,
+                deleted_at: message.deleted_at,
+                layout: message.layout.clone(),
+                send_at: message.send_at,
+                deliveries: deliveries
+                    .into_iter()
+                    .map(|d| entity::DeliveryRecord {
+                        workspace_id: d.delivery.workspace_id.clone(),
+                        state: d.state.clone(),
+                        attempts: d.delivery.attempts,
+                        result: d.result.clone(),
+                        remote: d.remote.clone(),
+                        updated_at: d.updated_at,
+                    })
+                    .collect(),
+            })
+        }
+    }
+
+    // オンメモリの outbox
+    #[derive(Clone, Debug)]
+    pub struct MessageRepositoryForMemory {
+        store: Arc<RwLock<MessageDBOnMemory>>,
+    }
+
+    impl MessageRepositoryForMemory {
+        pub fn new() -> Self {
+            Self {
+                store: Arc::default(),
+            }
+        }
+
+        fn write_store_ref(&self) -> RwLockWriteGuard<'_, MessageDBOnMemory> {
+            self.store.write().unwrap()
+        }
+
+        fn read_store_ref(&self) -> RwLockReadGuard<'_, MessageDBOnMemory> {
+            self.store.read().unwrap()
+        }
+    }
+
+    #[async_trait]
+    impl MessageRepository for MessageRepositoryForMemory {
+        async fn enqueue(
+            &self,
+            message: &NewMessage,
+            targets: &[entity::WorkspaceId],
+            lease: Duration,
+        ) -> Result<(entity::MessageId, Vec<entity::Delivery>)> {
+            let mut store = self.write_store_ref();
+            // 予約送信の取り消しで削除されることがあるので, 件数ではなく最大の ID から採番する
+            let message_id = entity::MessageId::new(
+                store
+                    .messages
+                    .keys()
+                    .map(|id| id.to_raw())
+                    .max()
+                    .unwrap_or(0)
+                    + 1,
+            );
+            let now = Utc::now();
+            store.messages.insert(
+                message_id.clone(),
+                MessageOnMemory {
+                    text: message.text.clone(),
+                    profile: message.profile.clone(),
+                    attachments: message.attachments.clone(),
+                    layout: message.layout.clone(),
+                    send_at: message.send_at,
+                    created_at: now,
+                    parent_id: message.parent_id.clone(),
+                    edited_at: None,
+                    deleted_at: None,
+                },
+            );
+
+            let mut deliveries = vec![];
+            for workspace_id in targets {
+                let id = entity::DeliveryId::new(
+                    store
+                        .deliveries
+                        .keys()
+                        .map(|id| id.to_raw())
+                        .max()
+                        .unwrap_or(0)
+                        + 1,
+                );
+                let delivery = entity::Delivery {
+                    id: id.clone(),
+                    message_id: message_id.clone(),
+                    workspace_id: workspace_id.clone(),
+                    text: message.text.clone(),
+                    attempts: if message.send_at.is_some() { 0 } else { 1 },
+                    parent: store.parent(&message_id, workspace_id),
+                    profile: message.profile.clone(),
+                    attachments: message.attachments.clone(),
+                    layout: message.layout.clone(),
+                    remote: None,
+                };
+                store.deliveries.insert(
+                    id,
+                    DeliveryOnMemory {
+                        delivery: delivery.clone(),
+                        state: entity::DeliveryState::Pending,
+                        result: None,
+                        remote: None,
+                        next_attempt_at: match message.send_at {
+                            Some(send_at) => instant_at(send_at),
+                            None => Instant::now() + lease,
+                        },
+                        updated_at: now,
+                    },
+                );
+                deliveries.push(delivery);
+            }
+            Ok((message_id, deliveries))
+        }
+
+        async fn claim(&self, limit: i64, lease: Duration) -> Result<Vec<entity::Delivery>> {
+            let mut store = self.write_store_ref();
+            let now = Instant::now();
+            let mut due = store
+                .deliveries
+                .values_mut()
+                .filter(|d| d.state == entity::DeliveryState::Pending && d.next_attempt_at <= now)
+                .collect::<Vec<_>>();
+            due.sort_by_key(|d| d.next_attempt_at);
+
+            let mut claimed = due
+                .into_iter()
+                .take(limit.max(0) as usize)
+                .map(|d| {
+                    d.delivery.attempts += 1;
+                    d.next_attempt_at = now + lease;
+                    entity::Delivery {
+                        remote: d.remote.clone(),
+                        ..d.delivery.clone()
+                    }
+                })
+                .collect::<Vec<_>>();
+            // 編集された message は新しい text で送る
+            for d in claimed.iter_mut() {
+                if let Some(message) = store.messages.get(&d.message_id) {
+                    d.text = message.text.clone();
+                    d.profile = message.profile.clone();
+                }
+                d.parent = store.parent(&d.message_id, &d.workspace_id);
+            }
+            Ok(claimed)
+        }
+
+        async fn mark_sent(
+            &self,
+            id: &entity::DeliveryId,
+            result: &serde_json::Value,
+            remote: Option<&serde_json::Value>,
+        ) -> Result<bool> {
+            let mut store = self.write_store_ref();
+            match store.deliveries.get_mut(id) {
+                Some(d) if d.state == entity::DeliveryState::Pending => {
+                    d.state = entity::DeliveryState::Sent;
+                    d.result = Some(result.clone());
+                    d.remote = remote.cloned();
+                    d.updated_at = Utc::now();
+                    Ok(true)
+                }
+                _ => Ok(false),
+            }
+        }
+
+        async fn mark_failed(
+            &self,
+            id: &entity::DeliveryId,
+            result: &serde_json::Value,
+            retry_after: Option<Duration>,
+            remote: Option<&serde_json::Value>,
+        ) -> Result<()> {
+            let mut store = self.write_store_ref();
+            let pending = store
+                .deliveries
+                .get_mut(id)
+                .filter(|d| d.state == entity::DeliveryState::Pending);
+            if let Some(d) = pending {
+                d.result = Some(result.clone());
+                d.remote = remote.cloned();
+                d.updated_at = Utc::now();
+                match retry_after {
+                    Some(after) => {
+                        d.state = entity::DeliveryState::Pending;
+                        d.next_attempt_at = Instant::now() + after;
+                    }
+                    None => d.state = entity::DeliveryState::Failed,
+                }
+            }
+            Ok(())
+        }
+
+        async fn update_remote(
+            &self,
+            message_id: &entity::MessageId,
+            workspace_id: &entity::WorkspaceId,
+            remote: Option<&serde_json::Value>,
+        ) -> Result<()> {
+            let mut store = self.write_store_ref();
+            store
+                .deliveries
+                .values_mut()
+                .filter(|d| {
+                    &d.delivery.message_id == message_id
+                        && &d.delivery.workspace_id == workspace_id
+                        && d.state == entity::DeliveryState::Sent
+                })
+                .for_each(|d| d.remote = remote.cloned());
+            Ok(())
+        }
+
+        async fn all(
+            &self,
+            limit: i64,
+            before: Option<entity::MessageId>,
+        ) -> Result<Vec<entity::Message>> {
+            let store = self.read_store_ref();
+            Ok(store.filter_messages(limit, before, |m| !is_scheduled(m)))
+        }
+
+        async fn find(&self, id: entity::MessageId) -> Result<entity::Message> {
+            let store = self.read_store_ref();
+            let message = store
+                .to_message(&id)
+                .ok_or(MessageRepositoryError::NotFound(id))?;
+            Ok(message)
+        }
+
+        async fn find_attachment(&self, key: &str) -> Result<entity::Attachment> {
+            let store = self.read_store_ref();
+            let attachment = store
+                .messages
+                .values()
+                .filter(|m| m.deleted_at.is_none())
+                .flat_map(|m| m.attachments.iter())
+                .find(|a| a.key == key)
+                .ok_or(MessageRepositoryError::AttachmentNotFound(key.to_string()))?;
+            Ok(attachment.clone())
+        }
+
+        async fn update_text(&self, id: &entity::MessageId, text: &str) -> Result<()> {
+            let mut store = self.write_store_ref();
+            let message = store
+                .messages
+                .get_mut(id)
+                .filter(|m| m.deleted_at.is_none())
+                .ok_or(MessageRepositoryError::NotFound(id.clone()))?;
+            message.text = text.to_string();
+            message.edited_at = Some(Utc::now());
+            Ok(())
+        }
+
+        async fn mark_deleted(
+            &self,
+            id: &entity::MessageId,
+            result: &serde_json::Value,
+        ) -> Result<()> {
+            let mut store = self.write_store_ref();
+            let now = Utc::now();
+            let message = store
+                .messages
+                .get_mut(id)
+                .ok_or(MessageRepositoryError::NotFound(id.clone()))?;
+            message.deleted_at.get_or_insert(now);
+            for d in store.deliveries.values_mut() {
+                if &d.delivery.message_id == id && d.state == entity::DeliveryState::Pending {
+                    d.state = entity::DeliveryState::Failed;
+                    d.result = Some(result.clone());
+                    d.updated_at = now;
+                }
+            }
+            Ok(())
+        }
+
+        async fn search(
+            &self,
+            condition: &SearchCondition,
+            limit: i64,
+            before: Option<entity::MessageId>,
+        ) -> Result<Vec<entity::Message>> {
+            // 大文字小文字を区別しない部分一致
+            let keyword = condition.keyword.to_lowercase();
+            let store = self.read_store_ref();
+            Ok(store.filter_messages(limit, before, |m| {
+                !is_scheduled(m)
+                    && m.text.to_lowercase().contains(keyword.as_str())
+                    && condition.from.is_none_or(|from| m.created_at >= from)
+                    && condition.to.is_none_or(|to| m.created_at < to)
+                    && condition
+                        .workspace_id
+                        .as_ref()
+                        .is_none_or(|ws_id| m.deliveries.iter().any(|d| &d.workspace_id == ws_id))
+            }))
+        }
+
+        async fn scheduled(&self) -> Result<Vec<entity::Message>> {
+            let store = self.read_store_ref();
+            let mut messages = store.filter_messages(i64::MAX, None, |m| {
+                is_scheduled(m) && m.deleted_at.is_none()
+            });
+            messages.sort_by_key(|m| (m.send_at, m.id.clone()));
+            Ok(messages)
+        }
+
+        async fn update_scheduled(
+            &self,
+            id: &entity::MessageId,
+            send_at: Option<DateTime<Utc>>,
+            text: Option<&str>,
+        ) -> Result<()> {
+            let mut store = self.write_store_ref();
+            let message = store
+                .messages
+                .get_mut(id)
+                .filter(|m| m.deleted_at.is_none() && m.send_at.is_some_and(|t| t > Utc::now()))
+                .ok_or(MessageRepositoryError::NotFound(id.clone()))?;
+            if let Some(text) = text {
+                message.text = text.to_string();
+            }
+            let Some(send_at) = send_at else {
+                return Ok(());
+            };
+            message.send_at = Some(send_at);
+            for d in store.deliveries.values_mut() {
+                if &d.delivery.message_id == id && d.state == entity::DeliveryState::Pending {
+                    d.next_attempt_at = instant_at(send_at);
+                    d.updated_at = Utc::now();
+                }
+            }
+            Ok(())
+        }
+
+        async fn cancel_scheduled(&self, id: &entity::MessageId) -> Result<()> {
+            let mut store = self.write_store_ref();
+            store
+                .messages
+                .get(id)
+                .filter(|m| m.deleted_at.is_none() && m.send_at.is_some_and(|t| t > Utc::now()))
+                .ok_or(MessageRepositoryError::NotFound(id.clone()))?;
+            store.messages.remove(id);
+            store.deliveries.retain(|_, d| &d.delivery.message_id != id);
+            Ok(())
+        }
+    }
+
+    // 送信時刻を過ぎていない予約送信の message か
+    fn is_scheduled(message: &entity::Message) -> bool {
+        message.send_at.is_some_and(|t| t > Utc::now())
+    }
+
+    // 日時を Instant にする. 過ぎた日時は現在とする
+    fn instant_at(at: DateTime<Utc>) -> Instant {
+        Instant::now() + (at - Utc::now()).to_std().unwrap_or_default()
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[tokio::test]
+        async fn outbox_scenario() {
+            let repo = MessageRepositoryForMemory::new();
+            let targets = vec![entity::WorkspaceId::new(1), entity::WorkspaceId::new(2)];
+
+            // enqueue した delivery は lease の間 claim されない
+            let (message_id, deliveries) = repo
+                .enqueue(&NewMessage::new("hello"), &targets, Duration::from_secs(60))
+                .await
+                .expect("failed to enqueue");
+            assert_eq!(message_id, entity::MessageId::new(1));
+            assert_eq!(deliveries.len(), 2);
+            assert!(deliveries.iter().all(|d| d.attempts == 1));
+            let claimed = repo
+                .claim(10, Duration::from_secs(60))
+                .await
+                .expect("failed to claim");
+            assert!(claimed.is_empty());
+
+            // 1 件目は送信成功, 2 件目は途中まで送って再送待ち
+            repo.mark_sent(
+                &deliveries[0].id,
+                &serde_json::json!({"status": "sent"}),
+                Some(&serde_json::json!({"type": "discord", "id": "1"})),
+            )
+            .await
+            .expect("failed to mark sent");
+            let timeout = serde_json::json!({"status": "timeout"});
+            let partial = serde_json::json!({"chunks": [{"type": "discord", "id": "2"}]});
+            repo.mark_failed(
+                &deliveries[1].id,
+                &timeout,
+                Some(Duration::ZERO),
+                Some(&partial),
+            )
+            .await
+            .expect("failed to mark failed");
+
+            let claimed = repo
+                .claim(10, Duration::from_secs(60))
+                .await
+                .expect("failed to claim");
+            assert_eq!(claimed.len(), 1);
+            assert_eq!(claimed[0].id, deliveries[1].id);
+            assert_eq!(claimed[0].text, "hello");
+            assert_eq!(claimed[0].attempts, 2);
+            assert_eq!(claimed[0].remote, Some(partial));
+
+            // 再送しない失敗は以降 claim されない
+            let rejected = serde_json::json!({"status": "rejected", "http_status": 400});
+            repo.mark_failed(&claimed[0].id, &rejected, None, None)
+                .await
+                .expect("failed to mark failed");
+            let claimed = repo
+                .claim(10, Duration::ZERO)
+                .await
+                .expect("failed to claim");
+            assert!(claimed.is_empty());
+
+            // 履歴
+            let message = repo.find(message_id).await.expect("failed to find message");
+            assert_eq!(message.text, "hello");
+            let states = message
+                .deliveries
+                .iter()
+                .map(|d| (d.state.clone(), d.result.clone()))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                states,
+                vec![
+                    (
+                        entity::DeliveryState::Sent,
+                        Some(serde_json::json!({"status": "sent"}))
+                    ),
+                    (entity::DeliveryState::Failed, Some(rejected)),
+                ]
+            );
+        }
+
+        #[tokio::test]
+        async fn message_pagination() {
+            let repo = MessageRepositoryForMemory::new();
+            for text in ["1", "2", "3"] {
+                repo.enqueue(&NewMessage::new(text), &[], Duration::ZERO)
+                    .await
+                    .expect("failed to enqueue");
+            }
+
+            let page = repo.all(2, None).await.expect("failed to list messages");
+            let texts = page.iter().map(|m| m.text.as_str()).collect::<Vec<_>>();
+            assert_eq!(texts, vec!["3", "2"]);
+
+            let page = repo
+                .all(2, Some(page[1].id.clone()))
+                .await
+                .expect("failed to list messages");
+            let texts = page.iter().map(|m| m.text.as_str()).collect::<Vec<_>>();
+            assert_eq!(texts, vec!["1"]);
+
+            let e = repo
+                .find(entity::MessageId::new(4))
+                .await
+                .expect_err("message 4 should not exist");
+            assert!(matches!(
+                e.downcast_ref::<MessageRepositoryError>(),
+                Some(MessageRepositoryError::NotFound(_))
+            ));
+        }
+
+        #[tokio::test]
+        async fn scheduled_message() {
+            let repo = MessageRepositoryForMemory::new();
+            let targets = vec![entity::WorkspaceId::new(1)];
+            let message = NewMessage {
+                send_at: Some(Utc::now() + chrono::Duration::hours(1)),
+                ..NewMessage::new("good night")
+            };
+            let (message_id, deliveries) = repo
+                .enqueue(&message, &targets, Duration::ZERO)
+                .await
+                .expect("failed to enqueue");
+            assert_eq!(deliveries[0].attempts, 0);
+
+            // 送信時刻までは claim されず, 送信履歴にも出ない
+            let claimed = repo.claim(10, Duration::ZERO).await.unwrap();
+            assert!(claimed.is_empty());
+            assert!(repo.all(10, None).await.unwrap().is_empty());
+            let scheduled = repo.scheduled().await.unwrap();
+            assert_eq!(scheduled.len(), 1);
+            assert_eq!(scheduled[0].id, message_id);
+
+            // 送信前の text の変更は編集として扱わない
+            repo.update_scheduled(&message_id, None, Some("good night!"))
+                .await
+                .expect("failed to update text");
+            let scheduled = repo.scheduled().await.unwrap();
+            assert_eq!(scheduled[0].text, "good night!");
+            assert!(scheduled[0].edited_at.is_none());
+
+            // 送信時刻を過ぎると claim される
+            repo.update_scheduled(&message_id, Some(Utc::now()), None)
+                .await
+                .expect("failed to reschedule");
+            let claimed = repo.claim(10, Duration::from_secs(60)).await.unwrap();
+            assert_eq!(claimed.len(), 1);
+            assert_eq!(claimed[0].attempts, 1);
+            assert_eq!(claimed[0].text, "good night!");
+            assert!(repo.scheduled().await.unwrap().is_empty());
+            assert_eq!(repo.all(10, None).await.unwrap().len(), 1);
+
+            // 送信時刻を過ぎた message は変更・取り消しできない
+            let e = repo
+                .update_scheduled(&message_id, None, Some("too late"))
+                .await
+                .expect_err("sent message should not be updated");
+            assert!(matches!(
+                e.downcast_ref::<MessageRepositoryError>(),
+                Some(MessageRepositoryError::NotFound(_))
+            ));
+            let e = repo
+                .cancel_scheduled(&message_id)
+                .await
+                .expect_err("sent message should not be cancelled");
+            assert!(matches!(
+                e.downcast_ref::<MessageRepositoryError>(),
+                Some(MessageRepositoryError::NotFound(_))
+            ));
+
+            // 取り消した message は送信履歴にも残らない
+            let (message_id, _) = repo
+                .enqueue(&message, &targets, Duration::ZERO)
+                .await
+                .expect("failed to enqueue");
+            repo.cancel_scheduled(&message_id)
+                .await
+                .expect("failed to cancel");
+            assert!(repo.scheduled().await.unwrap().is_empty());
+            assert!(repo.find(message_id).await.is_err());
+        }
+
+        #[tokio::test]
+        async fn edit_and_delete_message() {
+            let repo = MessageRepositoryForMemory::new();
+            let targets = vec![entity::WorkspaceId::new(1), entity::WorkspaceId::new(2)];
+            let (message_id, deliveries) = repo
+                .enqueue(&NewMessage::new("helo"), &targets, Duration::ZERO)
+                .await
+                .expect("failed to enqueue");
+            repo.mark_sent(
+                &deliveries[0].id,
+                &serde_json::json!({"status": "sent"}),
+                None,
+            )
+            .await
+            .expect("failed to mark sent");
+
+            // 再送待ちの delivery は編集後の text で送られる
+            repo.update_text(&message_id, "hello")
+                .await
+                .expect("failed to update text");
+            let claimed = repo
+                .claim(10, Duration::from_secs(60))
+                .await
+                .expect("failed to claim");
+            assert_eq!(claimed.len(), 1);
+            assert_eq!(claimed[0].text, "hello");
+            let message = repo.find(message_id.clone()).await.unwrap();
+            assert_eq!(message.text, "hello");
+            assert!(message.edited_at.is_some());
+
+            // 削除すると再送待ちの delivery は取り消される
+            let cancelled = serde_json::json!({"status": "cancelled"});
+            repo.mark_deleted(&message_id, &cancelled)
+                .await
+                .expect("failed to mark deleted");
+            let message = repo.find(message_id.clone()).await.unwrap();
+            assert!(message.deleted_at.is_some());
+            assert_eq!(
+                message
+                    .deliveries
+                    .iter()
+                    .map(|d| (d.state.clone(), d.result.clone()))
+                    .collect::<Vec<_>>(),
+                vec![
+                    (
+                        entity::DeliveryState::Sent,
+                        Some(serde_json::json!({"status": "sent"}))
+                    ),
+                    (entity::DeliveryState::Failed, Some(cancelled.clone())),
+                ]
+            );
+
+            // 送信中だった delivery の結果で取り消しを上書きしない
+            let sent = serde_json::json!({"status": "sent"});
+            let recorded = repo.mark_sent(&claimed[0].id, &sent, None).await.unwrap();
+            assert!(!recorded);
+            repo.mark_failed(&claimed[0].id, &sent, Some(Duration::ZERO), None)
+                .await
+                .unwrap();
+            let message = repo.find(message_id.clone()).await.unwrap();
+            assert_eq!(message.deliveries[1].state, entity::DeliveryState::Failed);
+            assert_eq!(message.deliveries[1].result, Some(cancelled));
+
+            // 削除済みの message は編集できない
+            let e = repo
+                .update_text(&message_id, "hello!")
+                .await
+                .expect_err("deleted message should not be edited");
+            assert!(matches!(
+                e.downcast_ref::<MessageRepositoryError>(),
+                Some(MessageRepositoryError::NotFound(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use crate::message::service::MessageError;
+
+use ::anyhow::Result;
+use ::axum::http::StatusCode;
+use ::rand::Rng;
+use ::reqwest::header::RETRY_AFTER;
+use ::serde::Deserialize;
+use ::std::time::Duration;
+
+// webhook への送信の再試行の設定
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// `attempt` 回目の失敗後に待つ時間 (full jitter)
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let ceil = self
+            .base_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_backoff);
+        rand::thread_rng().gen_range(Duration::ZERO..=ceil)
+    }
+}
+
+// Discord は 429 の body に `retry_after` (秒) を含める
+// Telegram は `parameters.retry_after` に含める
+#[derive(Debug, Deserialize)]
+struct RateLimitBody {
+    retry_after: Option<f64>,
+    parameters: Option<RateLimitParameters>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RateLimitParameters {
+    retry_after: f64,
+}
+
+/// `build` で組み立てたリクエストを送り, 2xx 以外は失敗として扱う.
+///
+/// 429 は `Retry-After` header か body の `retry_after` に従って待ち,
+/// 5xx と接続エラーは jitter 付きの exponential backoff で再試行する.
+pub async fn send_with_retry<F>(policy: &RetryPolicy, build: F) -> Result<reqwest::Response>
+where
+    F: Fn() -> reqwest::RequestBuilder + Send + Sync,
+{
+    let res = send_with_retry_unchecked(policy, build).await?;
+    match res.status().is_success() {
+        true => Ok(res),
+        false => Err(MessageError::Rejected(res.status()).into()),
+    }
+}
+
+/// `send_with_retry` と同じく再試行するが, 再試行しなかった 2xx 以外の response もそのまま返す.
+/// body のエラーの内容で扱いを変える場合に使う
+pub async fn send_with_retry_unchecked<F>(
+    policy: &RetryPolicy,
+    build: F,
+) -> Result<reqwest::Response>
+where
+    F: Fn() -> reqwest::RequestBuilder + Send + Sync,
+{
+    let mut attempt = 0;
+    loop {
+        let wait = match build().send().await {
+            Ok(res) if res.status().is_success() => return Ok(res),
+            Ok(res) => {
+                let status = res.status();
+                if attempt >= policy.max_retries {
+                    return Ok(res);
+                }
+                if status == StatusCode::TOO_MANY_REQUESTS {
+                    match retry_after(res).await {
+                        // 待ち時間が長すぎる場合は諦める
+                        Some(d) if d > policy.max_backoff => {
+                            return Err(MessageError::Rejected(status).into());
+                        }
+                        Some(d) => d,
+                        None => policy.backoff(attempt),
+                    }
+                } else if status.is_server_error() {
+                    policy.backoff(attempt)
+                } else {
+                    return Ok(res);
+                }
+            }
+            Err(e) => {
+                if attempt >= policy.max_retries || !(e.is_connect() || e.is_timeout()) {
+                    return Err(MessageError::Transport(e.to_string()).into());
+                }
+                policy.backoff(attempt)
+            }
+        };
+        attempt += 1;
+        tracing::warn!(
+            "retry sending ({}/{}) after {:?}",
+            attempt,
+            policy.max_retries,
+            wait
+        );
+        tokio::time::sleep(wait).await;
+    }
+}
+
+async fn retry_after(res: reqwest::Response) -> Option<Duration> {
+    let header = res
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<f64>().ok());
+    let secs = match header {
+        Some(secs) => secs,
+        None => {
+            let body = res.json::<RateLimitBody>().await.ok()?;
+            body.retry_after
+                .or(body.parameters.map(|p| p.retry_after))?
+        }
+    };
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::axum::extract::Extension;
+    use ::axum::response::IntoResponse;
+    use ::axum::routing::post;
+    use ::axum::Router;
+    use ::std::net::SocketAddr;
+    use ::std::sync::atomic::{AtomicUsize, Ordering};
+    use ::std::sync::Arc;
+
+    // 1 回目は 429 を返し, 2 回目以降は 200 を返す
+    async fn rate_limited(Extension(hits): Extension<Arc<AtomicUsize>>) -> impl IntoResponse {
+        if hits.fetch_add(1, Ordering::SeqCst) == 0 {
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                r#"{"message": "You are being rate limited.", "retry_after": 0.01}"#,
+            )
+        } else {
+            (StatusCode::OK, "")
+        }
+    }
+
+    async fn bad_request(Extension(hits): Extension<Arc<AtomicUsize>>) -> StatusCode {
+        hits.fetch_add(1, Ordering::SeqCst);
+        StatusCode::BAD_REQUEST
+    }
+
+    async fn spawn_server(hits: Arc<AtomicUsize>) -> SocketAddr {
+        let app = Router::new()
+            .route("/rate_limited", post(rate_limited))
+            .route("/bad_request", post(bad_request))
+            .layer(Extension(hits));
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    #[tokio::test]
+    async fn retries_after_rate_limit() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let addr = spawn_server(hits.clone()).await;
+        let url = format!("http://{}/rate_limited", addr);
+        let client = reqwest::Client::new();
+
+        let res = send_with_retry(&RetryPolicy::default(), || client.post(&url))
+            .await
+            .expect("failed to send");
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_error() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let addr = spawn_server(hits.clone()).await;
+        let url = format!("http://{}/bad_request", addr);
+        let client = reqwest::Client::new();
+
+        let e = send_with_retry(&RetryPolicy::default(), || client.post(&url))
+            .await
+            .expect_err("client error should not succeed");
+        assert!(matches!(
+            e.downcast_ref::<MessageError>(),
+            Some(MessageError::Rejected(StatusCode::BAD_REQUEST))
+        ));
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let policy = RetryPolicy::default();
+        for attempt in 0..20 {
+            assert!(policy.backoff(attempt) <= policy.max_backoff);
+        }
+    }
+}
@@ -1,126 +1,6581 @@
-use crate::entity::WorkspaceType;
+use crate::entity;
+use crate::entity::{Workspace, WorkspaceType};
+use crate::message::markup;
+use crate::message::markup::{escape_html, Document, Markup};
+use crate::message::outbox;
+use crate::message::outbox::OutboxConfig;
+use crate::message::repository::{
+    MessageRepository, MessageRepositoryError, NewMessage, SearchCondition,
+};
+use crate::message::retry::{send_with_retry, send_with_retry_unchecked, RetryPolicy};
+use crate::workspace::repository::{RepositoryError, WorkspaceRepository};
 
 use ::anyhow::Result;
 use ::axum::async_trait;
+use ::axum::http::StatusCode;
+use ::chrono::{DateTime, Utc};
+use ::futures::stream;
+use ::futures::StreamExt;
+use ::once_cell::sync::Lazy;
+use ::regex::Regex;
+use ::serde::de::DeserializeOwned;
 use ::serde::Deserialize;
 use ::serde::Serialize;
 use ::std::boxed::Box;
+use ::std::collections::HashMap;
+use ::std::collections::HashSet;
+use ::std::sync::Arc;
+use ::std::time::{Duration, Instant};
 use ::thiserror::Error;
+use ::unicode_segmentation::UnicodeSegmentation;
 
 #[derive(Debug, Error)]
 pub enum MessageError {
-    #[error("Unexpected Error: {0}")]
-    Unexpected(String),
+    #[error("Webhook rejected the message with status {0}")]
+    Rejected(StatusCode),
+    #[error("Failed to send message to webhook: {0}")]
+    Transport(String),
+    #[error("Timed out after {0:?}")]
+    Timeout(Duration),
+    #[error("Invalid config for {0}: {1}")]
+    InvalidConfig(WorkspaceType, String),
+    // HTTP としては成功したが, API が body でエラーを返した場合
+    #[error("API returned an error: {0}")]
+    Api(String),
+    // 送信先が編集・削除などに対応していない場合
+    #[error("Unsupported operation: {0}")]
+    UnsupportedOperation(String),
+    // 添付ファイルの数やサイズが送信先の上限を超えた場合
+    #[error("Attachments exceed the limit: {0}")]
+    AttachmentLimit(String),
+    // text が送信先の上限を超え, workspace が分割しない設定の場合
+    #[error("Text is too long: {0}")]
+    TooLong(String),
+    // 予約送信の送信時刻が現在より前の場合
+    #[error("send_at must be in the future: {0}")]
+    PastSendAt(DateTime<Utc>),
 }
 
+/// workspace の設定から Sender を作る. 設定が不正な場合は `MessageError::InvalidConfig` を返す.
+///
+/// `text` は CommonMark として parse し, 送信先の記法に変換して送る.
+/// Markdown をそのまま解釈できる送信先には `text` をそのまま送る.
 pub fn get_sender(
-    ws_type: WorkspaceType,
-    webhook_url: &str,
+    ws: &Workspace,
     text: &str,
+    delivery: &DeliveryConfig,
 ) -> Result<Box<dyn Sender>, MessageError> {
-    match ws_type {
+    let retry = &delivery.retry;
+    let document = Document::parse(text);
+    match ws.ws_type {
         WorkspaceType::Slack => {
-            return Ok(Box::new(SlackSender::new(webhook_url, text)));
+            let webhook_url = require_webhook_url(ws)?;
+            Ok(Box::new(SlackSender::new(
+                webhook_url,
+                &ws.profile,
+                &document.render(Markup::Slack),
+                retry,
+            )))
+        }
+        WorkspaceType::SlackBot => {
+            let config: SlackBotConfig = parse_config(ws)?;
+            Ok(Box::new(SlackBotSender::new(
+                config,
+                &ws.profile,
+                &document.render(Markup::Slack),
+                retry,
+            )))
         }
         WorkspaceType::Discord => {
-            return Ok(Box::new(DiscordSender::new(webhook_url, text)));
-        } // _ => Err(MessageError::Unexpected(format!("Unknown workspace type: {}", ws_type)).into()),
+            let webhook_url = require_webhook_url(ws)?;
+            let config: DiscordConfig = parse_config(ws)?;
+            Ok(Box::new(DiscordSender::new(
+                webhook_url,
+                config,
+                &ws.profile,
+                &document.render(Markup::Discord),
+                retry,
+            )))
+        }
+        WorkspaceType::Teams => {
+            let webhook_url = require_webhook_url(ws)?;
+            Ok(Box::new(TeamsSender::new(webhook_url, text, retry)))
+        }
+        WorkspaceType::Mattermost => {
+            let webhook_url = require_webhook_url(ws)?;
+            let config: MattermostConfig = parse_config(ws)?;
+            Ok(Box::new(MattermostSender::new(
+                webhook_url,
+                config,
+                &ws.profile,
+                text,
+                retry,
+            )))
+        }
+        WorkspaceType::RocketChat => {
+            let webhook_url = require_webhook_url(ws)?;
+            let config: RocketChatConfig = parse_config(ws)?;
+            Ok(Box::new(RocketChatSender::new(
+                webhook_url,
+                config,
+                &ws.profile,
+                text,
+                retry,
+            )))
+        }
+        WorkspaceType::Telegram => {
+            let config: TelegramConfig = parse_config(ws)?;
+            let markup = match config.parse_mode {
+                Some(TelegramParseMode::MarkdownV2) => Markup::TelegramMarkdownV2,
+                Some(TelegramParseMode::Html) => Markup::TelegramHtml,
+                None => Markup::Plain,
+            };
+            let text = document.render(markup);
+            Ok(Box::new(TelegramSender::new(config, &text, retry)))
+        }
+        WorkspaceType::Matrix => {
+            let config: MatrixConfig = parse_config(ws)?;
+            let sender = MatrixSender::new(
+                config,
+                &document.render(Markup::Plain),
+                &document.render(Markup::Html),
+                retry,
+            )
+            .map_err(|e| MessageError::InvalidConfig(ws.ws_type.clone(), e.to_string()))?;
+            Ok(Box::new(sender))
+        }
+        WorkspaceType::GoogleChat => {
+            let webhook_url = require_webhook_url(ws)?;
+            let config: GoogleChatConfig = parse_config(ws)?;
+            // text の書式は Slack と同じ. card は payload で HTML として escape する
+            let text = match config.card {
+                true => document.render(Markup::Plain),
+                false => document.render(Markup::Slack),
+            };
+            let sender = GoogleChatSender::new(webhook_url, config, &text, retry)
+                .map_err(|e| MessageError::InvalidConfig(ws.ws_type.clone(), e.to_string()))?;
+            Ok(Box::new(sender))
+        }
+        WorkspaceType::Zulip => {
+            let config: ZulipConfig = parse_config(ws)?;
+            Ok(Box::new(ZulipSender::new(config, text, retry)))
+        }
+        WorkspaceType::Mastodon => {
+            let config: FediverseConfig = parse_config(ws)?;
+            Ok(Box::new(MastodonSender::new(
+                config,
+                &document.render(Markup::Plain),
+                retry,
+            )))
+        }
+        WorkspaceType::Misskey => {
+            let config: FediverseConfig = parse_config(ws)?;
+            Ok(Box::new(MisskeySender::new(
+                config,
+                &document.render(Markup::Plain),
+                retry,
+            )))
+        }
+        WorkspaceType::Bluesky => {
+            let config: BlueskyConfig = parse_config(ws)?;
+            Ok(Box::new(BlueskySender::new(
+                config,
+                &document.render(Markup::Plain),
+                retry,
+                &delivery.bluesky_sessions,
+            )))
+        }
+        WorkspaceType::Generic => {
+            let webhook_url = require_webhook_url(ws)?;
+            let config: GenericConfig = parse_config(ws)?;
+            let sender = GenericSender::new(ws, webhook_url, config, text, retry)
+                .map_err(|e| MessageError::InvalidConfig(ws.ws_type.clone(), e.to_string()))?;
+            Ok(Box::new(sender))
+        }
+        WorkspaceType::Email => {
+            let config: EmailConfig = parse_config(ws)?;
+            let sender = EmailSender::new(
+                config,
+                &document.render(Markup::Plain),
+                &document.render(Markup::Html),
+                retry,
+            )
+            .map_err(|e| MessageError::InvalidConfig(ws.ws_type.clone(), e.to_string()))?;
+            Ok(Box::new(sender))
+        }
+    }
+}
+
+fn require_webhook_url(ws: &Workspace) -> Result<&str, MessageError> {
+    if ws.webhook_url.is_empty() {
+        return Err(MessageError::InvalidConfig(
+            ws.ws_type.clone(),
+            "webhook_url is required".to_string(),
+        ));
+    }
+    Ok(ws.webhook_url.as_str())
+}
+
+// config を WorkspaceType ごとの設定の構造体に変換する
+fn parse_config<C: DeserializeOwned>(ws: &Workspace) -> Result<C, MessageError> {
+    let config = match &ws.config {
+        serde_json::Value::Null => serde_json::json!({}),
+        config => config.clone(),
+    };
+    serde_json::from_value(config)
+        .map_err(|e| MessageError::InvalidConfig(ws.ws_type.clone(), e.to_string()))
+}
+
+/// workspace を登録・更新する前に, Sender を作れる webhook_url と config か検証する
+pub fn validate_config(ws: &Workspace) -> Result<()> {
+    get_sender(ws, "", &DeliveryConfig::default())?;
+    long_text_policy(ws)?;
+    Ok(())
+}
+
+// 送信先の上限を超える text の扱い. workspace の config の long_text で指定する
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LongTextPolicy {
+    // 上限に収まるように分けて, 順に送る
+    #[default]
+    Split,
+    // 送信せずに MessageError::TooLong を返す
+    Reject,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct LongTextConfig {
+    #[serde(default)]
+    long_text: LongTextPolicy,
+}
+
+pub fn long_text_policy(ws: &Workspace) -> Result<LongTextPolicy, MessageError> {
+    parse_config::<LongTextConfig>(ws).map(|config| config.long_text)
+}
+
+/// text を送信先の上限に収まる chunk に分ける.
+/// workspace の long_text が reject の場合は分けずに `MessageError::TooLong` を返す.
+pub fn fit_text(
+    ws: &Workspace,
+    text: &str,
+    config: &DeliveryConfig,
+) -> Result<Vec<String>, MessageError> {
+    let sender = get_sender(ws, text, config)?;
+    let Some(max_chars) = sender.exceeds_limit() else {
+        return Ok(vec![text.to_string()]);
+    };
+    if long_text_policy(ws)? == LongTextPolicy::Reject {
+        return Err(MessageError::TooLong(format!(
+            "{} accepts up to {} characters",
+            ws.ws_type, max_chars
+        )));
+    }
+    if sender.truncates_long_text() {
+        return Ok(vec![text.to_string()]);
+    }
+    // 送信先の記法に変換すると長くなる場合があるので, 収まるまで短く分け直す
+    let mut budget = max_chars;
+    loop {
+        let chunks = markup::split_text(text, budget);
+        let mut fits = true;
+        for chunk in chunks.iter() {
+            fits &= get_sender(ws, chunk, config)?.exceeds_limit().is_none();
+        }
+        if fits || budget == 1 {
+            return Ok(chunks);
+        }
+        budget = budget * 3 / 4;
     }
 }
 
+////////////
+// Report //
+////////////
+
+// target ごとの送信結果
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub struct SlackMessagePayload {
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Sent,
+    Rejected { http_status: u16 },
+    TransportError { error: String },
+    ApiError { error: String },
+    Timeout,
+    UnknownWorkspace,
+    InvalidConfig { error: String },
+    // 送信前に message が削除された
+    Cancelled,
+    AttachmentLimit { error: String },
+    TooLong { error: String },
+}
+
+impl DeliveryStatus {
+    pub fn from_error(e: &anyhow::Error) -> Self {
+        match e.downcast_ref::<MessageError>() {
+            Some(MessageError::Rejected(status)) => Self::Rejected {
+                http_status: status.as_u16(),
+            },
+            Some(MessageError::Timeout(_)) => Self::Timeout,
+            Some(MessageError::Api(error)) => Self::ApiError {
+                error: error.clone(),
+            },
+            Some(e @ MessageError::InvalidConfig(..)) => Self::InvalidConfig {
+                error: e.to_string(),
+            },
+            Some(MessageError::AttachmentLimit(error)) => Self::AttachmentLimit {
+                error: error.clone(),
+            },
+            Some(MessageError::TooLong(error)) => Self::TooLong {
+                error: error.clone(),
+            },
+            _ => Self::TransportError {
+                error: e.to_string(),
+            },
+        }
+    }
+
+    pub fn is_sent(&self) -> bool {
+        matches!(self, Self::Sent)
+    }
+
+    /// outbox から再送すれば成功する見込みがあるか
+    pub fn is_retriable(&self) -> bool {
+        match self {
+            Self::TransportError { .. } | Self::Timeout => true,
+            Self::Rejected { http_status } => *http_status == 429 || *http_status >= 500,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeliveryResult {
+    pub target: entity::WorkspaceIdTypeAlias,
+    #[serde(flatten)]
+    pub status: DeliveryStatus,
+    // 失敗したが outbox から再送される
+    pub will_retry: bool,
+}
+
+// POST /message の response body
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageResponse {
+    pub message_id: entity::MessageIdTypeAlias,
+    pub results: Vec<DeliveryResult>,
+}
+
+impl MessageResponse {
+    /// 全て成功: 200, 一部成功: 207, 全て失敗: 502
+    pub fn status_code(&self) -> StatusCode {
+        let sent = self.results.iter().filter(|r| r.status.is_sent()).count();
+        if sent == self.results.len() {
+            StatusCode::OK
+        } else if sent > 0 {
+            StatusCode::MULTI_STATUS
+        } else {
+            StatusCode::BAD_GATEWAY
+        }
+    }
+}
+
+// 送信履歴の response body
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResponseMessage {
+    pub id: entity::MessageIdTypeAlias,
     pub text: String,
+    pub created_at: DateTime<Utc>,
+    // thread の返信の場合の親 message
+    pub parent_id: Option<entity::MessageIdTypeAlias>,
+    pub edited_at: Option<DateTime<Utc>>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub layout: Option<entity::Layout>,
+    // 予約送信の場合の送信時刻
+    pub send_at: Option<DateTime<Utc>>,
+    pub deliveries: Vec<ResponseDelivery>,
 }
 
-#[async_trait]
-pub trait Sender: Send + Sync {
-    async fn send(&self) -> Result<()>;
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResponseDelivery {
+    pub target: entity::WorkspaceIdTypeAlias,
+    // pending | sent | failed
+    pub state: String,
+    pub attempts: i32,
+    // 最後の送信結果 (未送信の場合は null)
+    pub result: Option<DeliveryStatus>,
+    // 送信先が返した message の参照
+    pub remote: Option<RemoteMessages>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResponseMessageList {
+    pub messages: Vec<ResponseMessage>,
+    // 次のページを取得する際の `before`
+    pub next: Option<entity::MessageIdTypeAlias>,
+}
+
+impl ResponseMessageList {
+    fn new(messages: Vec<entity::Message>, limit: i64) -> Self {
+        // limit 件取得できた場合のみ続きがあるとみなす
+        let next = match messages.last() {
+            Some(m) if messages.len() as i64 == limit => Some(m.id.to_raw()),
+            _ => None,
+        };
+        Self {
+            messages: messages.into_iter().map(ResponseMessage::from).collect(),
+            next,
+        }
+    }
+}
+
+impl From<entity::Message> for ResponseMessage {
+    fn from(message: entity::Message) -> Self {
+        Self {
+            id: message.id.to_raw(),
+            text: message.text,
+            created_at: message.created_at,
+            parent_id: message.parent_id.map(|id| id.to_raw()),
+            edited_at: message.edited_at,
+            deleted_at: message.deleted_at,
+            layout: message.layout,
+            send_at: message.send_at,
+            deliveries: message
+                .deliveries
+                .into_iter()
+                .map(|d| ResponseDelivery {
+                    target: d.workspace_id.to_raw(),
+                    state: d.state.to_string(),
+                    attempts: d.attempts,
+                    result: d.result.and_then(|r| serde_json::from_value(r).ok()),
+                    remote: d.remote.map(|r| RemoteMessages::from_value(Some(r))),
+                    updated_at: d.updated_at,
+                })
+                .collect(),
+        }
+    }
+}
+
+pub async fn all_messages<M>(
+    repo: Arc<M>,
+    limit: i64,
+    before: Option<entity::MessageId>,
+) -> Result<ResponseMessageList>
+where
+    M: MessageRepository,
+{
+    let messages = repo.all(limit, before).await?;
+    Ok(ResponseMessageList::new(messages, limit))
+}
+
+pub async fn search_messages<M>(
+    repo: Arc<M>,
+    condition: SearchCondition,
+    limit: i64,
+    before: Option<entity::MessageId>,
+) -> Result<ResponseMessageList>
+where
+    M: MessageRepository,
+{
+    let messages = repo.search(&condition, limit, before).await?;
+    Ok(ResponseMessageList::new(messages, limit))
 }
 
+pub async fn find_message<M>(repo: Arc<M>, id: entity::MessageId) -> Result<ResponseMessage>
+where
+    M: MessageRepository,
+{
+    let message = repo.find(id).await?;
+    Ok(ResponseMessage::from(message))
+}
+
+pub async fn scheduled_messages<M>(repo: Arc<M>) -> Result<Vec<ResponseMessage>>
+where
+    M: MessageRepository,
+{
+    let messages = repo.scheduled().await?;
+    Ok(messages.into_iter().map(ResponseMessage::from).collect())
+}
+
+/// 予約送信の message の送信時刻か text を変える.
+/// 分割しない設定の target に収まらない text は, 送信時刻を待たずに拒否する
+pub async fn update_scheduled<T, M>(
+    repo: Arc<T>,
+    outbox: Arc<M>,
+    config: &DeliveryConfig,
+    id: entity::MessageId,
+    send_at: Option<DateTime<Utc>>,
+    text: Option<&str>,
+) -> Result<ResponseMessage>
+where
+    T: WorkspaceRepository,
+    M: MessageRepository,
+{
+    if let Some(send_at) = send_at.filter(|t| *t <= Utc::now()) {
+        return Err(MessageError::PastSendAt(send_at).into());
+    }
+    if let Some(text) = text {
+        let message = outbox.find(id.clone()).await?;
+        let targets = message
+            .deliveries
+            .iter()
+            .map(|d| d.workspace_id.to_raw())
+            .collect();
+        let layout = message.layout.as_ref();
+        check_targets(&repo.all().await?, config, targets, text, layout)?;
+    }
+    outbox.update_scheduled(&id, send_at, text).await?;
+    find_message(outbox, id).await
+}
+
+pub async fn cancel_scheduled<M>(repo: Arc<M>, id: entity::MessageId) -> Result<()>
+where
+    M: MessageRepository,
+{
+    repo.cancel_scheduled(&id).await
+}
+
+// 送信の並列数, タイムアウト, 再試行の設定
 #[derive(Debug, Clone)]
-pub struct SlackSender {
-    webhook_url: String,
-    text: String,
+pub struct DeliveryConfig {
+    pub concurrency: usize,
+    pub timeout: Duration,
+    pub retry: RetryPolicy,
+    pub outbox: OutboxConfig,
+    // 添付ファイルの link に使う API の公開 URL
+    pub public_url: Option<String>,
+    // clone した DeliveryConfig の間 (API と outbox の worker) で共有する
+    pub bluesky_sessions: BlueskySessions,
 }
 
-impl SlackSender {
-    pub fn new(webhook_url: &str, text: &str) -> Self {
+impl Default for DeliveryConfig {
+    fn default() -> Self {
         Self {
-            webhook_url: webhook_url.to_string(),
-            text: text.to_string(),
+            concurrency: 8,
+            timeout: Duration::from_secs(60),
+            retry: RetryPolicy::default(),
+            outbox: OutboxConfig::default(),
+            public_url: None,
+            bluesky_sessions: BlueskySessions::default(),
         }
     }
 }
 
-#[async_trait]
-impl Sender for SlackSender {
-    async fn send(&self) -> Result<()> {
-        tracing::info!("send to slack webhook");
-        let payload = SlackMessagePayload {
-            text: self.text.clone(),
+/// message を outbox に登録してから各 target に送信する.
+/// 送信に失敗した target は outbox の worker が再送する.
+pub async fn send_message<T, M>(
+    repo: Arc<T>,
+    outbox: Arc<M>,
+    config: &DeliveryConfig,
+    targets: Vec<entity::WorkspaceIdTypeAlias>,
+    message: NewMessage,
+) -> Result<MessageResponse>
+where
+    T: WorkspaceRepository,
+    M: MessageRepository,
+{
+    deliver(repo, outbox, config, targets, message).await
+}
+
+/// message を outbox に登録し, `send_at` になったら outbox の worker に送らせる
+pub async fn schedule_message<T, M>(
+    repo: Arc<T>,
+    outbox: Arc<M>,
+    config: &DeliveryConfig,
+    targets: Vec<entity::WorkspaceIdTypeAlias>,
+    message: NewMessage,
+) -> Result<ResponseMessage>
+where
+    T: WorkspaceRepository,
+    M: MessageRepository,
+{
+    if let Some(send_at) = message.send_at.filter(|t| *t <= Utc::now()) {
+        return Err(MessageError::PastSendAt(send_at).into());
+    }
+    let ws_map = repo.all().await?;
+    let layout = message.layout.as_ref();
+    let targets = check_targets(&ws_map, config, targets, &message.text, layout)?;
+    let (message_id, _) = outbox.enqueue(&message, &targets, Duration::ZERO).await?;
+    find_message(outbox, message_id).await
+}
+
+/// targets の重複を除き, リクエストされた順に並べる.
+/// 分割しない設定の target に収まらない text は, 送信履歴に残さずに拒否する.
+/// layout を text に加えて送る target では, 加えた後の text で確かめる
+pub fn check_targets(
+    workspaces: &[entity::Workspace],
+    config: &DeliveryConfig,
+    targets: Vec<entity::WorkspaceIdTypeAlias>,
+    text: &str,
+    layout: Option<&entity::Layout>,
+) -> Result<Vec<entity::WorkspaceId>> {
+    let mut seen = HashSet::new();
+    let targets = targets
+        .into_iter()
+        .filter(|id| seen.insert(*id))
+        .map(entity::WorkspaceId::new)
+        .collect::<Vec<_>>();
+    for ws in workspaces.iter().filter(|ws| targets.contains(&ws.id)) {
+        let (text, _) = with_layout(ws, text, layout);
+        if let Err(e @ MessageError::TooLong(_)) = fit_text(ws, &text, config) {
+            return Err(e.into());
+        }
+    }
+    Ok(targets)
+}
+
+/// `parent_id` の message の thread に, 親 message と同じ target へ返信する.
+/// 返信の返信は, thread の先頭の message への返信として扱う.
+pub async fn reply_message<T, M>(
+    repo: Arc<T>,
+    outbox: Arc<M>,
+    config: &DeliveryConfig,
+    parent_id: entity::MessageId,
+    message: NewMessage,
+) -> Result<MessageResponse>
+where
+    T: WorkspaceRepository,
+    M: MessageRepository,
+{
+    let mut parent = outbox.find(parent_id).await?;
+    while let Some(id) = parent.parent_id.clone() {
+        parent = outbox.find(id).await?;
+    }
+    if parent.deleted_at.is_some() {
+        return Err(MessageRepositoryError::NotFound(parent.id).into());
+    }
+    let targets = parent
+        .deliveries
+        .iter()
+        .map(|d| d.workspace_id.to_raw())
+        .collect();
+    let message = NewMessage {
+        parent_id: Some(parent.id),
+        ..message
+    };
+    deliver(repo, outbox, config, targets, message).await
+}
+
+async fn deliver<T, M>(
+    repo: Arc<T>,
+    outbox: Arc<M>,
+    config: &DeliveryConfig,
+    targets: Vec<entity::WorkspaceIdTypeAlias>,
+    message: NewMessage,
+) -> Result<MessageResponse>
+where
+    T: WorkspaceRepository,
+    M: MessageRepository,
+{
+    // db から一覧取得
+    let workspaces = repo.all().await?;
+    // 結果はリクエストされた順に並べる
+    let layout = message.layout.as_ref();
+    let target_ids = check_targets(&workspaces, config, targets, &message.text, layout)?;
+    let targets = target_ids.iter().map(|id| id.to_raw()).collect::<Vec<_>>();
+    let mut ws_map: HashMap<entity::WorkspaceIdTypeAlias, entity::Workspace> = workspaces
+        .into_iter()
+        .map(|ws| (ws.id.to_raw(), ws))
+        .collect();
+
+    // 存在しない workspace 宛も送信履歴に残すため, 全ての target を outbox に登録する
+    let (message_id, deliveries) = outbox
+        .enqueue(&message, &target_ids, outbox::lease(config))
+        .await?;
+    let mut deliveries: HashMap<entity::WorkspaceIdTypeAlias, entity::Delivery> = deliveries
+        .into_iter()
+        .map(|d| (d.workspace_id.to_raw(), d))
+        .collect();
+
+    let jobs = targets
+        .into_iter()
+        .map(|id| (id, ws_map.remove(&id), deliveries.remove(&id)))
+        .collect::<Vec<_>>();
+
+    let (repo, outbox) = (repo.as_ref(), outbox.as_ref());
+    let results = stream::iter(jobs)
+        .map(|(id, ws, delivery)| async move {
+            let (status, remote) = match (ws, delivery.as_ref()) {
+                (Some(ws), Some(delivery)) => send_to_workspace(ws, delivery, config).await,
+                _ => (DeliveryStatus::UnknownWorkspace, RemoteMessages::default()),
+            };
+            let will_retry = match delivery {
+                Some(delivery) => {
+                    outbox::record(repo, outbox, config, &delivery, &status, &remote).await
+                }
+                None => false,
+            };
+            if !status.is_sent() {
+                tracing::warn!("failed to send to workspace {}: {:?}", id, status);
+            }
+            DeliveryResult {
+                target: id,
+                status,
+                will_retry,
+            }
+        })
+        .buffered(config.concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await;
+
+    Ok(MessageResponse {
+        message_id: message_id.to_raw(),
+        results,
+    })
+}
+
+/// 送信結果と, 送信先に投稿した message の参照を返す.
+/// 前回までの試行で投稿済みの部分は送らずに, その続きから送る.
+/// `parent` がある場合は親 message の thread に返信する.
+/// `profile` の未設定の項目は workspace の設定を使う.
+pub async fn send_to_workspace(
+    mut ws: entity::Workspace,
+    delivery: &entity::Delivery,
+    config: &DeliveryConfig,
+) -> (DeliveryStatus, RemoteMessages) {
+    tracing::info!("send to webhook");
+    ws.profile = delivery.profile.clone().or(ws.profile);
+    let layout = delivery.layout.as_ref();
+    let (text, layout) = with_layout(&ws, &delivery.text, layout);
+    let mut sent = RemoteMessages::from_value(delivery.remote.clone());
+    let send = send_with_attachments(&ws, delivery, &text, layout, config, &mut sent);
+    // 再試行を含めた送信全体のタイムアウト. タイムアウトまでに投稿できた部分の参照は残す
+    let status = match tokio::time::timeout(config.timeout, send).await {
+        Ok(Ok(())) => DeliveryStatus::Sent,
+        Ok(Err(e)) => DeliveryStatus::from_error(&e),
+        Err(_) => DeliveryStatus::from_error(&MessageError::Timeout(config.timeout).into()),
+    };
+    (status, sent)
+}
+
+// 返信先の親 message
+#[derive(Debug, Clone, Copy)]
+struct ReplyTo<'a> {
+    text: &'a str,
+    // 同じ送信先に送った親 message の最初の chunk. ない場合は親 message を引用して送る
+    remote: Option<&'a RemoteMessage>,
+}
+
+async fn send_with_attachments(
+    ws: &entity::Workspace,
+    delivery: &entity::Delivery,
+    text: &str,
+    layout: Option<&entity::Layout>,
+    config: &DeliveryConfig,
+    sent: &mut RemoteMessages,
+) -> Result<()> {
+    let (parent, attachments) = (delivery.parent.as_ref(), delivery.attachments.as_slice());
+    // 再送しても同じ投稿として扱われるよう, idempotency key は delivery ごとに固定する
+    let key = format!("delivery-{}", delivery.id);
+    let parent_remote = parent.map(|p| RemoteMessages::from_value(p.remote.clone()));
+    let reply_to = parent.map(|p| ReplyTo {
+        text: &p.text,
+        remote: parent_remote.as_ref().and_then(RemoteMessages::first),
+    });
+    if attachments.is_empty() {
+        return send_or_reply(ws, text, reply_to, layout, config, &key, &mut sent.chunks).await;
+    }
+    // 親 message の thread に返信できない場合は, 引用した text に link を加えて送る
+    let posting = match reply_to {
+        Some(ReplyTo { remote: None, .. }) => None,
+        _ => get_sender(ws, "", config)?.file_posting(reply_to.and_then(|p| p.remote)),
+    };
+    let per_post = match posting {
+        // 添付に対応していない送信先には, ファイルの link を text に加えて送る
+        None => {
+            let text = attachment_links(text, attachments, config.public_url.as_deref());
+            return send_or_reply(ws, &text, reply_to, layout, config, &key, &mut sent.chunks)
+                .await;
+        }
+        Some(FilePosting::WithText) if fit_text(ws, text, config)?.len() == 1 => {
+            if sent.chunks.is_empty() {
+                let mut sender = get_sender(ws, text, config)?;
+                if let Some(layout) = layout {
+                    sender.set_layout(layout);
+                }
+                let remote = reply_to.and_then(|p| p.remote);
+                sent.chunks
+                    .push(sender.send_files(attachments, remote).await?);
+            }
+            return Ok(());
+        }
+        // 長い text は分けて先に送り, ファイルは text なしで送る
+        Some(FilePosting::WithText) => attachments.len(),
+        Some(FilePosting::Separately(n)) => n.max(1),
+    };
+    if !text.is_empty() {
+        send_or_reply(ws, text, reply_to, layout, config, &key, &mut sent.chunks).await?;
+    }
+    // 送信済みのファイルは送り直さない
+    let sender = get_sender(ws, "", config)?;
+    for files in attachments.chunks(per_post).skip(sent.files.len()) {
+        let remote = sender
+            .send_files(files, reply_to.and_then(|p| p.remote))
+            .await?;
+        sent.files.push(remote);
+    }
+    Ok(())
+}
+
+async fn send_or_reply(
+    ws: &entity::Workspace,
+    text: &str,
+    parent: Option<ReplyTo<'_>>,
+    layout: Option<&entity::Layout>,
+    config: &DeliveryConfig,
+    key: &str,
+    sent: &mut Vec<Option<RemoteMessage>>,
+) -> Result<()> {
+    let Some(parent) = parent else {
+        return send_chunks(ws, text, None, layout, config, key, sent).await;
+    };
+    if let Some(remote) = parent.remote {
+        match send_chunks(ws, text, Some(remote), layout, config, key, sent).await {
+            Err(e) if is_unsupported(&e) => {}
+            res => return res,
+        }
+    }
+    // thread に返信できない送信先には, 親 message を引用して送る
+    let text = quote_reply(parent.text, text);
+    send_chunks(ws, &text, None, layout, config, key, sent).await
+}
+
+/// 送信先の上限に合わせて text を分け, 順に送って参照を `sent` に加える.
+/// layout は最後の chunk と一緒に送る. `sent` にある chunk は投稿済みとして飛ばす.
+/// idempotency key は `key` に chunk の番号を加えたものにする
+async fn send_chunks(
+    ws: &entity::Workspace,
+    text: &str,
+    parent: Option<&RemoteMessage>,
+    layout: Option<&entity::Layout>,
+    config: &DeliveryConfig,
+    key: &str,
+    sent: &mut Vec<Option<RemoteMessage>>,
+) -> Result<()> {
+    let chunks = fit_text(ws, text, config)?;
+    for (i, chunk) in chunks.iter().enumerate().skip(sent.len()) {
+        let mut sender = get_sender(ws, chunk, config)?;
+        sender.set_idempotency_key(&format!("{}-{}", key, i));
+        if let (Some(layout), true) = (layout, i + 1 == chunks.len()) {
+            sender.set_layout(layout);
+        }
+        let previous = match (sender.threads_chunks(), i.checked_sub(1)) {
+            (true, Some(j)) => sent[j].clone(),
+            _ => None,
+        };
+        let remote = match previous.as_ref().or(parent) {
+            Some(parent) => sender.reply(parent).await?,
+            None => sender.send().await?,
         };
+        sent.push(remote);
+    }
+    Ok(())
+}
 
-        reqwest::Client::new()
-            .post(&self.webhook_url)
-            .json(&payload)
-            .send()
-            .await
-            .map_err(|e| {
-                MessageError::Unexpected(format!("Failed to send message to webhook: {}", e))
-            })?;
-        Ok(())
+/// text とは別に layout を送れる送信先か
+pub fn supports_layout(ws_type: &WorkspaceType) -> bool {
+    matches!(
+        ws_type,
+        WorkspaceType::Slack | WorkspaceType::SlackBot | WorkspaceType::Discord
+    )
+}
+
+/// layout に対応していない送信先では, layout を text に加えて layout なしで送る
+fn with_layout<'a>(
+    ws: &entity::Workspace,
+    text: &str,
+    layout: Option<&'a entity::Layout>,
+) -> (String, Option<&'a entity::Layout>) {
+    match layout {
+        Some(layout) if !supports_layout(&ws.ws_type) => (layout_text(text, layout), None),
+        layout => (text.to_string(), layout),
+    }
+}
+
+/// layout を CommonMark にして text の後に加える
+pub fn layout_text(text: &str, layout: &entity::Layout) -> String {
+    let mut lines = vec![];
+    if let Some(title) = &layout.title {
+        lines.push(format!("**{}**", markup::escape(title)));
+    }
+    for field in layout.fields.iter() {
+        lines.push(format!(
+            "**{}**: {}",
+            markup::escape(&field.name),
+            markup::escape(&field.value)
+        ));
+    }
+    if !layout.buttons.is_empty() {
+        let buttons = layout
+            .buttons
+            .iter()
+            .map(|b| {
+                format!(
+                    "[{}]({})",
+                    markup::escape(&b.label),
+                    markup::escape_link_url(&b.url)
+                )
+            })
+            .collect::<Vec<_>>();
+        lines.push(buttons.join(" | "));
+    }
+    if let Some(image_url) = &layout.image_url {
+        lines.push(format!("<{}>", image_url));
+    }
+    if let Some(footer) = &layout.footer {
+        lines.push(markup::escape(footer));
+    }
+    std::iter::once(text.to_string())
+        .chain(std::iter::once(lines.join("\n")))
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+fn is_unsupported(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<MessageError>(),
+        Some(MessageError::UnsupportedOperation(_))
+    )
+}
+
+/// 添付ファイルの link を text の後に加える. `public_url` が未設定の場合は file 名だけを加える
+pub fn attachment_links(
+    text: &str,
+    attachments: &[entity::Attachment],
+    public_url: Option<&str>,
+) -> String {
+    let links = attachments.iter().map(|a| match public_url {
+        Some(url) => format!(
+            "📎 {} {}/attachments/{}",
+            a.filename,
+            url.trim_end_matches('/'),
+            a.key
+        ),
+        None => format!("📎 {}", a.filename),
+    });
+    std::iter::once(text.to_string())
+        .filter(|text| !text.is_empty())
+        .chain(links)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// 1 ファイルあたりのサイズの上限を確認する
+fn check_file_size(file: &entity::Attachment, max_bytes: usize) -> Result<(), MessageError> {
+    if file.data.len() > max_bytes {
+        return Err(MessageError::AttachmentLimit(format!(
+            "{} is {} bytes (max {} bytes)",
+            file.filename,
+            file.data.len(),
+            max_bytes
+        )));
     }
+    Ok(())
+}
+
+// multipart/form-data のファイルの part
+fn file_part(file: &entity::Attachment) -> reqwest::multipart::Part {
+    let part = || {
+        let body = reqwest::Body::from(file.data.clone());
+        reqwest::multipart::Part::stream_with_length(body, file.data.len() as u64)
+            .file_name(file.filename.clone())
+    };
+    part()
+        .mime_str(&file.content_type)
+        .unwrap_or_else(|_| part())
+}
+
+/// 親 message を引用した返信の text を作る
+pub fn quote_reply(parent: &str, text: &str) -> String {
+    const QUOTE_MAX_GRAPHEMES: usize = 100;
+    let quoted = truncate_graphemes(parent, QUOTE_MAX_GRAPHEMES)
+        .lines()
+        .map(|line| format!("> {}", line))
+        .collect::<Vec<_>>()
+        .join("\n");
+    // 空行を空けないと, CommonMark では続く text も引用の一部になる
+    format!("{}\n\n{}", quoted, text)
 }
 
+/////////////////////
+// Edit and delete //
+/////////////////////
+
+// 送信済みの message の編集・削除の target ごとの結果
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub struct DiscordMessagePayload {
-    pub content: String,
-    pub username: String, // "pollenJP Webhook"
-    pub avatar_url: String,
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum RemoteOperationStatus {
+    // 送信先に反映した
+    Done,
+    // 再送待ち. 編集後の text で送られる
+    Pending,
+    // 再送待ちの送信を取り消した
+    Cancelled,
+    // 送信に失敗していて, 送信先に message がない
+    NotDelivered,
+    // 送信先が編集・削除に対応していないか, message の参照が残っていない
+    Unsupported,
+    Failed { result: DeliveryStatus },
 }
 
-impl DiscordMessagePayload {
-    pub fn new(text: &str) -> Self {
-        Self {
-            content: text.to_string(),
-            // TODO: fix hard coded
-            username: "pollenJP Webhook".to_string(),
-            // TODO: fix hard coded
-            avatar_url: "https://i.gyazo.com/e5b2988a9f6f05cafa4ec384fae1a3dd.png".to_string(),
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteOperationResult {
+    pub target: entity::WorkspaceIdTypeAlias,
+    #[serde(flatten)]
+    pub status: RemoteOperationStatus,
+}
+
+// PATCH, DELETE /messages/:id の response body
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteOperationResponse {
+    pub message_id: entity::MessageIdTypeAlias,
+    pub results: Vec<RemoteOperationResult>,
+}
+
+impl RemoteOperationResponse {
+    /// 失敗なし: 200, 一部失敗: 207, 全て失敗: 502
+    pub fn status_code(&self) -> StatusCode {
+        let failed = self
+            .results
+            .iter()
+            .filter(|r| matches!(r.status, RemoteOperationStatus::Failed { .. }))
+            .count();
+        if failed == 0 {
+            StatusCode::OK
+        } else if failed < self.results.len() {
+            StatusCode::MULTI_STATUS
+        } else {
+            StatusCode::BAD_GATEWAY
         }
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct DiscordSender {
-    webhook_url: String,
-    text: String,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemoteOperation {
+    Edit,
+    Delete,
 }
 
-impl DiscordSender {
-    pub fn new(webhook_url: &str, text: &str) -> Self {
+/// message の text を置き換え, 送信済みの target にも編集を反映する
+pub async fn edit_message<T, M>(
+    repo: Arc<T>,
+    outbox: Arc<M>,
+    config: &DeliveryConfig,
+    id: entity::MessageId,
+    text: &str,
+) -> Result<RemoteOperationResponse>
+where
+    T: WorkspaceRepository,
+    M: MessageRepository,
+{
+    outbox.update_text(&id, text).await?;
+    let message = outbox.find(id).await?;
+    let operation = RemoteOperation::Edit;
+    Ok(apply_remote_operation(repo.as_ref(), outbox.as_ref(), config, message, operation).await)
+}
+
+/// message を削除済みにし, 送信済みの target からも削除する
+pub async fn delete_message<T, M>(
+    repo: Arc<T>,
+    outbox: Arc<M>,
+    config: &DeliveryConfig,
+    id: entity::MessageId,
+) -> Result<RemoteOperationResponse>
+where
+    T: WorkspaceRepository,
+    M: MessageRepository,
+{
+    let cancelled = serde_json::to_value(DeliveryStatus::Cancelled)?;
+    outbox.mark_deleted(&id, &cancelled).await?;
+    // 削除した後の状態を取得する. 削除より先に送信済みになった delivery はここで削除し,
+    // 後に送信を終えた delivery は outbox::record が削除する
+    let message = outbox.find(id).await?;
+    let operation = RemoteOperation::Delete;
+    Ok(apply_remote_operation(repo.as_ref(), outbox.as_ref(), config, message, operation).await)
+}
+
+async fn apply_remote_operation<T, M>(
+    repo: &T,
+    outbox: &M,
+    config: &DeliveryConfig,
+    message: entity::Message,
+    operation: RemoteOperation,
+) -> RemoteOperationResponse
+where
+    T: WorkspaceRepository,
+    M: MessageRepository,
+{
+    let (text, layout) = (message.text.as_str(), message.layout.as_ref());
+    let message_id = &message.id;
+    let results = stream::iter(message.deliveries.clone())
+        .map(|d| async move {
+            let status = match d.state {
+                entity::DeliveryState::Pending => match operation {
+                    RemoteOperation::Edit => RemoteOperationStatus::Pending,
+                    RemoteOperation::Delete => RemoteOperationStatus::Cancelled,
+                },
+                entity::DeliveryState::Failed if is_cancelled(&d) => {
+                    RemoteOperationStatus::Cancelled
+                }
+                entity::DeliveryState::Failed => RemoteOperationStatus::NotDelivered,
+                entity::DeliveryState::Sent => {
+                    let mut remote = RemoteMessages::from_value(d.remote.clone());
+                    if remote.is_complete() {
+                        let (content, ws_id) = ((text, layout), &d.workspace_id);
+                        let status = apply_to_workspace(
+                            repo,
+                            config,
+                            ws_id,
+                            content,
+                            &mut remote,
+                            operation,
+                        )
+                        .await;
+                        save_remote(outbox, message_id, ws_id, &remote).await;
+                        status
+                    } else {
+                        RemoteOperationStatus::Unsupported
+                    }
+                }
+            };
+            RemoteOperationResult {
+                target: d.workspace_id.to_raw(),
+                status,
+            }
+        })
+        .buffered(config.concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await;
+
+    RemoteOperationResponse {
+        message_id: message.id.to_raw(),
+        results,
+    }
+}
+
+/// 編集・削除で変わった送信先の message の参照を保存する
+pub async fn save_remote<M>(
+    outbox: &M,
+    message_id: &entity::MessageId,
+    workspace_id: &entity::WorkspaceId,
+    remote: &RemoteMessages,
+) where
+    M: MessageRepository,
+{
+    let res = match serde_json::to_value(remote) {
+        Ok(value) => {
+            outbox
+                .update_remote(message_id, workspace_id, Some(&value))
+                .await
+        }
+        Err(e) => Err(e.into()),
+    };
+    if let Err(e) = res {
+        tracing::error!(
+            "failed to save message references of message {} on workspace {}: {}",
+            message_id,
+            workspace_id,
+            e
+        );
+    }
+}
+
+// 削除によって取り消された delivery
+fn is_cancelled(delivery: &entity::DeliveryRecord) -> bool {
+    delivery
+        .result
+        .clone()
+        .and_then(|r| serde_json::from_value::<DeliveryStatus>(r).ok())
+        .is_some_and(|status| status == DeliveryStatus::Cancelled)
+}
+
+/// 送信先の message に編集・削除を反映し, 削除した chunk を `remote` から外す
+pub async fn apply_to_workspace<T>(
+    repo: &T,
+    config: &DeliveryConfig,
+    workspace_id: &entity::WorkspaceId,
+    (text, layout): (&str, Option<&entity::Layout>),
+    remote: &mut RemoteMessages,
+    operation: RemoteOperation,
+) -> RemoteOperationStatus
+where
+    T: WorkspaceRepository,
+{
+    let failed = |e: &anyhow::Error| RemoteOperationStatus::Failed {
+        result: DeliveryStatus::from_error(e),
+    };
+    let ws = match repo.find(workspace_id.clone()).await {
+        Ok(ws) => ws,
+        Err(e) => {
+            return match e.downcast_ref::<RepositoryError>() {
+                Some(RepositoryError::NotFound(_)) => RemoteOperationStatus::Failed {
+                    result: DeliveryStatus::UnknownWorkspace,
+                },
+                _ => failed(&e),
+            }
+        }
+    };
+    let (text, layout) = with_layout(&ws, text, layout);
+    let res = match operation {
+        RemoteOperation::Edit => {
+            let edit = edit_chunks(&ws, &text, layout, remote, config);
+            tokio::time::timeout(config.timeout, edit).await
+        }
+        RemoteOperation::Delete => {
+            tokio::time::timeout(config.timeout, delete_chunks(&ws, remote, config)).await
+        }
+    };
+    match res {
+        Ok(Ok(())) => RemoteOperationStatus::Done,
+        Ok(Err(e)) => match e.downcast_ref::<MessageError>() {
+            Some(MessageError::UnsupportedOperation(_)) => RemoteOperationStatus::Unsupported,
+            _ => {
+                tracing::warn!(
+                    "failed to {:?} message on workspace {}: {}",
+                    operation,
+                    workspace_id,
+                    e
+                );
+                failed(&e)
+            }
+        },
+        Err(_) => failed(&MessageError::Timeout(config.timeout).into()),
+    }
+}
+
+/// 送信済みの chunk を新しい text の chunk に順に置き換え, 余った chunk は削除する.
+/// 添付ファイルだけを送った message はそのままにする
+async fn edit_chunks(
+    ws: &entity::Workspace,
+    text: &str,
+    layout: Option<&entity::Layout>,
+    remote: &mut RemoteMessages,
+    config: &DeliveryConfig,
+) -> Result<()> {
+    let chunks = fit_text(ws, text, config)?;
+    // 後から投稿すると順序が崩れるので, 送信済みの chunk の数より多くは分けられない
+    if chunks.len() > remote.chunks.len() {
+        return Err(MessageError::TooLong(format!(
+            "the edited text needs {} messages on {}, but {} were sent",
+            chunks.len(),
+            ws.ws_type,
+            remote.chunks.len()
+        ))
+        .into());
+    }
+    for (i, (chunk, r)) in chunks.iter().zip(remote.chunks.iter()).enumerate() {
+        let mut sender = get_sender(ws, chunk, config)?;
+        if let (Some(layout), true) = (layout, i + 1 == chunks.len()) {
+            sender.set_layout(layout);
+        }
+        if let Some(r) = r {
+            sender.edit(r).await?;
+        }
+    }
+    let sender = get_sender(ws, "", config)?;
+    while remote.chunks.len() > chunks.len() {
+        if let Some(Some(r)) = remote.chunks.last() {
+            sender.delete(r).await?;
+        }
+        remote.chunks.pop();
+    }
+    Ok(())
+}
+
+/// 送信済みの message を後ろから削除する. 途中で失敗しても, 削除できた分は `remote` から外す
+async fn delete_chunks(
+    ws: &entity::Workspace,
+    remote: &mut RemoteMessages,
+    config: &DeliveryConfig,
+) -> Result<()> {
+    let sender = get_sender(ws, "", config)?;
+    for sent in [&mut remote.files, &mut remote.chunks] {
+        while let Some(last) = sent.last() {
+            if let Some(r) = last {
+                sender.delete(r).await?;
+            }
+            sent.pop();
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlackMessagePayload {
+    pub text: String,
+    // username と icon_url は legacy な incoming webhook でのみ反映される
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon_url: Option<String>,
+    #[serde(flatten)]
+    pub layout: SlackLayout,
+}
+
+impl SlackMessagePayload {
+    pub fn new(text: &str, profile: &entity::Profile) -> Self {
         Self {
-            webhook_url: webhook_url.to_string(),
             text: text.to_string(),
+            username: profile.display_name.clone(),
+            icon_url: profile.avatar_url.clone(),
+            layout: SlackLayout::default(),
         }
     }
 }
 
-#[async_trait]
-impl Sender for DiscordSender {
-    async fn send(&self) -> Result<()> {
-        tracing::info!("send to discord webhook");
-        let payload = DiscordMessagePayload::new(&self.text);
+// layout を Block Kit にしたもの. blocks がある場合, text は通知にだけ使われる
+// https://api.slack.com/reference/block-kit/blocks
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct SlackLayout {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blocks: Vec<serde_json::Value>,
+    // color は attachments でしか付けられないので, color がある場合は layout を attachments に入れる
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<serde_json::Value>,
+}
 
-        reqwest::Client::new()
-            .post(&self.webhook_url)
-            .json(&payload)
-            .send()
-            .await
-            .map_err(|e| {
-                MessageError::Unexpected(format!("Failed to send message to webhook: {}", e))
-            })?;
-        Ok(())
+impl SlackLayout {
+    // section block の text の文字数の上限
+    const SECTION_MAX_CHARS: usize = 3000;
+
+    pub fn new(text: &str, layout: Option<&entity::Layout>) -> Self {
+        let Some(layout) = layout else {
+            return Self::default();
+        };
+        let mut blocks = vec![];
+        if !text.is_empty() {
+            for chunk in markup::split_text(text, Self::SECTION_MAX_CHARS) {
+                blocks.push(serde_json::json!({
+                    "type": "section",
+                    "text": {"type": "mrkdwn", "text": chunk},
+                }));
+            }
+        }
+
+        let mut layout_blocks = vec![];
+        if let Some(title) = &layout.title {
+            layout_blocks.push(serde_json::json!({
+                "type": "header",
+                "text": {"type": "plain_text", "text": title},
+            }));
+        }
+        if !layout.fields.is_empty() {
+            let fields = layout
+                .fields
+                .iter()
+                .map(|f| {
+                    let text = format!("*{}*\n{}", escape_html(&f.name), escape_html(&f.value));
+                    serde_json::json!({"type": "mrkdwn", "text": text})
+                })
+                .collect::<Vec<_>>();
+            layout_blocks.push(serde_json::json!({"type": "section", "fields": fields}));
+        }
+        if let Some(image_url) = &layout.image_url {
+            layout_blocks.push(serde_json::json!({
+                "type": "image",
+                "image_url": image_url,
+                "alt_text": layout.title.as_deref().unwrap_or("image"),
+            }));
+        }
+        if !layout.buttons.is_empty() {
+            let buttons = layout
+                .buttons
+                .iter()
+                .map(|b| {
+                    serde_json::json!({
+                        "type": "button",
+                        "text": {"type": "plain_text", "text": b.label},
+                        "url": b.url,
+                    })
+                })
+                .collect::<Vec<_>>();
+            layout_blocks.push(serde_json::json!({"type": "actions", "elements": buttons}));
+        }
+        if let Some(footer) = &layout.footer {
+            layout_blocks.push(serde_json::json!({
+                "type": "context",
+                "elements": [{"type": "mrkdwn", "text": escape_html(footer)}],
+            }));
+        }
+
+        match &layout.color {
+            Some(color) => Self {
+                blocks,
+                attachments: vec![serde_json::json!({"color": color, "blocks": layout_blocks})],
+            },
+            None => {
+                blocks.extend(layout_blocks);
+                Self {
+                    blocks,
+                    attachments: vec![],
+                }
+            }
+        }
+    }
+}
+
+// 送信先の platform が返した message の参照. 送信済みの message の編集・削除に使う
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RemoteMessage {
+    Discord {
+        id: String,
+        // forum channel の場合に message を作成した thread
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        thread_id: Option<String>,
+    },
+    SlackBot {
+        channel: String,
+        ts: String,
+    },
+    Telegram {
+        chat_id: i64,
+        message_id: i64,
+    },
+    Matrix {
+        event_id: String,
+    },
+    Zulip {
+        id: i64,
+    },
+    Mastodon {
+        id: String,
+    },
+    Misskey {
+        id: String,
+    },
+    Bluesky {
+        // at://{did}/app.bsky.feed.post/{rkey}
+        uri: String,
+        cid: String,
+    },
+}
+
+/// 1 つの delivery で送信先に投稿した message の参照. 送信先が参照を返さない場合は None になる.
+/// 途中で送信に失敗した場合は, 再送で投稿済みの部分を飛ばすのにも使う
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct RemoteMessages {
+    // 分けて送った text の chunk の順の参照
+    #[serde(default)]
+    pub chunks: Vec<Option<RemoteMessage>>,
+    // 長い text と別に送った添付ファイル (または添付の link) の参照
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<Option<RemoteMessage>>,
+}
+
+impl RemoteMessages {
+    /// deliveries.remote の JSON から作る. 読めない場合は投稿済みの部分がないものとする
+    pub fn from_value(value: Option<serde_json::Value>) -> Self {
+        let Some(value) = value else {
+            return Self::default();
+        };
+        serde_json::from_value(value).unwrap_or_else(|e| {
+            tracing::warn!("invalid message references: {}", e);
+            Self::default()
+        })
+    }
+
+    /// thread の返信先にする最初の chunk
+    pub fn first(&self) -> Option<&RemoteMessage> {
+        self.chunks.first()?.as_ref()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty() && self.files.is_empty()
+    }
+
+    /// 投稿した全ての message の参照が残っていて, 編集・削除できるか
+    pub fn is_complete(&self) -> bool {
+        !self.is_empty() && self.chunks.iter().chain(&self.files).all(Option::is_some)
+    }
+}
+
+// 送信先が添付ファイルをどう投稿するか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilePosting {
+    // text と全てのファイルを 1 つの message で送る
+    WithText,
+    // text は編集できるように別の message で先に送り, ファイルは n 個ずつ text なしで送る.
+    // 途中で失敗した場合の再送は, 送信済みのファイルを飛ばす
+    Separately(usize),
+}
+
+#[async_trait]
+pub trait Sender: Send + Sync {
+    /// 送信し, 送信先が message の参照を返した場合はそれを返す
+    async fn send(&self) -> Result<Option<RemoteMessage>>;
+
+    /// `parent` の thread に返信として送信する
+    async fn reply(&self, _parent: &RemoteMessage) -> Result<Option<RemoteMessage>> {
+        Err(MessageError::UnsupportedOperation("reply".to_string()).into())
+    }
+
+    /// 添付ファイルを `file_posting` に従って送信する. `parent` がある場合はその thread に返信する
+    async fn send_files(
+        &self,
+        _files: &[entity::Attachment],
+        _parent: Option<&RemoteMessage>,
+    ) -> Result<Option<RemoteMessage>> {
+        Err(MessageError::UnsupportedOperation("attachments".to_string()).into())
+    }
+
+    /// 送信済みの `remote` を Sender の text に置き換える
+    async fn edit(&self, _remote: &RemoteMessage) -> Result<()> {
+        Err(MessageError::UnsupportedOperation("edit".to_string()).into())
+    }
+
+    /// 送信済みの `remote` を削除する
+    async fn delete(&self, _remote: &RemoteMessage) -> Result<()> {
+        Err(MessageError::UnsupportedOperation("delete".to_string()).into())
+    }
+
+    /// `parent` に返信する場合を含めた添付ファイルの送り方. 添付に対応していない場合は None
+    fn file_posting(&self, _parent: Option<&RemoteMessage>) -> Option<FilePosting> {
+        None
+    }
+
+    /// text が送信先の 1 回の投稿の上限を超える場合に, その上限の文字数を返す
+    fn exceeds_limit(&self) -> Option<usize> {
+        None
+    }
+
+    /// 上限を超える text を分けずに切り詰めて送る場合に true.
+    /// workspace の long_text が reject の場合は切り詰めずに `MessageError::TooLong` になる
+    fn truncates_long_text(&self) -> bool {
+        false
+    }
+
+    /// 分けて送る chunk を, 前の chunk への返信として thread にする場合に true
+    fn threads_chunks(&self) -> bool {
+        false
+    }
+
+    /// 再送しても同じ投稿として扱われるよう, 送信先に渡す idempotency key を `key` にする.
+    /// 対応していない送信先は何もしない
+    fn set_idempotency_key(&mut self, _key: &str) {}
+
+    /// text と一緒に `layout` を送るようにする. 対応していない送信先は何もしない
+    fn set_layout(&mut self, _layout: &entity::Layout) {}
+}
+
+fn over_limit(text: &str, max_chars: usize) -> Option<usize> {
+    (text.chars().count() > max_chars).then_some(max_chars)
+}
+
+// 別の WorkspaceType で送信した message の参照を受け取った場合
+fn remote_mismatch(remote: &RemoteMessage) -> anyhow::Error {
+    MessageError::UnsupportedOperation(format!("message reference {:?}", remote)).into()
+}
+
+#[derive(Debug, Clone)]
+pub struct SlackSender {
+    webhook_url: String,
+    profile: entity::Profile,
+    text: String,
+    layout: Option<entity::Layout>,
+    retry: RetryPolicy,
+}
+
+impl SlackSender {
+    // これより長い text は Slack が切り詰める
+    const MAX_CHARS: usize = 40_000;
+
+    pub fn new(
+        webhook_url: &str,
+        profile: &entity::Profile,
+        text: &str,
+        retry: &RetryPolicy,
+    ) -> Self {
+        Self {
+            webhook_url: webhook_url.to_string(),
+            profile: profile.clone(),
+            text: text.to_string(),
+            layout: None,
+            retry: retry.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Sender for SlackSender {
+    fn exceeds_limit(&self) -> Option<usize> {
+        over_limit(&self.text, Self::MAX_CHARS)
+    }
+
+    fn set_layout(&mut self, layout: &entity::Layout) {
+        self.layout = Some(layout.clone());
+    }
+
+    async fn send(&self) -> Result<Option<RemoteMessage>> {
+        tracing::info!("send to slack webhook");
+        let payload = SlackMessagePayload {
+            layout: SlackLayout::new(&self.text, self.layout.as_ref()),
+            ..SlackMessagePayload::new(&self.text, &self.profile)
+        };
+
+        let client = reqwest::Client::new();
+        send_with_retry(&self.retry, || {
+            client.post(&self.webhook_url).json(&payload)
+        })
+        .await?;
+        Ok(None)
+    }
+}
+
+// https://api.slack.com/methods/chat.postMessage
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlackBotConfig {
+    // xoxb-...
+    pub bot_token: String,
+    // channel id (e.g. C0123456789)
+    pub channel: String,
+    // username と icon_emoji には chat:write.customize scope が必要.
+    // workspace の display_name と avatar_url が設定されている場合はそちらを使う
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub icon_emoji: Option<String>,
+    #[serde(default = "SlackBotConfig::default_api_url")]
+    pub api_url: String,
+}
+
+impl SlackBotConfig {
+    fn default_api_url() -> String {
+        "https://slack.com/api".to_string()
+    }
+
+    fn method_url(&self, method: &str) -> String {
+        format!("{}/{}", self.api_url.trim_end_matches('/'), method)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlackPostMessagePayload {
+    pub channel: String,
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon_emoji: Option<String>,
+    // thread の返信の場合の親 message の ts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread_ts: Option<String>,
+    #[serde(flatten)]
+    pub layout: SlackLayout,
+}
+
+impl SlackPostMessagePayload {
+    pub fn new(config: &SlackBotConfig, profile: &entity::Profile, text: &str) -> Self {
+        // icon_emoji は icon_url より優先されるため, avatar_url がある場合は送らない
+        let icon_emoji = match profile.avatar_url {
+            Some(_) => None,
+            None => config.icon_emoji.clone(),
+        };
+        Self {
+            channel: config.channel.clone(),
+            text: text.to_string(),
+            username: profile
+                .display_name
+                .clone()
+                .or_else(|| config.username.clone()),
+            icon_url: profile.avatar_url.clone(),
+            icon_emoji,
+            thread_ts: None,
+            layout: SlackLayout::default(),
+        }
+    }
+}
+
+// Slack の Web API は失敗時も 200 を返し, body の ok と error で結果を返す
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SlackApiResponse {
+    pub ok: bool,
+    pub error: Option<String>,
+    // 投稿した message の timestamp. 編集や thread の返信に使う
+    pub ts: Option<String>,
+    pub channel: Option<String>,
+    // files.getUploadURLExternal の結果
+    pub upload_url: Option<String>,
+    pub file_id: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SlackBotSender {
+    config: SlackBotConfig,
+    profile: entity::Profile,
+    text: String,
+    layout: Option<entity::Layout>,
+    retry: RetryPolicy,
+}
+
+impl SlackBotSender {
+    // これより長い text は Slack が切り詰める
+    const MAX_CHARS: usize = 40_000;
+
+    pub fn new(
+        config: SlackBotConfig,
+        profile: &entity::Profile,
+        text: &str,
+        retry: &RetryPolicy,
+    ) -> Self {
+        Self {
+            config,
+            profile: profile.clone(),
+            text: text.to_string(),
+            layout: None,
+            retry: retry.clone(),
+        }
+    }
+
+    fn payload(&self) -> SlackPostMessagePayload {
+        SlackPostMessagePayload {
+            layout: SlackLayout::new(&self.text, self.layout.as_ref()),
+            ..SlackPostMessagePayload::new(&self.config, &self.profile, &self.text)
+        }
+    }
+
+    // 1 ファイルあたりのサイズの上限
+    const MAX_FILE_BYTES: usize = 1024 * 1024 * 1024;
+
+    async fn call<P: Serialize + Sync>(
+        &self,
+        method: &str,
+        payload: &P,
+    ) -> Result<SlackApiResponse> {
+        self.call_with(method, |req| req.json(payload)).await
+    }
+
+    // `body` で body を設定して Web API を呼ぶ
+    async fn call_with<F>(&self, method: &str, body: F) -> Result<SlackApiResponse>
+    where
+        F: Fn(reqwest::RequestBuilder) -> reqwest::RequestBuilder + Send + Sync,
+    {
+        let url = self.config.method_url(method);
+        let client = reqwest::Client::new();
+        let res = send_with_retry(&self.retry, || {
+            body(client.post(&url).bearer_auth(&self.config.bot_token))
+        })
+        .await?;
+        let body = res
+            .json::<SlackApiResponse>()
+            .await
+            .map_err(|e| MessageError::Transport(e.to_string()))?;
+        if !body.ok {
+            return Err(MessageError::Api(body.error.unwrap_or_default()).into());
+        }
+        Ok(body)
+    }
+}
+
+#[async_trait]
+impl Sender for SlackBotSender {
+    fn exceeds_limit(&self) -> Option<usize> {
+        over_limit(&self.text, Self::MAX_CHARS)
+    }
+
+    fn set_layout(&mut self, layout: &entity::Layout) {
+        self.layout = Some(layout.clone());
+    }
+
+    async fn send(&self) -> Result<Option<RemoteMessage>> {
+        tracing::info!("send to slack bot");
+        let payload = self.payload();
+        let body = self.call("chat.postMessage", &payload).await?;
+        Ok(body.ts.map(|ts| RemoteMessage::SlackBot {
+            channel: body.channel.unwrap_or_else(|| self.config.channel.clone()),
+            ts,
+        }))
+    }
+
+    async fn reply(&self, parent: &RemoteMessage) -> Result<Option<RemoteMessage>> {
+        let RemoteMessage::SlackBot { channel, ts } = parent else {
+            return Err(remote_mismatch(parent));
+        };
+        tracing::info!("reply to slack thread ts={}", ts);
+        let payload = SlackPostMessagePayload {
+            channel: channel.clone(),
+            thread_ts: Some(ts.clone()),
+            ..self.payload()
+        };
+        let body = self.call("chat.postMessage", &payload).await?;
+        Ok(body.ts.map(|ts| RemoteMessage::SlackBot {
+            channel: channel.clone(),
+            ts,
+        }))
+    }
+
+    // text は編集・削除できるように chat.postMessage で別に送り, ファイルはまとめて 1 回で共有する
+    fn file_posting(&self, parent: Option<&RemoteMessage>) -> Option<FilePosting> {
+        match parent {
+            None | Some(RemoteMessage::SlackBot { .. }) => {
+                Some(FilePosting::Separately(usize::MAX))
+            }
+            Some(_) => None,
+        }
+    }
+
+    // files.uploadV2 と同じく, ファイルごとに upload 先の URL を取得して upload してから channel に共有する.
+    // 共有するまでは channel に出ないので, 途中で失敗した再送で重複しない
+    // https://api.slack.com/messaging/files#uploading_files
+    async fn send_files(
+        &self,
+        files: &[entity::Attachment],
+        parent: Option<&RemoteMessage>,
+    ) -> Result<Option<RemoteMessage>> {
+        let (channel, thread_ts) = match parent {
+            None => (self.config.channel.clone(), None),
+            Some(RemoteMessage::SlackBot { channel, ts }) => (channel.clone(), Some(ts.clone())),
+            Some(parent) => return Err(remote_mismatch(parent)),
+        };
+        for file in files {
+            check_file_size(file, Self::MAX_FILE_BYTES)?;
+        }
+        tracing::info!("send {} files to slack bot", files.len());
+
+        let client = reqwest::Client::new();
+        let mut uploaded = vec![];
+        for file in files {
+            let length = file.data.len().to_string();
+            let body = self
+                .call_with("files.getUploadURLExternal", |req| {
+                    req.form(&[("filename", file.filename.as_str()), ("length", &length)])
+                })
+                .await?;
+            let (Some(upload_url), Some(file_id)) = (body.upload_url, body.file_id) else {
+                return Err(MessageError::Api("upload_url is not returned".to_string()).into());
+            };
+            send_with_retry(&self.retry, || {
+                client.post(&upload_url).body(file.data.clone())
+            })
+            .await?;
+            uploaded.push(serde_json::json!({"id": file_id, "title": file.filename}));
+        }
+        let mut payload = serde_json::json!({"files": uploaded, "channel_id": channel});
+        if let Some(ts) = thread_ts {
+            payload["thread_ts"] = ts.into();
+        }
+        // 共有したファイルの message の ts は返らない
+        self.call("files.completeUploadExternal", &payload).await?;
+        Ok(None)
+    }
+
+    async fn edit(&self, remote: &RemoteMessage) -> Result<()> {
+        let RemoteMessage::SlackBot { channel, ts } = remote else {
+            return Err(remote_mismatch(remote));
+        };
+        tracing::info!("edit slack message ts={}", ts);
+        let mut payload = serde_json::json!({"channel": channel, "ts": ts, "text": self.text});
+        if let Some(layout) = &self.layout {
+            let layout = SlackLayout::new(&self.text, Some(layout));
+            payload["blocks"] = serde_json::to_value(layout.blocks)?;
+            payload["attachments"] = serde_json::to_value(layout.attachments)?;
+        }
+        self.call("chat.update", &payload).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, remote: &RemoteMessage) -> Result<()> {
+        let RemoteMessage::SlackBot { channel, ts } = remote else {
+            return Err(remote_mismatch(remote));
+        };
+        tracing::info!("delete slack message ts={}", ts);
+        let payload = serde_json::json!({"channel": channel, "ts": ts});
+        self.call("chat.delete", &payload).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct DiscordConfig {
+    // forum channel の webhook の場合, message ごとに post (thread) を作る
+    #[serde(default)]
+    pub forum: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiscordMessagePayload {
+    pub content: String,
+    // 未設定の場合は webhook の名前とアイコンになる
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avatar_url: Option<String>,
+    // forum channel に作る post の title
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub embeds: Vec<serde_json::Value>,
+}
+
+impl DiscordMessagePayload {
+    pub fn new(text: &str, profile: &entity::Profile) -> Self {
+        Self {
+            content: text.to_string(),
+            username: profile.display_name.clone(),
+            avatar_url: profile.avatar_url.clone(),
+            thread_name: None,
+            embeds: vec![],
+        }
+    }
+}
+
+// webhook_url に thread_id がある場合も, thread_id が 1 つだけになるように置き換える
+fn set_thread_id(url: &mut reqwest::Url, thread_id: &str) {
+    let query = url
+        .query_pairs()
+        .filter(|(key, _)| key != "thread_id")
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect::<Vec<_>>();
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(query)
+        .append_pair("thread_id", thread_id);
+}
+
+/// layout を embed にする. webhook では link の button を送れないので, button は description の link にする
+/// https://discord.com/developers/docs/resources/channel#embed-object
+pub fn discord_embed(layout: &entity::Layout) -> serde_json::Value {
+    let mut embed = serde_json::json!({});
+    if let Some(title) = &layout.title {
+        embed["title"] = title.as_str().into();
+    }
+    if let Some(color) = &layout.color {
+        // validation で #RRGGBB であることを確認している
+        embed["color"] = u32::from_str_radix(color.trim_start_matches('#'), 16)
+            .unwrap_or_default()
+            .into();
+    }
+    if !layout.fields.is_empty() {
+        embed["fields"] = layout
+            .fields
+            .iter()
+            .map(|f| serde_json::json!({"name": f.name, "value": f.value, "inline": true}))
+            .collect();
+    }
+    if !layout.buttons.is_empty() {
+        embed["description"] = layout
+            .buttons
+            .iter()
+            .map(|b| {
+                let link = format!(
+                    "[{}]({})",
+                    markup::escape(&b.label),
+                    markup::escape_link_url(&b.url)
+                );
+                Document::parse(&link).render(Markup::Discord)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+            .into();
+    }
+    if let Some(image_url) = &layout.image_url {
+        embed["image"] = serde_json::json!({"url": image_url});
+    }
+    if let Some(footer) = &layout.footer {
+        embed["footer"] = serde_json::json!({"text": footer});
+    }
+    embed
+}
+
+#[derive(Debug, Clone)]
+pub struct DiscordSender {
+    webhook_url: String,
+    config: DiscordConfig,
+    profile: entity::Profile,
+    text: String,
+    layout: Option<entity::Layout>,
+    retry: RetryPolicy,
+}
+
+// `?wait=true` で送信した場合に返る message
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct DiscordMessage {
+    pub id: String,
+    // forum の post や thread 内の message の場合は thread の id
+    pub channel_id: String,
+}
+
+impl DiscordSender {
+    // content の文字数の上限
+    const MAX_CHARS: usize = 2000;
+    const THREAD_NAME_MAX_CHARS: usize = 100;
+    // 1 message に添付できるファイルの数と合計サイズ (boost されていない server の上限)
+    const MAX_FILES: usize = 10;
+    const MAX_ATTACHMENT_BYTES: usize = 10 * 1024 * 1024;
+
+    pub fn new(
+        webhook_url: &str,
+        config: DiscordConfig,
+        profile: &entity::Profile,
+        text: &str,
+        retry: &RetryPolicy,
+    ) -> Self {
+        Self {
+            webhook_url: webhook_url.to_string(),
+            config,
+            profile: profile.clone(),
+            text: text.to_string(),
+            layout: None,
+            retry: retry.clone(),
+        }
+    }
+
+    // thread への返信の payload
+    fn message_payload(&self) -> DiscordMessagePayload {
+        DiscordMessagePayload {
+            embeds: self.layout.iter().map(discord_embed).collect(),
+            ..DiscordMessagePayload::new(&self.text, &self.profile)
+        }
+    }
+
+    // forum channel の場合は text の 1 行目を title にして post を作る
+    fn payload(&self) -> DiscordMessagePayload {
+        let mut payload = self.message_payload();
+        if self.config.forum {
+            let title = self.text.lines().next().unwrap_or_default();
+            payload.thread_name = Some(truncate_graphemes(title, Self::THREAD_NAME_MAX_CHARS));
+        }
+        payload
+    }
+
+    // `body` で body を設定して送信し, 作成された message の参照を返す
+    async fn post<F>(&self, thread_id: Option<&str>, body: F) -> Result<RemoteMessage>
+    where
+        F: Fn(reqwest::RequestBuilder) -> reqwest::RequestBuilder + Send + Sync,
+    {
+        let mut url = self.url(&[])?;
+        url.query_pairs_mut().append_pair("wait", "true");
+        if let Some(thread_id) = thread_id {
+            set_thread_id(&mut url, thread_id);
+        }
+
+        let client = reqwest::Client::new();
+        let res = send_with_retry(&self.retry, || body(client.post(url.clone()))).await?;
+        let message = res
+            .json::<DiscordMessage>()
+            .await
+            .map_err(|e| MessageError::Transport(e.to_string()))?;
+        // forum の post か, thread_id を付けた webhook_url で thread に送った場合は thread に返信できる
+        let in_thread = self.config.forum || thread_id.is_some() || self.webhook_thread_id();
+        Ok(RemoteMessage::Discord {
+            id: message.id,
+            thread_id: in_thread.then_some(message.channel_id),
+        })
+    }
+
+    // webhook_url が thread に送るものか (`?thread_id=...`)
+    fn webhook_thread_id(&self) -> bool {
+        reqwest::Url::parse(&self.webhook_url)
+            .is_ok_and(|url| url.query_pairs().any(|(key, _)| key == "thread_id"))
+    }
+
+    // thread 内の message を編集・削除する場合は thread_id が必要
+    fn message_url(&self, id: &str, thread_id: &Option<String>) -> Result<reqwest::Url> {
+        let mut url = self.url(&["messages", id])?;
+        if let Some(thread_id) = thread_id {
+            set_thread_id(&mut url, thread_id);
+        }
+        Ok(url)
+    }
+
+    // webhook_url に path を足す. thread_id などの query はそのまま残す
+    fn url(&self, path: &[&str]) -> Result<reqwest::Url> {
+        let mut url = reqwest::Url::parse(&self.webhook_url)?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("webhook_url can not be a base"))?
+            .pop_if_empty()
+            .extend(path);
+        Ok(url)
+    }
+}
+
+#[async_trait]
+impl Sender for DiscordSender {
+    fn exceeds_limit(&self) -> Option<usize> {
+        over_limit(&self.text, Self::MAX_CHARS)
+    }
+
+    fn set_layout(&mut self, layout: &entity::Layout) {
+        self.layout = Some(layout.clone());
+    }
+
+    async fn send(&self) -> Result<Option<RemoteMessage>> {
+        tracing::info!("send to discord webhook");
+        let payload = self.payload();
+        Ok(Some(self.post(None, |req| req.json(&payload)).await?))
+    }
+
+    // webhook では forum の post か thread にのみ返信できる
+    async fn reply(&self, parent: &RemoteMessage) -> Result<Option<RemoteMessage>> {
+        let RemoteMessage::Discord {
+            thread_id: Some(thread_id),
+            ..
+        } = parent
+        else {
+            return Err(remote_mismatch(parent));
+        };
+        tracing::info!("reply to discord thread {}", thread_id);
+        let payload = self.message_payload();
+        Ok(Some(
+            self.post(Some(thread_id), |req| req.json(&payload)).await?,
+        ))
+    }
+
+    // thread 以外には返信できない
+    fn file_posting(&self, parent: Option<&RemoteMessage>) -> Option<FilePosting> {
+        match parent {
+            None
+            | Some(RemoteMessage::Discord {
+                thread_id: Some(_), ..
+            }) => Some(FilePosting::WithText),
+            Some(_) => None,
+        }
+    }
+
+    // https://discord.com/developers/docs/reference#uploading-files
+    async fn send_files(
+        &self,
+        files: &[entity::Attachment],
+        parent: Option<&RemoteMessage>,
+    ) -> Result<Option<RemoteMessage>> {
+        let (payload, thread_id) = match parent {
+            None => (self.payload(), None),
+            Some(RemoteMessage::Discord {
+                thread_id: Some(thread_id),
+                ..
+            }) => (self.message_payload(), Some(thread_id.as_str())),
+            Some(parent) => return Err(remote_mismatch(parent)),
+        };
+        if files.len() > Self::MAX_FILES {
+            return Err(MessageError::AttachmentLimit(format!(
+                "{} files (max {})",
+                files.len(),
+                Self::MAX_FILES
+            ))
+            .into());
+        }
+        let total = files.iter().map(|f| f.data.len()).sum::<usize>();
+        if total > Self::MAX_ATTACHMENT_BYTES {
+            return Err(MessageError::AttachmentLimit(format!(
+                "{} bytes in total (max {} bytes)",
+                total,
+                Self::MAX_ATTACHMENT_BYTES
+            ))
+            .into());
+        }
+        tracing::info!("send {} files to discord webhook", files.len());
+        let payload_json = serde_json::to_string(&payload)?;
+        let remote = self
+            .post(thread_id, |req| {
+                let form =
+                    reqwest::multipart::Form::new().text("payload_json", payload_json.clone());
+                let form = files.iter().enumerate().fold(form, |form, (i, file)| {
+                    form.part(format!("files[{}]", i), file_part(file))
+                });
+                req.multipart(form)
+            })
+            .await?;
+        Ok(Some(remote))
+    }
+
+    async fn edit(&self, remote: &RemoteMessage) -> Result<()> {
+        let RemoteMessage::Discord { id, thread_id } = remote else {
+            return Err(remote_mismatch(remote));
+        };
+        tracing::info!("edit discord message {}", id);
+        let url = self.message_url(id, thread_id)?;
+        let mut payload = serde_json::json!({"content": self.text});
+        if let Some(layout) = &self.layout {
+            payload["embeds"] = serde_json::json!([discord_embed(layout)]);
+        }
+
+        let client = reqwest::Client::new();
+        send_with_retry(&self.retry, || client.patch(url.clone()).json(&payload)).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, remote: &RemoteMessage) -> Result<()> {
+        let RemoteMessage::Discord { id, thread_id } = remote else {
+            return Err(remote_mismatch(remote));
+        };
+        tracing::info!("delete discord message {}", id);
+        let url = self.message_url(id, thread_id)?;
+
+        let client = reqwest::Client::new();
+        send_with_retry(&self.retry, || client.delete(url.clone())).await?;
+        Ok(())
+    }
+}
+
+// Teams の incoming webhook (Workflows を含む) に送る Adaptive Card
+// https://learn.microsoft.com/en-us/microsoftteams/platform/webhooks-and-connectors/how-to/connectors-using
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TeamsMessagePayload {
+    #[serde(rename = "type")]
+    pub message_type: String,
+    pub attachments: Vec<TeamsAttachment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TeamsAttachment {
+    #[serde(rename = "contentType")]
+    pub content_type: String,
+    pub content: AdaptiveCard,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdaptiveCard {
+    #[serde(rename = "$schema")]
+    pub schema: String,
+    #[serde(rename = "type")]
+    pub card_type: String,
+    pub version: String,
+    pub body: Vec<AdaptiveTextBlock>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdaptiveTextBlock {
+    #[serde(rename = "type")]
+    pub block_type: String,
+    pub text: String,
+    pub wrap: bool,
+}
+
+impl TeamsMessagePayload {
+    pub fn new(text: &str) -> Self {
+        Self {
+            message_type: "message".to_string(),
+            attachments: vec![TeamsAttachment {
+                content_type: "application/vnd.microsoft.card.adaptive".to_string(),
+                content: AdaptiveCard {
+                    schema: "http://adaptivecards.io/schemas/adaptive-card.json".to_string(),
+                    card_type: "AdaptiveCard".to_string(),
+                    version: "1.4".to_string(),
+                    body: vec![AdaptiveTextBlock {
+                        block_type: "TextBlock".to_string(),
+                        text: text.to_string(),
+                        wrap: true,
+                    }],
+                },
+            }],
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TeamsSender {
+    webhook_url: String,
+    text: String,
+    retry: RetryPolicy,
+}
+
+impl TeamsSender {
+    pub fn new(webhook_url: &str, text: &str, retry: &RetryPolicy) -> Self {
+        Self {
+            webhook_url: webhook_url.to_string(),
+            text: text.to_string(),
+            retry: retry.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Sender for TeamsSender {
+    async fn send(&self) -> Result<Option<RemoteMessage>> {
+        tracing::info!("send to teams webhook");
+        let payload = TeamsMessagePayload::new(&self.text);
+
+        let client = reqwest::Client::new();
+        send_with_retry(&self.retry, || {
+            client.post(&self.webhook_url).json(&payload)
+        })
+        .await?;
+        Ok(None)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct MattermostConfig {
+    // webhook の既定以外の channel に送る場合 (channel の name か @username)
+    #[serde(default)]
+    pub channel: Option<String>,
+    // avatar_url が未設定の場合のアイコン (e.g. robot_face)
+    #[serde(default)]
+    pub icon_emoji: Option<String>,
+}
+
+// https://developers.mattermost.com/integrate/webhooks/incoming/
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MattermostMessagePayload {
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon_emoji: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+}
+
+impl MattermostMessagePayload {
+    pub fn new(text: &str, config: &MattermostConfig, profile: &entity::Profile) -> Self {
+        Self {
+            text: text.to_string(),
+            username: profile.display_name.clone(),
+            icon_url: profile.avatar_url.clone(),
+            // icon_emoji は icon_url より優先されるので, avatar_url がある場合は送らない
+            icon_emoji: match profile.avatar_url {
+                Some(_) => None,
+                None => config.icon_emoji.clone(),
+            },
+            channel: config.channel.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MattermostSender {
+    webhook_url: String,
+    config: MattermostConfig,
+    profile: entity::Profile,
+    text: String,
+    retry: RetryPolicy,
+}
+
+impl MattermostSender {
+    // message の文字数の上限 (MaxPostSize の既定値)
+    const MAX_CHARS: usize = 16_383;
+
+    pub fn new(
+        webhook_url: &str,
+        config: MattermostConfig,
+        profile: &entity::Profile,
+        text: &str,
+        retry: &RetryPolicy,
+    ) -> Self {
+        Self {
+            webhook_url: webhook_url.to_string(),
+            config,
+            profile: profile.clone(),
+            text: text.to_string(),
+            retry: retry.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Sender for MattermostSender {
+    fn exceeds_limit(&self) -> Option<usize> {
+        over_limit(&self.text, Self::MAX_CHARS)
+    }
+
+    async fn send(&self) -> Result<Option<RemoteMessage>> {
+        tracing::info!("send to mattermost webhook");
+        let payload = MattermostMessagePayload::new(&self.text, &self.config, &self.profile);
+
+        let client = reqwest::Client::new();
+        send_with_retry(&self.retry, || {
+            client.post(&self.webhook_url).json(&payload)
+        })
+        .await?;
+        Ok(None)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct RocketChatConfig {
+    // webhook の既定以外の channel に送る場合 (#channel か @username)
+    #[serde(default)]
+    pub channel: Option<String>,
+    // avatar_url が未設定の場合のアイコン (e.g. :robot:)
+    #[serde(default)]
+    pub emoji: Option<String>,
+}
+
+// https://docs.rocket.chat/use-rocket.chat/workspace-administration/integrations
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RocketChatMessagePayload {
+    pub text: String,
+    // 表示名
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    // アイコン画像の URL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub emoji: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+}
+
+impl RocketChatMessagePayload {
+    pub fn new(text: &str, config: &RocketChatConfig, profile: &entity::Profile) -> Self {
+        Self {
+            text: text.to_string(),
+            alias: profile.display_name.clone(),
+            avatar: profile.avatar_url.clone(),
+            // avatar_url がある場合はそちらを使う
+            emoji: match profile.avatar_url {
+                Some(_) => None,
+                None => config.emoji.clone(),
+            },
+            channel: config.channel.clone(),
+        }
+    }
+}
+
+// Rocket.Chat は失敗時も 200 を返すことがあるため body の success を確認する
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RocketChatResponse {
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RocketChatSender {
+    webhook_url: String,
+    config: RocketChatConfig,
+    profile: entity::Profile,
+    text: String,
+    retry: RetryPolicy,
+}
+
+impl RocketChatSender {
+    // message の文字数の上限 (Message_MaxAllowedSize の既定値)
+    const MAX_CHARS: usize = 5000;
+
+    pub fn new(
+        webhook_url: &str,
+        config: RocketChatConfig,
+        profile: &entity::Profile,
+        text: &str,
+        retry: &RetryPolicy,
+    ) -> Self {
+        Self {
+            webhook_url: webhook_url.to_string(),
+            config,
+            profile: profile.clone(),
+            text: text.to_string(),
+            retry: retry.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Sender for RocketChatSender {
+    fn exceeds_limit(&self) -> Option<usize> {
+        over_limit(&self.text, Self::MAX_CHARS)
+    }
+
+    async fn send(&self) -> Result<Option<RemoteMessage>> {
+        tracing::info!("send to rocket.chat webhook");
+        let payload = RocketChatMessagePayload::new(&self.text, &self.config, &self.profile);
+
+        let client = reqwest::Client::new();
+        let res = send_with_retry(&self.retry, || {
+            client.post(&self.webhook_url).json(&payload)
+        })
+        .await?;
+        if let Ok(body) = res.json::<RocketChatResponse>().await {
+            if !body.success {
+                return Err(MessageError::Api(body.error.unwrap_or_default()).into());
+            }
+        }
+        Ok(None)
+    }
+}
+
+// https://core.telegram.org/bots/api#sendmessage
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TelegramConfig {
+    pub bot_token: String,
+    pub chat_id: TelegramChatId,
+    #[serde(default)]
+    pub parse_mode: Option<TelegramParseMode>,
+    #[serde(default = "TelegramConfig::default_api_url")]
+    pub api_url: String,
+}
+
+impl TelegramConfig {
+    fn default_api_url() -> String {
+        "https://api.telegram.org".to_string()
+    }
+}
+
+// 数値の chat id か `@channelusername`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TelegramChatId {
+    Id(i64),
+    Username(String),
+}
+
+impl std::fmt::Display for TelegramChatId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Id(id) => id.fmt(f),
+            Self::Username(username) => username.fmt(f),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TelegramParseMode {
+    MarkdownV2,
+    #[serde(rename = "HTML")]
+    Html,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TelegramMessagePayload {
+    pub chat_id: TelegramChatId,
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parse_mode: Option<TelegramParseMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_to_message_id: Option<i64>,
+}
+
+impl TelegramMessagePayload {
+    /// `text` は get_sender で parse mode の記法に変換済み
+    pub fn new(config: &TelegramConfig, text: &str) -> Self {
+        Self {
+            chat_id: config.chat_id.clone(),
+            text: text.to_string(),
+            parse_mode: config.parse_mode,
+            reply_to_message_id: None,
+        }
+    }
+}
+
+// editMessageText の body
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TelegramEditMessagePayload {
+    pub chat_id: i64,
+    pub message_id: i64,
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parse_mode: Option<TelegramParseMode>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TelegramResponse<R> {
+    pub ok: bool,
+    pub description: Option<String>,
+    pub result: Option<R>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TelegramMessage {
+    pub message_id: i64,
+    pub chat: TelegramChat,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TelegramChat {
+    pub id: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct TelegramSender {
+    config: TelegramConfig,
+    text: String,
+    retry: RetryPolicy,
+}
+
+impl TelegramSender {
+    // sendMessage の text の文字数の上限
+    const MAX_CHARS: usize = 4096;
+    // sendPhoto と sendDocument で送れるファイルのサイズの上限
+    const MAX_PHOTO_BYTES: usize = 10 * 1000 * 1000;
+    const MAX_DOCUMENT_BYTES: usize = 50 * 1000 * 1000;
+
+    pub fn new(config: TelegramConfig, text: &str, retry: &RetryPolicy) -> Self {
+        Self {
+            config,
+            text: text.to_string(),
+            retry: retry.clone(),
+        }
+    }
+
+    fn method_url(&self, method: &str) -> String {
+        format!(
+            "{}/bot{}/{}",
+            self.config.api_url.trim_end_matches('/'),
+            self.config.bot_token,
+            method
+        )
+    }
+
+    async fn call<P, R>(&self, method: &str, payload: &P) -> Result<Option<R>>
+    where
+        P: Serialize + Sync,
+        R: DeserializeOwned,
+    {
+        self.call_with(method, |req| req.json(payload)).await
+    }
+
+    // `body` で body を設定して Bot API を呼ぶ
+    async fn call_with<F, R>(&self, method: &str, body: F) -> Result<Option<R>>
+    where
+        F: Fn(reqwest::RequestBuilder) -> reqwest::RequestBuilder + Send + Sync,
+        R: DeserializeOwned,
+    {
+        let url = self.method_url(method);
+        let client = reqwest::Client::new();
+        let res = send_with_retry(&self.retry, || body(client.post(&url))).await?;
+        let body = res
+            .json::<TelegramResponse<R>>()
+            .await
+            .map_err(|e| MessageError::Transport(e.to_string()))?;
+        if !body.ok {
+            return Err(MessageError::Api(body.description.unwrap_or_default()).into());
+        }
+        Ok(body.result)
+    }
+}
+
+#[async_trait]
+impl Sender for TelegramSender {
+    fn exceeds_limit(&self) -> Option<usize> {
+        over_limit(&self.text, Self::MAX_CHARS)
+    }
+
+    async fn send(&self) -> Result<Option<RemoteMessage>> {
+        tracing::info!("send to telegram bot");
+        let payload = TelegramMessagePayload::new(&self.config, &self.text);
+        let message: Option<TelegramMessage> = self.call("sendMessage", &payload).await?;
+        Ok(message.map(|m| RemoteMessage::Telegram {
+            chat_id: m.chat.id,
+            message_id: m.message_id,
+        }))
+    }
+
+    async fn reply(&self, parent: &RemoteMessage) -> Result<Option<RemoteMessage>> {
+        let RemoteMessage::Telegram {
+            chat_id,
+            message_id,
+        } = parent
+        else {
+            return Err(remote_mismatch(parent));
+        };
+        tracing::info!("reply to telegram message {}", message_id);
+        let payload = TelegramMessagePayload {
+            chat_id: TelegramChatId::Id(*chat_id),
+            reply_to_message_id: Some(*message_id),
+            ..TelegramMessagePayload::new(&self.config, &self.text)
+        };
+        let message: Option<TelegramMessage> = self.call("sendMessage", &payload).await?;
+        Ok(message.map(|m| RemoteMessage::Telegram {
+            chat_id: m.chat.id,
+            message_id: m.message_id,
+        }))
+    }
+
+    // text は編集できるように sendMessage で別に送り, ファイルは 1 つずつ送る
+    fn file_posting(&self, parent: Option<&RemoteMessage>) -> Option<FilePosting> {
+        match parent {
+            None | Some(RemoteMessage::Telegram { .. }) => Some(FilePosting::Separately(1)),
+            Some(_) => None,
+        }
+    }
+
+    // 画像は sendPhoto, それ以外や大きな画像は sendDocument で送る
+    async fn send_files(
+        &self,
+        files: &[entity::Attachment],
+        parent: Option<&RemoteMessage>,
+    ) -> Result<Option<RemoteMessage>> {
+        let (chat_id, reply_to_message_id) = match parent {
+            None => (self.config.chat_id.clone(), None),
+            Some(RemoteMessage::Telegram {
+                chat_id,
+                message_id,
+            }) => (TelegramChatId::Id(*chat_id), Some(*message_id)),
+            Some(parent) => return Err(remote_mismatch(parent)),
+        };
+        for file in files {
+            check_file_size(file, Self::MAX_DOCUMENT_BYTES)?;
+        }
+        tracing::info!("send {} files to telegram bot", files.len());
+
+        let mut first: Option<TelegramMessage> = None;
+        for file in files {
+            let (method, field) = if file.content_type.starts_with("image/")
+                && file.data.len() <= Self::MAX_PHOTO_BYTES
+            {
+                ("sendPhoto", "photo")
+            } else {
+                ("sendDocument", "document")
+            };
+            let message: Option<TelegramMessage> = self
+                .call_with(method, |req| {
+                    let mut form = reqwest::multipart::Form::new()
+                        .text("chat_id", chat_id.to_string())
+                        .part(field, file_part(file));
+                    if let Some(id) = reply_to_message_id {
+                        form = form.text("reply_to_message_id", id.to_string());
+                    }
+                    req.multipart(form)
+                })
+                .await?;
+            first = first.or(message);
+        }
+        Ok(first.map(|m| RemoteMessage::Telegram {
+            chat_id: m.chat.id,
+            message_id: m.message_id,
+        }))
+    }
+
+    async fn edit(&self, remote: &RemoteMessage) -> Result<()> {
+        let RemoteMessage::Telegram {
+            chat_id,
+            message_id,
+        } = remote
+        else {
+            return Err(remote_mismatch(remote));
+        };
+        tracing::info!("edit telegram message {}", message_id);
+        let message = TelegramMessagePayload::new(&self.config, &self.text);
+        let payload = TelegramEditMessagePayload {
+            chat_id: *chat_id,
+            message_id: *message_id,
+            text: message.text,
+            parse_mode: message.parse_mode,
+        };
+        self.call::<_, serde_json::Value>("editMessageText", &payload)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, remote: &RemoteMessage) -> Result<()> {
+        let RemoteMessage::Telegram {
+            chat_id,
+            message_id,
+        } = remote
+        else {
+            return Err(remote_mismatch(remote));
+        };
+        tracing::info!("delete telegram message {}", message_id);
+        let payload = serde_json::json!({"chat_id": chat_id, "message_id": message_id});
+        self.call::<_, serde_json::Value>("deleteMessage", &payload)
+            .await?;
+        Ok(())
+    }
+}
+
+// https://spec.matrix.org/v1.6/client-server-api/#put_matrixclientv3roomsroomidsendeventtypetxnid
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatrixConfig {
+    // e.g. https://matrix.org
+    pub homeserver_url: String,
+    // e.g. !abcdefg:matrix.org
+    pub room_id: String,
+    pub access_token: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatrixMessagePayload {
+    pub msgtype: String,
+    pub body: String,
+    pub format: String,
+    pub formatted_body: String,
+}
+
+impl MatrixMessagePayload {
+    pub fn new(text: &str, html: &str) -> Self {
+        Self {
+            msgtype: "m.text".to_string(),
+            body: text.to_string(),
+            format: "org.matrix.custom.html".to_string(),
+            formatted_body: html.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct MatrixEventResponse {
+    pub event_id: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct MatrixSender {
+    // {homeserver_url}/_matrix/client/v3/rooms/{room_id}
+    room_url: reqwest::Url,
+    access_token: String,
+    txn_id: String,
+    // body と formatted_body
+    text: String,
+    html: String,
+    retry: RetryPolicy,
+}
+
+impl MatrixSender {
+    /// transaction id は Sender ごとに作る. 送信の再送では set_idempotency_key で delivery ごとに固定する
+    pub fn new(config: MatrixConfig, text: &str, html: &str, retry: &RetryPolicy) -> Result<Self> {
+        let mut room_url = reqwest::Url::parse(config.homeserver_url.as_str())?;
+        room_url
+            .path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("homeserver_url can not be a base"))?
+            .pop_if_empty()
+            .extend(["_matrix", "client", "v3", "rooms", config.room_id.as_str()]);
+        Ok(Self {
+            room_url,
+            access_token: config.access_token,
+            txn_id: uuid::Uuid::new_v4().to_string(),
+            text: text.to_string(),
+            html: html.to_string(),
+            retry: retry.clone(),
+        })
+    }
+
+    fn url(&self, path: &[&str]) -> reqwest::Url {
+        let mut url = self.room_url.clone();
+        // new で base になれることを確認している
+        url.path_segments_mut().unwrap().extend(path);
+        url
+    }
+
+    async fn put<P: Serialize + Sync>(&self, url: reqwest::Url, payload: &P) -> Result<String> {
+        let client = reqwest::Client::new();
+        let res = send_with_retry(&self.retry, || {
+            client
+                .put(url.clone())
+                .bearer_auth(&self.access_token)
+                .json(payload)
+        })
+        .await?;
+        let body = res
+            .json::<MatrixEventResponse>()
+            .await
+            .map_err(|e| MessageError::Transport(e.to_string()))?;
+        Ok(body.event_id)
+    }
+}
+
+#[async_trait]
+impl Sender for MatrixSender {
+    fn set_idempotency_key(&mut self, key: &str) {
+        self.txn_id = key.to_string();
+    }
+
+    async fn send(&self) -> Result<Option<RemoteMessage>> {
+        tracing::info!("send to matrix room");
+        let payload = MatrixMessagePayload::new(&self.text, &self.html);
+        let url = self.url(&["send", "m.room.message", &self.txn_id]);
+        let event_id = self.put(url, &payload).await?;
+        Ok(Some(RemoteMessage::Matrix { event_id }))
+    }
+
+    // https://spec.matrix.org/v1.6/client-server-api/#threading
+    async fn reply(&self, parent: &RemoteMessage) -> Result<Option<RemoteMessage>> {
+        let RemoteMessage::Matrix { event_id } = parent else {
+            return Err(remote_mismatch(parent));
+        };
+        tracing::info!("reply to matrix thread {}", event_id);
+        let mut payload = serde_json::to_value(MatrixMessagePayload::new(&self.text, &self.html))?;
+        // thread に対応していない client では親 event への返信として表示される
+        payload["m.relates_to"] = serde_json::json!({
+            "rel_type": "m.thread",
+            "event_id": event_id,
+            "is_falling_back": true,
+            "m.in_reply_to": {"event_id": event_id},
+        });
+        let url = self.url(&["send", "m.room.message", &self.txn_id]);
+        let event_id = self.put(url, &payload).await?;
+        Ok(Some(RemoteMessage::Matrix { event_id }))
+    }
+
+    // https://spec.matrix.org/v1.6/client-server-api/#event-replacements
+    async fn edit(&self, remote: &RemoteMessage) -> Result<()> {
+        let RemoteMessage::Matrix { event_id } = remote else {
+            return Err(remote_mismatch(remote));
+        };
+        tracing::info!("edit matrix event {}", event_id);
+        let new_content = MatrixMessagePayload::new(&self.text, &self.html);
+        // 編集に対応していない client 向けの fallback
+        let fallback =
+            MatrixMessagePayload::new(&format!("* {}", self.text), &format!("* {}", self.html));
+        let mut payload = serde_json::to_value(fallback)?;
+        payload["m.new_content"] = serde_json::to_value(new_content)?;
+        payload["m.relates_to"] = serde_json::json!({
+            "rel_type": "m.replace",
+            "event_id": event_id,
+        });
+        let url = self.url(&["send", "m.room.message", &self.txn_id]);
+        self.put(url, &payload).await?;
+        Ok(())
+    }
+
+    // https://spec.matrix.org/v1.6/client-server-api/#redactions
+    async fn delete(&self, remote: &RemoteMessage) -> Result<()> {
+        let RemoteMessage::Matrix { event_id } = remote else {
+            return Err(remote_mismatch(remote));
+        };
+        tracing::info!("redact matrix event {}", event_id);
+        let url = self.url(&["redact", event_id, &self.txn_id]);
+        self.put(url, &serde_json::json!({})).await?;
+        Ok(())
+    }
+}
+
+// https://developers.google.com/chat/how-tos/webhooks
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct GoogleChatConfig {
+    // 同じ threadKey の message は同じ thread にまとめられる
+    #[serde(default)]
+    pub thread_key: Option<String>,
+    // true の場合は text ではなく cardsV2 で送る
+    #[serde(default)]
+    pub card: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GoogleChatMessagePayload {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cards_v2: Vec<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread: Option<GoogleChatThread>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GoogleChatThread {
+    pub thread_key: String,
+}
+
+impl GoogleChatMessagePayload {
+    pub fn new(config: &GoogleChatConfig, text: &str) -> Self {
+        let (text, cards_v2) = if config.card {
+            let card = serde_json::json!({
+                "cardId": "times-hub",
+                "card": {
+                    "sections": [{
+                        "widgets": [{"textParagraph": {"text": escape_html(text)}}],
+                    }],
+                },
+            });
+            (None, vec![card])
+        } else {
+            (Some(text.to_string()), vec![])
+        };
+        Self {
+            text,
+            cards_v2,
+            thread: config
+                .thread_key
+                .clone()
+                .map(|thread_key| GoogleChatThread { thread_key }),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GoogleChatSender {
+    url: reqwest::Url,
+    config: GoogleChatConfig,
+    text: String,
+    retry: RetryPolicy,
+}
+
+impl GoogleChatSender {
+    // text の文字数の上限
+    const MAX_CHARS: usize = 4096;
+
+    pub fn new(
+        webhook_url: &str,
+        config: GoogleChatConfig,
+        text: &str,
+        retry: &RetryPolicy,
+    ) -> Result<Self> {
+        let mut url = reqwest::Url::parse(webhook_url)?;
+        // threadKey を指定しても, このオプションがないと新しい thread になる
+        if config.thread_key.is_some() {
+            url.query_pairs_mut()
+                .append_pair("messageReplyOption", "REPLY_MESSAGE_FALLBACK_TO_NEW_THREAD");
+        }
+        Ok(Self {
+            url,
+            config,
+            text: text.to_string(),
+            retry: retry.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Sender for GoogleChatSender {
+    fn exceeds_limit(&self) -> Option<usize> {
+        over_limit(&self.text, Self::MAX_CHARS)
+    }
+
+    async fn send(&self) -> Result<Option<RemoteMessage>> {
+        tracing::info!("send to google chat webhook");
+        let payload = GoogleChatMessagePayload::new(&self.config, &self.text);
+
+        let client = reqwest::Client::new();
+        send_with_retry(&self.retry, || client.post(self.url.clone()).json(&payload)).await?;
+        Ok(None)
+    }
+}
+
+// https://zulip.com/api/send-message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ZulipConfig {
+    // e.g. https://example.zulipchat.com
+    pub site_url: String,
+    pub bot_email: String,
+    pub api_key: String,
+    pub stream: String,
+    pub topic: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ZulipMessagePayload {
+    #[serde(rename = "type")]
+    pub message_type: String,
+    pub to: String,
+    pub topic: String,
+    pub content: String,
+}
+
+impl ZulipMessagePayload {
+    pub fn new(config: &ZulipConfig, text: &str) -> Self {
+        Self {
+            message_type: "stream".to_string(),
+            to: config.stream.clone(),
+            topic: config.topic.clone(),
+            content: text.to_string(),
+        }
+    }
+}
+
+// Zulip は body の result でも成否を返す
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ZulipResponse {
+    pub result: String,
+    pub msg: Option<String>,
+    // 送信した message の id
+    pub id: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ZulipSender {
+    config: ZulipConfig,
+    text: String,
+    retry: RetryPolicy,
+}
+
+impl ZulipSender {
+    // message の文字数の上限
+    const MAX_CHARS: usize = 10_000;
+
+    pub fn new(config: ZulipConfig, text: &str, retry: &RetryPolicy) -> Self {
+        Self {
+            config,
+            text: text.to_string(),
+            retry: retry.clone(),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!(
+            "{}/api/v1/{}",
+            self.config.site_url.trim_end_matches('/'),
+            path
+        )
+    }
+
+    async fn call<F>(&self, build: F) -> Result<ZulipResponse>
+    where
+        F: Fn() -> reqwest::RequestBuilder + Send + Sync,
+    {
+        let res = send_with_retry(&self.retry, || {
+            build().basic_auth(&self.config.bot_email, Some(&self.config.api_key))
+        })
+        .await?;
+        let body = res
+            .json::<ZulipResponse>()
+            .await
+            .map_err(|e| MessageError::Transport(e.to_string()))?;
+        if body.result != "success" {
+            return Err(MessageError::Api(body.msg.unwrap_or_default()).into());
+        }
+        Ok(body)
+    }
+}
+
+#[async_trait]
+impl Sender for ZulipSender {
+    fn exceeds_limit(&self) -> Option<usize> {
+        over_limit(&self.text, Self::MAX_CHARS)
+    }
+
+    async fn send(&self) -> Result<Option<RemoteMessage>> {
+        tracing::info!("send to zulip stream");
+        let payload = ZulipMessagePayload::new(&self.config, &self.text);
+        let url = self.url("messages");
+
+        let client = reqwest::Client::new();
+        let body = self.call(|| client.post(&url).form(&payload)).await?;
+        Ok(body.id.map(|id| RemoteMessage::Zulip { id }))
+    }
+
+    async fn edit(&self, remote: &RemoteMessage) -> Result<()> {
+        let RemoteMessage::Zulip { id } = remote else {
+            return Err(remote_mismatch(remote));
+        };
+        tracing::info!("edit zulip message {}", id);
+        let url = self.url(&format!("messages/{}", id));
+
+        let client = reqwest::Client::new();
+        self.call(|| client.patch(&url).form(&[("content", &self.text)]))
+            .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, remote: &RemoteMessage) -> Result<()> {
+        let RemoteMessage::Zulip { id } = remote else {
+            return Err(remote_mismatch(remote));
+        };
+        tracing::info!("delete zulip message {}", id);
+        let url = self.url(&format!("messages/{}", id));
+
+        let client = reqwest::Client::new();
+        self.call(|| client.delete(&url)).await?;
+        Ok(())
+    }
+}
+
+// Mastodon / Misskey の workspace 共通の設定
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FediverseConfig {
+    // e.g. https://mastodon.social
+    pub instance_url: String,
+    pub access_token: String,
+    #[serde(default)]
+    pub visibility: FediverseVisibility,
+    // 指定した場合は content warning (CW) として本文を折りたたむ
+    #[serde(default)]
+    pub content_warning: Option<String>,
+    #[serde(default)]
+    pub overflow: FediverseOverflow,
+    // instance ごとに文字数の上限が異なる場合に指定する
+    #[serde(default)]
+    pub max_chars: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FediverseVisibility {
+    #[default]
+    Public,
+    Unlisted,
+    Followers,
+}
+
+// 文字数の上限を超えた場合の扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FediverseOverflow {
+    // 上限で切り詰めて "…" を付ける
+    #[default]
+    Truncate,
+    // long_text が split の場合と同じく分割し, 前の投稿への reply として thread にする
+    Thread,
+}
+
+/// `max_chars` 文字を超える text を切り詰めて "…" を付ける
+pub fn fediverse_post(text: &str, max_chars: usize) -> String {
+    let max_chars = max_chars.max(1);
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let mut post = text.chars().take(max_chars - 1).collect::<String>();
+    post.push('…');
+    post
+}
+
+// https://docs.joinmastodon.org/methods/statuses/#create
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MastodonStatusPayload {
+    pub status: String,
+    pub visibility: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spoiler_text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub in_reply_to_id: Option<String>,
+}
+
+impl MastodonStatusPayload {
+    pub fn new(config: &FediverseConfig, status: &str, in_reply_to_id: Option<String>) -> Self {
+        let visibility = match config.visibility {
+            FediverseVisibility::Public => "public",
+            FediverseVisibility::Unlisted => "unlisted",
+            FediverseVisibility::Followers => "private",
+        };
+        Self {
+            status: status.to_string(),
+            visibility: visibility.to_string(),
+            spoiler_text: config.content_warning.clone(),
+            in_reply_to_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct MastodonStatus {
+    pub id: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct MastodonSender {
+    config: FediverseConfig,
+    text: String,
+    retry: RetryPolicy,
+    // 再送しても同じ status を重複して作らないための Idempotency-Key
+    idempotency_key: String,
+}
+
+impl MastodonSender {
+    const MAX_CHARS: usize = 500;
+
+    pub fn new(config: FediverseConfig, text: &str, retry: &RetryPolicy) -> Self {
+        Self {
+            config,
+            text: text.to_string(),
+            retry: retry.clone(),
+            idempotency_key: uuid::Uuid::new_v4().to_string(),
+        }
+    }
+
+    fn max_chars(&self) -> usize {
+        self.config.max_chars.unwrap_or(Self::MAX_CHARS)
+    }
+
+    fn status_url(&self, id: &str) -> String {
+        format!(
+            "{}/api/v1/statuses/{}",
+            self.config.instance_url.trim_end_matches('/'),
+            id
+        )
+    }
+
+    async fn post(&self, in_reply_to_id: Option<String>) -> Result<Option<RemoteMessage>> {
+        let url = format!(
+            "{}/api/v1/statuses",
+            self.config.instance_url.trim_end_matches('/')
+        );
+        let status = fediverse_post(&self.text, self.max_chars());
+        let payload = MastodonStatusPayload::new(&self.config, &status, in_reply_to_id);
+
+        let client = reqwest::Client::new();
+        let res = send_with_retry(&self.retry, || {
+            client
+                .post(&url)
+                .bearer_auth(&self.config.access_token)
+                .header("Idempotency-Key", &self.idempotency_key)
+                .json(&payload)
+        })
+        .await?;
+        let status = res
+            .json::<MastodonStatus>()
+            .await
+            .map_err(|e| MessageError::Transport(e.to_string()))?;
+        Ok(Some(RemoteMessage::Mastodon { id: status.id }))
+    }
+}
+
+#[async_trait]
+impl Sender for MastodonSender {
+    fn exceeds_limit(&self) -> Option<usize> {
+        over_limit(&self.text, self.max_chars())
+    }
+
+    fn truncates_long_text(&self) -> bool {
+        self.config.overflow == FediverseOverflow::Truncate
+    }
+
+    fn threads_chunks(&self) -> bool {
+        true
+    }
+
+    fn set_idempotency_key(&mut self, key: &str) {
+        self.idempotency_key = key.to_string();
+    }
+
+    async fn send(&self) -> Result<Option<RemoteMessage>> {
+        tracing::info!("send to mastodon");
+        self.post(None).await
+    }
+
+    async fn reply(&self, parent: &RemoteMessage) -> Result<Option<RemoteMessage>> {
+        let RemoteMessage::Mastodon { id } = parent else {
+            return Err(remote_mismatch(parent));
+        };
+        tracing::info!("reply to mastodon status {}", id);
+        self.post(Some(id.clone())).await
+    }
+
+    // https://docs.joinmastodon.org/methods/statuses/#edit
+    async fn edit(&self, remote: &RemoteMessage) -> Result<()> {
+        let RemoteMessage::Mastodon { id } = remote else {
+            return Err(remote_mismatch(remote));
+        };
+        tracing::info!("edit mastodon status {}", id);
+        let url = self.status_url(id);
+        let payload = serde_json::json!({
+            "status": fediverse_post(&self.text, self.max_chars()),
+            "spoiler_text": self.config.content_warning,
+        });
+
+        let client = reqwest::Client::new();
+        send_with_retry(&self.retry, || {
+            client
+                .put(&url)
+                .bearer_auth(&self.config.access_token)
+                .json(&payload)
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, remote: &RemoteMessage) -> Result<()> {
+        let RemoteMessage::Mastodon { id } = remote else {
+            return Err(remote_mismatch(remote));
+        };
+        tracing::info!("delete mastodon status {}", id);
+        let url = self.status_url(id);
+
+        let client = reqwest::Client::new();
+        send_with_retry(&self.retry, || {
+            client.delete(&url).bearer_auth(&self.config.access_token)
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+// https://misskey-hub.net/docs/api/endpoints/notes/create
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MisskeyNotePayload {
+    // access token
+    pub i: String,
+    pub text: String,
+    pub visibility: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cw: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_id: Option<String>,
+}
+
+impl MisskeyNotePayload {
+    pub fn new(config: &FediverseConfig, text: &str, reply_id: Option<String>) -> Self {
+        let visibility = match config.visibility {
+            FediverseVisibility::Public => "public",
+            FediverseVisibility::Unlisted => "home",
+            FediverseVisibility::Followers => "followers",
+        };
+        Self {
+            i: config.access_token.clone(),
+            text: text.to_string(),
+            visibility: visibility.to_string(),
+            cw: config.content_warning.clone(),
+            reply_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MisskeyCreatedNote {
+    pub created_note: MisskeyNote,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct MisskeyNote {
+    pub id: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct MisskeySender {
+    config: FediverseConfig,
+    text: String,
+    retry: RetryPolicy,
+}
+
+impl MisskeySender {
+    const MAX_CHARS: usize = 3000;
+
+    pub fn new(config: FediverseConfig, text: &str, retry: &RetryPolicy) -> Self {
+        Self {
+            config,
+            text: text.to_string(),
+            retry: retry.clone(),
+        }
+    }
+
+    fn max_chars(&self) -> usize {
+        self.config.max_chars.unwrap_or(Self::MAX_CHARS)
+    }
+
+    async fn post(&self, reply_id: Option<String>) -> Result<Option<RemoteMessage>> {
+        let url = format!(
+            "{}/api/notes/create",
+            self.config.instance_url.trim_end_matches('/')
+        );
+        let text = fediverse_post(&self.text, self.max_chars());
+        let payload = MisskeyNotePayload::new(&self.config, &text, reply_id);
+
+        let client = reqwest::Client::new();
+        let res = send_with_retry(&self.retry, || client.post(&url).json(&payload)).await?;
+        let body = res
+            .json::<MisskeyCreatedNote>()
+            .await
+            .map_err(|e| MessageError::Transport(e.to_string()))?;
+        Ok(Some(RemoteMessage::Misskey {
+            id: body.created_note.id,
+        }))
+    }
+}
+
+#[async_trait]
+impl Sender for MisskeySender {
+    fn exceeds_limit(&self) -> Option<usize> {
+        over_limit(&self.text, self.max_chars())
+    }
+
+    fn truncates_long_text(&self) -> bool {
+        self.config.overflow == FediverseOverflow::Truncate
+    }
+
+    fn threads_chunks(&self) -> bool {
+        true
+    }
+
+    async fn send(&self) -> Result<Option<RemoteMessage>> {
+        tracing::info!("send to misskey");
+        self.post(None).await
+    }
+
+    async fn reply(&self, parent: &RemoteMessage) -> Result<Option<RemoteMessage>> {
+        let RemoteMessage::Misskey { id } = parent else {
+            return Err(remote_mismatch(parent));
+        };
+        tracing::info!("reply to misskey note {}", id);
+        self.post(Some(id.clone())).await
+    }
+
+    // https://misskey-hub.net/docs/api/endpoints/notes/delete
+    async fn delete(&self, remote: &RemoteMessage) -> Result<()> {
+        let RemoteMessage::Misskey { id } = remote else {
+            return Err(remote_mismatch(remote));
+        };
+        tracing::info!("delete misskey note {}", id);
+        let url = format!(
+            "{}/api/notes/delete",
+            self.config.instance_url.trim_end_matches('/')
+        );
+        let payload = serde_json::json!({"i": self.config.access_token, "noteId": id});
+
+        let client = reqwest::Client::new();
+        send_with_retry(&self.retry, || client.post(&url).json(&payload)).await?;
+        Ok(())
+    }
+}
+
+// https://docs.bsky.app/docs/advanced-guides/posts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlueskyConfig {
+    #[serde(default = "BlueskyConfig::default_service_url")]
+    pub service_url: String,
+    // e.g. pollenjp.bsky.social
+    pub handle: String,
+    // アカウントのパスワードではなく App Password を使う
+    pub app_password: String,
+}
+
+impl BlueskyConfig {
+    fn default_service_url() -> String {
+        "https://bsky.social".to_string()
+    }
+
+    fn xrpc_url(&self, method: &str) -> String {
+        format!("{}/xrpc/{}", self.service_url.trim_end_matches('/'), method)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlueskySession {
+    pub access_jwt: String,
+    pub did: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BlueskyErrorBody {
+    pub error: String,
+}
+
+// createSession は rate limit が厳しいため, service と handle ごとに session を使い回す.
+// access token の有効期限を過ぎた session は次に使う時に捨てる
+// (service_url, handle)
+type BlueskySessionKey = (String, String);
+
+#[derive(Debug, Clone, Default)]
+pub struct BlueskySessions {
+    sessions: Arc<tokio::sync::Mutex<HashMap<BlueskySessionKey, (BlueskySession, Instant)>>>,
+}
+
+impl BlueskySessions {
+    // access token の有効期限 (2 時間) より少し短くする
+    const TTL: Duration = Duration::from_secs(110 * 60);
+
+    async fn get(&self, key: &BlueskySessionKey) -> Option<BlueskySession> {
+        let mut sessions = self.sessions.lock().await;
+        sessions.retain(|_, (_, created_at)| created_at.elapsed() < Self::TTL);
+        sessions.get(key).map(|(session, _)| session.clone())
+    }
+
+    async fn insert(&self, key: BlueskySessionKey, session: BlueskySession) {
+        self.sessions
+            .lock()
+            .await
+            .insert(key, (session, Instant::now()));
+    }
+
+    async fn remove(&self, key: &BlueskySessionKey) {
+        self.sessions.lock().await.remove(key);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlueskyPostRecord {
+    #[serde(rename = "$type")]
+    pub record_type: String,
+    pub text: String,
+    pub created_at: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub facets: Vec<BlueskyFacet>,
+}
+
+// https://docs.bsky.app/docs/advanced-guides/post-richtext
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlueskyFacet {
+    pub index: BlueskyByteSlice,
+    pub features: Vec<BlueskyFacetFeature>,
+}
+
+// UTF-8 の byte offset
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlueskyByteSlice {
+    pub byte_start: usize,
+    pub byte_end: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "$type")]
+pub enum BlueskyFacetFeature {
+    #[serde(rename = "app.bsky.richtext.facet#link")]
+    Link { uri: String },
+    #[serde(rename = "app.bsky.richtext.facet#mention")]
+    Mention { did: String },
+}
+
+// facet の候補. mention は送信時に handle を DID に解決する
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlueskyFacetCandidate {
+    Link(String),
+    Mention(String),
+}
+
+static BLUESKY_FACET_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?:^|\s)(?:(https?://\S+)|@([a-zA-Z0-9][a-zA-Z0-9.-]*\.[a-zA-Z]{2,}))").unwrap()
+});
+
+/// text 中の link と mention を byte offset とともに返す
+pub fn bluesky_facet_candidates(text: &str) -> Vec<(BlueskyByteSlice, BlueskyFacetCandidate)> {
+    BLUESKY_FACET_RE
+        .captures_iter(text)
+        .filter_map(|caps| {
+            if let Some(m) = caps.get(1) {
+                // 文末の句読点や括弧は link に含めない
+                let uri = m
+                    .as_str()
+                    .trim_end_matches(&['.', ',', ';', ':', '!', '?', ')', '…'][..]);
+                let index = BlueskyByteSlice {
+                    byte_start: m.start(),
+                    byte_end: m.start() + uri.len(),
+                };
+                Some((index, BlueskyFacetCandidate::Link(uri.to_string())))
+            } else {
+                let m = caps.get(2)?;
+                let index = BlueskyByteSlice {
+                    // `@` を含める
+                    byte_start: m.start() - 1,
+                    byte_end: m.end(),
+                };
+                Some((
+                    index,
+                    BlueskyFacetCandidate::Mention(m.as_str().to_string()),
+                ))
+            }
+        })
+        .collect()
+}
+
+/// `max` grapheme を超える場合は切り詰めて "…" を付ける
+pub fn truncate_graphemes(text: &str, max: usize) -> String {
+    if text.graphemes(true).count() <= max {
+        return text.to_string();
+    }
+    let mut truncated = text
+        .graphemes(true)
+        .take(max.saturating_sub(1))
+        .collect::<String>();
+    truncated.push('…');
+    truncated
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct BlueskyCreatedRecord {
+    pub uri: String,
+    pub cid: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct BlueskyResolvedHandle {
+    pub did: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlueskySender {
+    config: BlueskyConfig,
+    text: String,
+    retry: RetryPolicy,
+    sessions: BlueskySessions,
+}
+
+impl BlueskySender {
+    const MAX_GRAPHEMES: usize = 300;
+
+    pub fn new(
+        config: BlueskyConfig,
+        text: &str,
+        retry: &RetryPolicy,
+        sessions: &BlueskySessions,
+    ) -> Self {
+        Self {
+            config,
+            text: text.to_string(),
+            retry: retry.clone(),
+            sessions: sessions.clone(),
+        }
+    }
+
+    fn session_key(&self) -> BlueskySessionKey {
+        (self.config.service_url.clone(), self.config.handle.clone())
+    }
+
+    async fn create_session(&self, client: &reqwest::Client) -> Result<BlueskySession> {
+        tracing::info!("create bluesky session for {}", self.config.handle);
+        let url = self.config.xrpc_url("com.atproto.server.createSession");
+        let payload = serde_json::json!({
+            "identifier": self.config.handle,
+            "password": self.config.app_password,
+        });
+        // 作り直せなかった場合は古い session も使わない
+        self.sessions.remove(&self.session_key()).await;
+        let res = send_with_retry(&self.retry, || client.post(&url).json(&payload)).await?;
+        let session = res
+            .json::<BlueskySession>()
+            .await
+            .map_err(|e| MessageError::Transport(e.to_string()))?;
+        self.sessions
+            .insert(self.session_key(), session.clone())
+            .await;
+        Ok(session)
+    }
+
+    // 解決できなかった mention は facet にしない
+    async fn resolve_handle(&self, client: &reqwest::Client, handle: &str) -> Option<String> {
+        let url = self.config.xrpc_url("com.atproto.identity.resolveHandle");
+        let res = client
+            .get(&url)
+            .query(&[("handle", handle)])
+            .send()
+            .await
+            .ok()?;
+        if !res.status().is_success() {
+            tracing::warn!("failed to resolve bluesky handle: {}", handle);
+            return None;
+        }
+        res.json::<BlueskyResolvedHandle>()
+            .await
+            .ok()
+            .map(|res| res.did)
+    }
+
+    async fn record(&self, client: &reqwest::Client) -> BlueskyPostRecord {
+        let text = truncate_graphemes(&self.text, Self::MAX_GRAPHEMES);
+        let mut facets = vec![];
+        for (index, candidate) in bluesky_facet_candidates(&text) {
+            let feature = match candidate {
+                BlueskyFacetCandidate::Link(uri) => BlueskyFacetFeature::Link { uri },
+                BlueskyFacetCandidate::Mention(handle) => {
+                    match self.resolve_handle(client, &handle).await {
+                        Some(did) => BlueskyFacetFeature::Mention { did },
+                        None => continue,
+                    }
+                }
+            };
+            facets.push(BlueskyFacet {
+                index,
+                features: vec![feature],
+            });
+        }
+        BlueskyPostRecord {
+            record_type: "app.bsky.feed.post".to_string(),
+            text,
+            created_at: Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            facets,
+        }
+    }
+
+    /// session の access token で xrpc の `method` を呼ぶ.
+    /// 使い回した session の token が期限切れの場合だけ, session を作り直して呼び直す
+    async fn call<F>(
+        &self,
+        client: &reqwest::Client,
+        method: &str,
+        payload: F,
+    ) -> Result<reqwest::Response>
+    where
+        F: Fn(&BlueskySession) -> serde_json::Value,
+    {
+        let cached = self.sessions.get(&self.session_key()).await;
+        let from_cache = cached.is_some();
+        let session = match cached {
+            Some(session) => session,
+            None => self.create_session(client).await?,
+        };
+        if let Some(res) = self
+            .call_once(client, &session, method, &payload(&session))
+            .await?
+        {
+            return Ok(res);
+        }
+        if from_cache {
+            let session = self.create_session(client).await?;
+            if let Some(res) = self
+                .call_once(client, &session, method, &payload(&session))
+                .await?
+            {
+                return Ok(res);
+            }
+        }
+        Err(MessageError::Rejected(StatusCode::BAD_REQUEST).into())
+    }
+
+    /// access token が期限切れ (400 の ExpiredToken) の場合は None を返す
+    async fn call_once(
+        &self,
+        client: &reqwest::Client,
+        session: &BlueskySession,
+        method: &str,
+        payload: &serde_json::Value,
+    ) -> Result<Option<reqwest::Response>> {
+        let url = self.config.xrpc_url(method);
+        let res = send_with_retry_unchecked(&self.retry, || {
+            client
+                .post(&url)
+                .bearer_auth(&session.access_jwt)
+                .json(payload)
+        })
+        .await?;
+        let status = res.status();
+        if status.is_success() {
+            return Ok(Some(res));
+        }
+        let body = res.json::<BlueskyErrorBody>().await.ok();
+        match body {
+            Some(body) if status == StatusCode::BAD_REQUEST && body.error == "ExpiredToken" => {
+                Ok(None)
+            }
+            _ => Err(MessageError::Rejected(status).into()),
+        }
+    }
+}
+
+#[async_trait]
+impl Sender for BlueskySender {
+    fn exceeds_limit(&self) -> Option<usize> {
+        let graphemes = self.text.graphemes(true).count();
+        (graphemes > Self::MAX_GRAPHEMES).then_some(Self::MAX_GRAPHEMES)
+    }
+
+    // long_text が split の場合は, 分けずに上限で切り詰めて送る
+    fn truncates_long_text(&self) -> bool {
+        true
+    }
+
+    async fn send(&self) -> Result<Option<RemoteMessage>> {
+        tracing::info!("send to bluesky");
+        let client = reqwest::Client::new();
+        let record = self.record(&client).await;
+
+        let res = self
+            .call(&client, "com.atproto.repo.createRecord", |session| {
+                serde_json::json!({
+                    "repo": session.did,
+                    "collection": "app.bsky.feed.post",
+                    "record": record,
+                })
+            })
+            .await?;
+        let created = res
+            .json::<BlueskyCreatedRecord>()
+            .await
+            .map_err(|e| MessageError::Transport(e.to_string()))?;
+        Ok(Some(RemoteMessage::Bluesky {
+            uri: created.uri,
+            cid: created.cid,
+        }))
+    }
+
+    async fn delete(&self, remote: &RemoteMessage) -> Result<()> {
+        let RemoteMessage::Bluesky { uri, .. } = remote else {
+            return Err(remote_mismatch(remote));
+        };
+        tracing::info!("delete bluesky post {}", uri);
+        let rkey = uri.rsplit('/').next().unwrap_or_default();
+
+        let client = reqwest::Client::new();
+        self.call(&client, "com.atproto.repo.deleteRecord", |session| {
+            serde_json::json!({
+                "repo": session.did,
+                "collection": "app.bsky.feed.post",
+                "rkey": rkey,
+            })
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EmailConfig {
+    pub host: String,
+    #[serde(default = "EmailConfig::default_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub tls: EmailTls,
+    // e.g. "times-hub <times@example.com>"
+    pub from: String,
+    pub to: Vec<String>,
+    // 省略した場合は本文の 1 行目を件名にする
+    #[serde(default)]
+    pub subject: Option<String>,
+}
+
+impl EmailConfig {
+    fn default_port() -> u16 {
+        587
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailTls {
+    #[default]
+    Starttls,
+    // SMTPS (port 465)
+    Tls,
+    // 暗号化しない. local の SMTP server 向け
+    None,
+}
+
+#[derive(Debug, Clone)]
+pub struct EmailSender {
+    config: EmailConfig,
+    message: lettre::Message,
+    retry: RetryPolicy,
+}
+
+impl EmailSender {
+    const SUBJECT_MAX_CHARS: usize = 60;
+
+    /// `text` は text/plain, `html` は text/html の part になる
+    pub fn new(config: EmailConfig, text: &str, html: &str, retry: &RetryPolicy) -> Result<Self> {
+        use lettre::message::{Mailbox, MultiPart};
+
+        if config.to.is_empty() {
+            anyhow::bail!("to must not be empty");
+        }
+        let subject = match &config.subject {
+            Some(subject) => subject.clone(),
+            None => text
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .chars()
+                .take(Self::SUBJECT_MAX_CHARS)
+                .collect(),
+        };
+        let mut builder = lettre::Message::builder()
+            .from(config.from.parse::<Mailbox>()?)
+            .subject(subject);
+        for to in config.to.iter() {
+            builder = builder.to(to.parse::<Mailbox>()?);
+        }
+        let html = format!("<!DOCTYPE html><html><body>{}</body></html>", html);
+        let message =
+            builder.multipart(MultiPart::alternative_plain_html(text.to_string(), html))?;
+        Ok(Self {
+            config,
+            message,
+            retry: retry.clone(),
+        })
+    }
+
+    fn transport(&self) -> Result<lettre::AsyncSmtpTransport<lettre::Tokio1Executor>> {
+        use lettre::transport::smtp::authentication::Credentials;
+        use lettre::{AsyncSmtpTransport, Tokio1Executor};
+
+        let host = self.config.host.as_str();
+        let mut builder = match self.config.tls {
+            EmailTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+            EmailTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+            EmailTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+        }
+        .port(self.config.port);
+        if let Some(username) = &self.config.username {
+            let password = self.config.password.clone().unwrap_or_default();
+            builder = builder.credentials(Credentials::new(username.clone(), password));
+        }
+        Ok(builder.build())
+    }
+}
+
+#[async_trait]
+impl Sender for EmailSender {
+    async fn send(&self) -> Result<Option<RemoteMessage>> {
+        use lettre::AsyncTransport;
+
+        tracing::info!("send to email");
+        let transport = self.transport()?;
+        let mut attempt = 0;
+        loop {
+            match transport.send(self.message.clone()).await {
+                Ok(_) => return Ok(None),
+                // 5xx の応答は再試行しても変わらない
+                Err(e) if e.is_permanent() => {
+                    return Err(MessageError::Api(e.to_string()).into());
+                }
+                Err(e) if attempt >= self.retry.max_retries => {
+                    return Err(MessageError::Transport(e.to_string()).into());
+                }
+                Err(e) => {
+                    let wait = self.retry.backoff(attempt);
+                    attempt += 1;
+                    tracing::warn!(
+                        "retry sending email ({}/{}) after {:?}: {}",
+                        attempt,
+                        self.retry.max_retries,
+                        wait,
+                        e
+                    );
+                    tokio::time::sleep(wait).await;
+                }
+            }
+        }
+    }
+}
+
+// 任意の HTTP endpoint に送るための設定 (URL は webhook_url)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenericConfig {
+    #[serde(default = "GenericConfig::default_method")]
+    pub method: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    // minijinja の template. `text`, `workspace.name`, `sent_at` などを使える
+    #[serde(default = "GenericConfig::default_body_template")]
+    pub body_template: String,
+    #[serde(default = "GenericConfig::default_content_type")]
+    pub content_type: String,
+}
+
+impl GenericConfig {
+    fn default_method() -> String {
+        "POST".to_string()
+    }
+
+    fn default_body_template() -> String {
+        "{{ text }}".to_string()
+    }
+
+    fn default_content_type() -> String {
+        "text/plain; charset=utf-8".to_string()
+    }
+}
+
+// body template に渡す値
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GenericTemplateContext {
+    pub text: String,
+    pub workspace: GenericTemplateWorkspace,
+    pub sent_at: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GenericTemplateWorkspace {
+    pub id: entity::WorkspaceIdTypeAlias,
+    pub name: String,
+}
+
+/// body template を描画する.
+/// content type が JSON の場合, `{{ text }}` は引用符付きの JSON 文字列として展開される.
+pub fn render_generic_body(
+    template: &str,
+    content_type: &str,
+    context: &GenericTemplateContext,
+) -> Result<String> {
+    let auto_escape = if content_type.contains("json") {
+        minijinja::AutoEscape::Json
+    } else if content_type.contains("html") {
+        minijinja::AutoEscape::Html
+    } else {
+        minijinja::AutoEscape::None
+    };
+    let mut env = minijinja::Environment::new();
+    env.set_auto_escape_callback(move |_| auto_escape);
+    env.add_template("body", template)?;
+    Ok(env.get_template("body")?.render(context)?)
+}
+
+#[derive(Debug, Clone)]
+pub struct GenericSender {
+    url: String,
+    method: reqwest::Method,
+    headers: reqwest::header::HeaderMap,
+    body: String,
+    retry: RetryPolicy,
+}
+
+impl GenericSender {
+    pub fn new(
+        ws: &Workspace,
+        webhook_url: &str,
+        config: GenericConfig,
+        text: &str,
+        retry: &RetryPolicy,
+    ) -> Result<Self> {
+        use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+
+        let method = reqwest::Method::from_bytes(config.method.to_uppercase().as_bytes())?;
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_str(&config.content_type)?);
+        for (name, value) in config.headers.iter() {
+            headers.insert(
+                HeaderName::from_bytes(name.as_bytes())?,
+                HeaderValue::from_str(value)?,
+            );
+        }
+        let context = GenericTemplateContext {
+            text: text.to_string(),
+            workspace: GenericTemplateWorkspace {
+                id: ws.id.to_raw(),
+                name: ws.name.clone(),
+            },
+            sent_at: Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        };
+        let body = render_generic_body(&config.body_template, &config.content_type, &context)?;
+        Ok(Self {
+            url: webhook_url.to_string(),
+            method,
+            headers,
+            body,
+            retry: retry.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Sender for GenericSender {
+    async fn send(&self) -> Result<Option<RemoteMessage>> {
+        tracing::info!("send to generic webhook");
+        let client = reqwest::Client::new();
+        send_with_retry(&self.retry, || {
+            client
+                .request(self.method.clone(), &self.url)
+                .headers(self.headers.clone())
+                .body(self.body.clone())
+        })
+        .await?;
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::axum::extract::Extension;
+    use ::axum::extract::Form;
+    use ::axum::extract::Path;
+    use ::axum::extract::RawQuery;
+    use ::axum::http::Method;
+    use ::axum::routing::{get, patch, post, put};
+    use ::axum::Json;
+    use ::axum::Router;
+    use ::http::HeaderMap;
+    use ::std::net::SocketAddr;
+    use ::std::sync::atomic::{AtomicUsize, Ordering};
+    use ::std::sync::Mutex;
+
+    // テスト用 server が受け取ったリクエストの記録
+    type Requests = Arc<Mutex<Vec<(String, String)>>>;
+
+    async fn spawn_server(app: Router) -> SocketAddr {
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    fn workspace(
+        ws_type: WorkspaceType,
+        webhook_url: &str,
+        config: serde_json::Value,
+    ) -> Workspace {
+        Workspace {
+            id: entity::WorkspaceId::new(1),
+            name: "test workspace".to_string(),
+            ws_type,
+            webhook_url: webhook_url.to_string(),
+            config,
+            profile: entity::Profile::default(),
+        }
+    }
+
+    #[test]
+    fn get_sender_validates_config() {
+        let config = DeliveryConfig::default();
+        let ws = workspace(WorkspaceType::Slack, "", serde_json::json!({}));
+        assert!(matches!(
+            get_sender(&ws, "", &config),
+            Err(MessageError::InvalidConfig(WorkspaceType::Slack, _))
+        ));
+
+        let ws = workspace(
+            WorkspaceType::Telegram,
+            "",
+            serde_json::json!({"bot_token": "123:abc"}),
+        );
+        assert!(matches!(
+            get_sender(&ws, "", &config),
+            Err(MessageError::InvalidConfig(WorkspaceType::Telegram, _))
+        ));
+
+        let ws = workspace(
+            WorkspaceType::Telegram,
+            "",
+            serde_json::json!({"bot_token": "123:abc", "chat_id": "@times", "parse_mode": "HTML"}),
+        );
+        assert!(get_sender(&ws, "", &config).is_ok());
+    }
+
+    #[test]
+    fn telegram_payload_uses_rendered_text() {
+        let mut config = TelegramConfig {
+            bot_token: "123:abc".to_string(),
+            chat_id: TelegramChatId::Id(-100),
+            parse_mode: Some(TelegramParseMode::MarkdownV2),
+            api_url: TelegramConfig::default_api_url(),
+        };
+        let text = Document::parse("1+1=2 (done). *italic*").render(Markup::TelegramMarkdownV2);
+        let payload = TelegramMessagePayload::new(&config, &text);
+        assert_eq!(payload.text, r"1\+1\=2 \(done\)\. _italic_");
+
+        config.parse_mode = Some(TelegramParseMode::Html);
+        let text = Document::parse("<b>a & b</b>").render(Markup::TelegramHtml);
+        let payload = TelegramMessagePayload::new(&config, &text);
+        assert_eq!(payload.text, "&lt;b&gt;a &amp; b&lt;/b&gt;");
+        assert_eq!(
+            serde_json::to_value(&payload).unwrap(),
+            serde_json::json!({"chat_id": -100, "text": payload.text, "parse_mode": "HTML"})
+        );
+    }
+
+    #[test]
+    fn teams_payload_is_adaptive_card() {
+        assert_eq!(
+            serde_json::to_value(TeamsMessagePayload::new("**hello**")).unwrap(),
+            serde_json::json!({
+                "type": "message",
+                "attachments": [{
+                    "contentType": "application/vnd.microsoft.card.adaptive",
+                    "content": {
+                        "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+                        "type": "AdaptiveCard",
+                        "version": "1.4",
+                        "body": [{"type": "TextBlock", "text": "**hello**", "wrap": true}],
+                    },
+                }],
+            })
+        );
+    }
+
+    // Teams の webhook. Workflows の webhook は 202 を返す
+    async fn teams_webhook(Extension(requests): Extension<Requests>, body: String) -> StatusCode {
+        requests.lock().unwrap().push(("teams".to_string(), body));
+        StatusCode::ACCEPTED
+    }
+
+    #[tokio::test]
+    async fn teams_sender_posts_adaptive_card() {
+        let requests = Requests::default();
+        let app = Router::new()
+            .route("/teams", post(teams_webhook))
+            .layer(Extension(requests.clone()));
+        let addr = spawn_server(app).await;
+        let config = DeliveryConfig::default();
+
+        let ws = workspace(
+            WorkspaceType::Teams,
+            &format!("http://{}/teams", addr),
+            serde_json::json!({}),
+        );
+        let sender = get_sender(&ws, "hello *times*", &config).unwrap();
+        assert_eq!(sender.send().await.expect("failed to send"), None);
+
+        // 送信先が拒否した場合は失敗にする
+        let ws = workspace(
+            WorkspaceType::Teams,
+            &format!("http://{}/closed", addr),
+            serde_json::json!({}),
+        );
+        let e = get_sender(&ws, "hello", &config)
+            .unwrap()
+            .send()
+            .await
+            .expect_err("unknown webhook should fail");
+        assert!(matches!(
+            e.downcast_ref::<MessageError>(),
+            Some(MessageError::Rejected(StatusCode::NOT_FOUND))
+        ));
+
+        // webhook_url がない場合は登録できない
+        let ws = workspace(WorkspaceType::Teams, "", serde_json::json!({}));
+        assert!(matches!(
+            get_sender(&ws, "", &config),
+            Err(MessageError::InvalidConfig(WorkspaceType::Teams, _))
+        ));
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        let body: serde_json::Value = serde_json::from_str(&requests[0].1).unwrap();
+        assert_eq!(
+            body["attachments"][0]["content"]["body"][0]["text"],
+            "hello *times*"
+        );
+    }
+
+    #[test]
+    fn mattermost_and_rocketchat_payloads_use_config() {
+        let profile = entity::Profile::default();
+        let config = MattermostConfig {
+            channel: Some("town-square".to_string()),
+            icon_emoji: Some("robot_face".to_string()),
+        };
+        assert_eq!(
+            serde_json::to_value(MattermostMessagePayload::new("hello", &config, &profile))
+                .unwrap(),
+            serde_json::json!({"text": "hello", "icon_emoji": "robot_face", "channel": "town-square"})
+        );
+        let config = RocketChatConfig {
+            channel: Some("#general".to_string()),
+            emoji: Some(":robot:".to_string()),
+        };
+        assert_eq!(
+            serde_json::to_value(RocketChatMessagePayload::new("hello", &config, &profile))
+                .unwrap(),
+            serde_json::json!({"text": "hello", "emoji": ":robot:", "channel": "#general"})
+        );
+
+        // avatar_url がある場合は絵文字のアイコンを送らない
+        let profile = entity::Profile {
+            display_name: Some("bot".to_string()),
+            avatar_url: Some("https://example.com/bot.png".to_string()),
+        };
+        let payload = MattermostMessagePayload::new(
+            "hello",
+            &MattermostConfig {
+                icon_emoji: Some("robot_face".to_string()),
+                ..Default::default()
+            },
+            &profile,
+        );
+        assert_eq!(
+            serde_json::to_value(payload).unwrap(),
+            serde_json::json!({"text": "hello", "username": "bot", "icon_url": "https://example.com/bot.png"})
+        );
+        let payload = RocketChatMessagePayload::new(
+            "hello",
+            &RocketChatConfig {
+                emoji: Some(":robot:".to_string()),
+                ..Default::default()
+            },
+            &profile,
+        );
+        assert_eq!(
+            serde_json::to_value(payload).unwrap(),
+            serde_json::json!({"text": "hello", "alias": "bot", "avatar": "https://example.com/bot.png"})
+        );
+    }
+
+    // channel が "#closed" の場合は, 200 で success: false を返す
+    async fn rocketchat_webhook(
+        Extension(requests): Extension<Requests>,
+        Json(payload): Json<serde_json::Value>,
+    ) -> Json<serde_json::Value> {
+        let channel = payload["channel"].as_str().unwrap_or_default().to_string();
+        requests
+            .lock()
+            .unwrap()
+            .push((channel.clone(), payload.to_string()));
+        match channel.as_str() {
+            "#closed" => Json(serde_json::json!({"success": false, "error": "room is closed"})),
+            _ => Json(serde_json::json!({"success": true})),
+        }
+    }
+
+    #[tokio::test]
+    async fn mattermost_and_rocketchat_senders_post_config() {
+        let requests = Requests::default();
+        let app = Router::new()
+            .route("/hooks/:id", post(rocketchat_webhook))
+            .layer(Extension(requests.clone()));
+        let addr = spawn_server(app).await;
+        let url = format!("http://{}/hooks/1", addr);
+        let config = DeliveryConfig::default();
+
+        let ws = workspace(
+            WorkspaceType::Mattermost,
+            &url,
+            serde_json::json!({"channel": "town-square", "icon_emoji": "robot_face"}),
+        );
+        let sender = get_sender(&ws, "hello", &config).unwrap();
+        assert_eq!(sender.send().await.expect("failed to send"), None);
+
+        let ws = workspace(
+            WorkspaceType::RocketChat,
+            &url,
+            serde_json::json!({"channel": "#general", "emoji": ":robot:"}),
+        );
+        let sender = get_sender(&ws, "hello", &config).unwrap();
+        assert_eq!(sender.send().await.expect("failed to send"), None);
+
+        // Rocket.Chat が success: false を返した場合は失敗にする
+        let ws = workspace(
+            WorkspaceType::RocketChat,
+            &url,
+            serde_json::json!({"channel": "#closed"}),
+        );
+        let e = get_sender(&ws, "hello", &config)
+            .unwrap()
+            .send()
+            .await
+            .expect_err("closed room should fail");
+        assert!(matches!(
+            e.downcast_ref::<MessageError>(),
+            Some(MessageError::Api(error)) if error == "room is closed"
+        ));
+
+        let requests = requests.lock().unwrap();
+        let channels = requests.iter().map(|(c, _)| c.as_str()).collect::<Vec<_>>();
+        assert_eq!(channels, vec!["town-square", "#general", "#closed"]);
+        let body: serde_json::Value = serde_json::from_str(&requests[0].1).unwrap();
+        assert_eq!(body["icon_emoji"], "robot_face");
+        let body: serde_json::Value = serde_json::from_str(&requests[1].1).unwrap();
+        assert_eq!(body["emoji"], ":robot:");
+
+        // config の型が違う場合は登録できない
+        let ws = workspace(
+            WorkspaceType::Mattermost,
+            &url,
+            serde_json::json!({"channel": 1}),
+        );
+        assert!(matches!(
+            get_sender(&ws, "", &config),
+            Err(MessageError::InvalidConfig(WorkspaceType::Mattermost, _))
+        ));
+    }
+
+    // 1 回目は 502 を返し, 2 回目以降は 200 を返す
+    async fn matrix_send(
+        Extension(requests): Extension<Requests>,
+        Path((room_id, txn_id)): Path<(String, String)>,
+        headers: HeaderMap,
+    ) -> (StatusCode, Json<serde_json::Value>) {
+        let auth = headers[http::header::AUTHORIZATION].to_str().unwrap();
+        let mut requests = requests.lock().unwrap();
+        requests.push((format!("{} {}", room_id, auth), txn_id));
+        if requests.len() == 1 {
+            (StatusCode::BAD_GATEWAY, Json(serde_json::json!({})))
+        } else {
+            (
+                StatusCode::OK,
+                Json(serde_json::json!({"event_id": "$event"})),
+            )
+        }
+    }
+
+    #[tokio::test]
+    async fn matrix_sender_reuses_transaction_id() {
+        let requests = Requests::default();
+        let app = Router::new()
+            .route(
+                "/_matrix/client/v3/rooms/:room_id/send/m.room.message/:txn_id",
+                put(matrix_send),
+            )
+            .layer(Extension(requests.clone()));
+        let addr = spawn_server(app).await;
+
+        let config = MatrixConfig {
+            homeserver_url: format!("http://{}/", addr),
+            room_id: "!times:example.com".to_string(),
+            access_token: "secret".to_string(),
+        };
+        let retry = RetryPolicy {
+            base_backoff: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        };
+        let sender = MatrixSender::new(config, "hello", "<p>hello</p>", &retry).unwrap();
+        let remote = sender.send().await.expect("failed to send");
+        assert_eq!(
+            remote,
+            Some(RemoteMessage::Matrix {
+                event_id: "$event".to_string()
+            })
+        );
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].0, "!times:example.com Bearer secret");
+        assert_eq!(requests[0].1, requests[1].1);
+    }
+
+    #[tokio::test]
+    async fn delivery_retry_reuses_transaction_id() {
+        use crate::message::repository::test_utils::MessageRepositoryForMemory;
+        use crate::workspace::repository::test_utils::WorkspaceRepositoryForMemory;
+        use crate::workspace::service::CreateWorkspacePayload;
+
+        let requests = Requests::default();
+        let app = Router::new()
+            .route(
+                "/_matrix/client/v3/rooms/:room_id/send/m.room.message/:txn_id",
+                put(matrix_send),
+            )
+            .layer(Extension(requests.clone()));
+        let addr = spawn_server(app).await;
+
+        let repo = Arc::new(WorkspaceRepositoryForMemory::new());
+        repo.create(CreateWorkspacePayload {
+            name: "matrix".to_string(),
+            ws_type: "matrix".to_string(),
+            webhook_url: String::new(),
+            config: serde_json::json!({
+                "homeserver_url": format!("http://{}/", addr),
+                "room_id": "!times:example.com",
+                "access_token": "secret",
+            }),
+            display_name: None,
+            avatar_url: None,
+        })
+        .await
+        .unwrap();
+        let outbox = Arc::new(MessageRepositoryForMemory::new());
+        let mut config = DeliveryConfig::default();
+        config.retry.max_retries = 0;
+        config.outbox.retry.base_backoff = Duration::ZERO;
+
+        // outbox からの再送でも, 最初の送信と同じ transaction id を使う
+        let res = send_message(
+            repo.clone(),
+            outbox.clone(),
+            &config,
+            vec![1],
+            NewMessage::new("hello"),
+        )
+        .await
+        .unwrap();
+        assert!(res.results[0].will_retry);
+        outbox::drain(repo.as_ref(), outbox.as_ref(), &config)
+            .await
+            .unwrap();
+
+        let txn_ids = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, txn_id)| txn_id.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(txn_ids, vec!["delivery-1-0", "delivery-1-0"]);
+    }
+
+    #[test]
+    fn google_chat_payload_uses_thread_and_card() {
+        let config = GoogleChatConfig::default();
+        assert_eq!(
+            serde_json::to_value(GoogleChatMessagePayload::new(&config, "hello")).unwrap(),
+            serde_json::json!({"text": "hello"})
+        );
+
+        let config = GoogleChatConfig {
+            thread_key: Some("times".to_string()),
+            card: true,
+        };
+        let payload = GoogleChatMessagePayload::new(&config, "a < b");
+        assert_eq!(payload.text, None);
+        assert_eq!(
+            payload.cards_v2[0]["card"]["sections"][0]["widgets"][0]["textParagraph"]["text"],
+            "a &lt; b"
+        );
+        assert_eq!(
+            serde_json::to_value(&payload).unwrap()["thread"],
+            serde_json::json!({"threadKey": "times"})
+        );
+
+        let sender = GoogleChatSender::new(
+            "https://chat.googleapis.com/v1/spaces/AAA/messages?key=k&token=t",
+            config,
+            "hello",
+            &RetryPolicy::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            sender.url.query(),
+            Some("key=k&token=t&messageReplyOption=REPLY_MESSAGE_FALLBACK_TO_NEW_THREAD")
+        );
+    }
+
+    async fn zulip_messages(
+        Extension(requests): Extension<Requests>,
+        headers: HeaderMap,
+        Form(payload): Form<ZulipMessagePayload>,
+    ) -> Json<serde_json::Value> {
+        let auth = headers[http::header::AUTHORIZATION].to_str().unwrap();
+        requests.lock().unwrap().push((
+            auth.to_string(),
+            format!("{} > {}: {}", payload.to, payload.topic, payload.content),
+        ));
+        Json(serde_json::json!({"result": "success", "msg": "", "id": 42}))
+    }
+
+    #[tokio::test]
+    async fn zulip_sender_posts_to_stream_topic() {
+        let requests = Requests::default();
+        let app = Router::new()
+            .route("/api/v1/messages", post(zulip_messages))
+            .layer(Extension(requests.clone()));
+        let addr = spawn_server(app).await;
+
+        let ws = workspace(
+            WorkspaceType::Zulip,
+            "",
+            serde_json::json!({
+                "site_url": format!("http://{}/", addr),
+                "bot_email": "times-bot@example.com",
+                "api_key": "secret",
+                "stream": "times",
+                "topic": "pollenjp",
+            }),
+        );
+        let sender = get_sender(&ws, "hello", &DeliveryConfig::default()).unwrap();
+        sender.send().await.expect("failed to send");
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(
+            requests[0],
+            (
+                // base64("times-bot@example.com:secret")
+                "Basic dGltZXMtYm90QGV4YW1wbGUuY29tOnNlY3JldA==".to_string(),
+                "times > pollenjp: hello".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn fediverse_post_truncates() {
+        let text = "aaaa bbbb cccc dddd";
+        assert_eq!(fediverse_post(text, 100), text);
+        assert_eq!(fediverse_post(text, 8), "aaaa bb…");
+        assert_eq!(fediverse_post("あいうえおか", 5), "あいうえ…");
+    }
+
+    #[test]
+    fn fediverse_and_bluesky_follow_long_text() {
+        let config = DeliveryConfig::default();
+        let text = "aaaa bbbb cccc dddd";
+        let misskey = |overflow: &str, long_text: &str| {
+            workspace(
+                WorkspaceType::Misskey,
+                "",
+                serde_json::json!({
+                    "instance_url": "https://misskey.example.com",
+                    "access_token": "secret",
+                    "overflow": overflow,
+                    "max_chars": 10,
+                    "long_text": long_text,
+                }),
+            )
+        };
+        // split の場合は overflow に従う
+        assert_eq!(
+            fit_text(&misskey("truncate", "split"), text, &config).unwrap(),
+            vec![text]
+        );
+        assert_eq!(
+            fit_text(&misskey("thread", "split"), text, &config).unwrap(),
+            vec!["aaaa bbbb", "cccc dddd"]
+        );
+        for overflow in ["truncate", "thread"] {
+            assert!(matches!(
+                fit_text(&misskey(overflow, "reject"), text, &config),
+                Err(MessageError::TooLong(_))
+            ));
+        }
+
+        let bluesky = |long_text: &str| {
+            workspace(
+                WorkspaceType::Bluesky,
+                "",
+                serde_json::json!({
+                    "handle": "times.bsky.social",
+                    "app_password": "xxxx-xxxx-xxxx-xxxx",
+                    "long_text": long_text,
+                }),
+            )
+        };
+        let long = "あ".repeat(301);
+        assert_eq!(
+            fit_text(&bluesky("split"), &long, &config).unwrap(),
+            vec![long.clone()]
+        );
+        assert!(matches!(
+            fit_text(&bluesky("reject"), &long, &config),
+            Err(MessageError::TooLong(_))
+        ));
+        assert!(fit_text(&bluesky("reject"), &long[3..], &config).is_ok());
+    }
+
+    async fn mastodon_statuses(
+        Extension(requests): Extension<Requests>,
+        headers: HeaderMap,
+        Json(payload): Json<MastodonStatusPayload>,
+    ) -> Json<serde_json::Value> {
+        let key = headers["Idempotency-Key"].to_str().unwrap();
+        let mut requests = requests.lock().unwrap();
+        requests.push((key.to_string(), serde_json::to_string(&payload).unwrap()));
+        Json(serde_json::json!({"id": requests.len().to_string()}))
+    }
+
+    #[tokio::test]
+    async fn mastodon_threads_long_text() {
+        let requests = Requests::default();
+        let app = Router::new()
+            .route("/api/v1/statuses", post(mastodon_statuses))
+            .layer(Extension(requests.clone()));
+        let addr = spawn_server(app).await;
+
+        let ws = workspace(
+            WorkspaceType::Mastodon,
+            "",
+            serde_json::json!({
+                "instance_url": format!("http://{}", addr),
+                "access_token": "secret",
+                "visibility": "followers",
+                "content_warning": "times",
+                "overflow": "thread",
+                "max_chars": 10,
+            }),
+        );
+        let config = DeliveryConfig::default();
+        let mut sent = vec![];
+        send_chunks(
+            &ws,
+            "aaaa bbbb cccc dddd",
+            None,
+            None,
+            &config,
+            "delivery-1",
+            &mut sent,
+        )
+        .await
+        .expect("failed to send");
+        assert_eq!(
+            sent,
+            vec![
+                Some(RemoteMessage::Mastodon {
+                    id: "1".to_string()
+                }),
+                Some(RemoteMessage::Mastodon {
+                    id: "2".to_string()
+                }),
+            ]
+        );
+
+        let requests = requests.lock().unwrap();
+        let payloads = requests
+            .iter()
+            .map(|(_, body)| serde_json::from_str::<MastodonStatusPayload>(body).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            payloads,
+            vec![
+                MastodonStatusPayload {
+                    status: "aaaa bbbb".to_string(),
+                    visibility: "private".to_string(),
+                    spoiler_text: Some("times".to_string()),
+                    in_reply_to_id: None,
+                },
+                // 前の status への返信にする
+                MastodonStatusPayload {
+                    status: "cccc dddd".to_string(),
+                    visibility: "private".to_string(),
+                    spoiler_text: Some("times".to_string()),
+                    in_reply_to_id: Some("1".to_string()),
+                },
+            ]
+        );
+        let keys = requests
+            .iter()
+            .map(|(key, _)| key.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec!["delivery-1-0", "delivery-1-1"]);
+    }
+
+    async fn misskey_notes(
+        Extension(requests): Extension<Requests>,
+        Path(method): Path<String>,
+        body: String,
+    ) -> Json<serde_json::Value> {
+        let mut requests = requests.lock().unwrap();
+        requests.push((method, body));
+        Json(serde_json::json!({"createdNote": {"id": format!("note{}", requests.len())}}))
+    }
+
+    #[tokio::test]
+    async fn misskey_resend_skips_posted_notes() {
+        let requests = Requests::default();
+        let app = Router::new()
+            .route("/api/notes/:method", post(misskey_notes))
+            .layer(Extension(requests.clone()));
+        let addr = spawn_server(app).await;
+
+        let ws = workspace(
+            WorkspaceType::Misskey,
+            "",
+            serde_json::json!({
+                "instance_url": format!("http://{}", addr),
+                "access_token": "secret",
+                "overflow": "thread",
+                "max_chars": 10,
+            }),
+        );
+        let config = DeliveryConfig::default();
+        // 前回の試行で最初の note だけ投稿できていた
+        let mut sent = vec![Some(RemoteMessage::Misskey {
+            id: "note0".to_string(),
+        })];
+        send_chunks(
+            &ws,
+            "aaaa bbbb cccc dddd",
+            None,
+            None,
+            &config,
+            "delivery-1",
+            &mut sent,
+        )
+        .await
+        .expect("failed to send");
+        assert_eq!(
+            sent,
+            vec![
+                Some(RemoteMessage::Misskey {
+                    id: "note0".to_string()
+                }),
+                Some(RemoteMessage::Misskey {
+                    id: "note1".to_string()
+                }),
+            ]
+        );
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        let body: serde_json::Value = serde_json::from_str(&requests[0].1).unwrap();
+        assert_eq!(body["text"], "cccc dddd");
+        assert_eq!(body["replyId"], "note0");
+    }
+
+    async fn fediverse_record(
+        Extension(requests): Extension<Requests>,
+        method: http::Method,
+        uri: http::Uri,
+        body: String,
+    ) -> Json<serde_json::Value> {
+        requests
+            .lock()
+            .unwrap()
+            .push((format!("{} {}", method, uri.path()), body));
+        Json(serde_json::json!({}))
+    }
+
+    #[tokio::test]
+    async fn fediverse_edit_and_delete() {
+        let requests = Requests::default();
+        let app = Router::new()
+            .route(
+                "/api/v1/statuses/:id",
+                put(fediverse_record).delete(fediverse_record),
+            )
+            .route("/api/notes/delete", post(fediverse_record))
+            .layer(Extension(requests.clone()));
+        let addr = spawn_server(app).await;
+
+        let config = DeliveryConfig::default();
+        let mastodon = workspace(
+            WorkspaceType::Mastodon,
+            "",
+            serde_json::json!({
+                "instance_url": format!("http://{}", addr),
+                "access_token": "secret",
+            }),
+        );
+        let status = RemoteMessage::Mastodon {
+            id: "1".to_string(),
+        };
+        let sender = get_sender(&mastodon, "edited", &config).unwrap();
+        sender.edit(&status).await.expect("failed to edit");
+        sender.delete(&status).await.expect("failed to delete");
+
+        let misskey = workspace(
+            WorkspaceType::Misskey,
+            "",
+            serde_json::json!({
+                "instance_url": format!("http://{}", addr),
+                "access_token": "secret",
+            }),
+        );
+        let note = RemoteMessage::Misskey {
+            id: "note1".to_string(),
+        };
+        let sender = get_sender(&misskey, "", &config).unwrap();
+        sender.delete(&note).await.expect("failed to delete");
+        // Misskey の note は編集できない
+        assert!(matches!(
+            sender
+                .edit(&note)
+                .await
+                .unwrap_err()
+                .downcast_ref::<MessageError>(),
+            Some(MessageError::UnsupportedOperation(_))
+        ));
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(
+            requests.iter().map(|(m, _)| m.as_str()).collect::<Vec<_>>(),
+            vec![
+                "PUT /api/v1/statuses/1",
+                "DELETE /api/v1/statuses/1",
+                "POST /api/notes/delete",
+            ]
+        );
+        let edit: serde_json::Value = serde_json::from_str(&requests[0].1).unwrap();
+        assert_eq!(edit["status"], "edited");
+        let delete: serde_json::Value = serde_json::from_str(&requests[2].1).unwrap();
+        assert_eq!(
+            delete,
+            serde_json::json!({"i": "secret", "noteId": "note1"})
+        );
+    }
+
+    #[test]
+    fn bluesky_facets_use_byte_offsets() {
+        let text = "日本語 https://example.com/a. cc @alice.bsky.social !";
+        let candidates = bluesky_facet_candidates(text);
+        assert_eq!(
+            candidates
+                .iter()
+                .map(|(index, candidate)| (&text[index.byte_start..index.byte_end], candidate))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "https://example.com/a",
+                    &BlueskyFacetCandidate::Link("https://example.com/a".to_string())
+                ),
+                (
+                    "@alice.bsky.social",
+                    &BlueskyFacetCandidate::Mention("alice.bsky.social".to_string())
+                ),
+            ]
+        );
+
+        // 結合文字を含む絵文字も 1 grapheme として数える
+        let family = "👨‍👩‍👧";
+        assert_eq!(truncate_graphemes(&family.repeat(3), 3), family.repeat(3));
+        assert_eq!(
+            truncate_graphemes(&family.repeat(4), 3),
+            format!("{}…", family.repeat(2))
+        );
+    }
+
+    async fn bluesky_xrpc(
+        Extension(requests): Extension<Requests>,
+        Path(method): Path<String>,
+        headers: HeaderMap,
+        body: String,
+    ) -> (StatusCode, Json<serde_json::Value>) {
+        let auth = headers
+            .get(http::header::AUTHORIZATION)
+            .map(|v| v.to_str().unwrap().to_string())
+            .unwrap_or_default();
+        let mut requests = requests.lock().unwrap();
+        requests.push((method.clone(), body));
+        let sessions = requests
+            .iter()
+            .filter(|(m, _)| m == "com.atproto.server.createSession")
+            .count();
+        match method.as_str() {
+            "com.atproto.server.createSession" => (
+                StatusCode::OK,
+                Json(serde_json::json!({
+                    "accessJwt": format!("access-{}", sessions),
+                    "refreshJwt": "refresh",
+                    "did": "did:plc:times",
+                    "handle": "times.bsky.social",
+                })),
+            ),
+            "com.atproto.identity.resolveHandle" => (
+                StatusCode::OK,
+                Json(serde_json::json!({"did": "did:plc:alice"})),
+            ),
+            "com.atproto.repo.createRecord" if requests.last().unwrap().1.contains("invalid") => (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"error": "InvalidRequest"})),
+            ),
+            // 最初の session は 3 回目の post で期限切れになる
+            "com.atproto.repo.createRecord" if auth == "Bearer access-1" && requests.len() > 6 => (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"error": "ExpiredToken"})),
+            ),
+            "com.atproto.repo.createRecord" => (
+                StatusCode::OK,
+                Json(
+                    serde_json::json!({"uri": "at://did:plc:times/app.bsky.feed.post/1", "cid": "c"}),
+                ),
+            ),
+            "com.atproto.repo.deleteRecord" => (StatusCode::OK, Json(serde_json::json!({}))),
+            _ => (StatusCode::NOT_FOUND, Json(serde_json::json!({}))),
+        }
+    }
+
+    #[tokio::test]
+    async fn bluesky_sender_caches_session() {
+        let requests = Requests::default();
+        let app = Router::new()
+            .route("/xrpc/:method", get(bluesky_xrpc).post(bluesky_xrpc))
+            .layer(Extension(requests.clone()));
+        let addr = spawn_server(app).await;
+
+        let ws = workspace(
+            WorkspaceType::Bluesky,
+            "",
+            serde_json::json!({
+                "service_url": format!("http://{}", addr),
+                "handle": "times.bsky.social",
+                "app_password": "xxxx-xxxx-xxxx-xxxx",
+            }),
+        );
+        let config = DeliveryConfig::default();
+        let mut remotes = vec![];
+        for _ in 0..3 {
+            let sender = get_sender(&ws, "hi @alice.bsky.social", &config).unwrap();
+            remotes.push(sender.send().await.expect("failed to send"));
+        }
+        let remote = RemoteMessage::Bluesky {
+            uri: "at://did:plc:times/app.bsky.feed.post/1".to_string(),
+            cid: "c".to_string(),
+        };
+        assert_eq!(remotes, vec![Some(remote.clone()); 3]);
+        // ExpiredToken 以外の 400 では session を作り直さない
+        let e = get_sender(&ws, "invalid", &config)
+            .unwrap()
+            .send()
+            .await
+            .expect_err("invalid record should fail");
+        assert!(matches!(
+            e.downcast_ref::<MessageError>(),
+            Some(MessageError::Rejected(StatusCode::BAD_REQUEST))
+        ));
+        get_sender(&ws, "", &config)
+            .unwrap()
+            .delete(&remote)
+            .await
+            .expect("failed to delete");
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(
+            requests.iter().map(|(m, _)| m.as_str()).collect::<Vec<_>>(),
+            vec![
+                "com.atproto.identity.resolveHandle",
+                "com.atproto.server.createSession",
+                "com.atproto.repo.createRecord",
+                // session を使い回す
+                "com.atproto.identity.resolveHandle",
+                "com.atproto.repo.createRecord",
+                // 期限切れなので作り直す
+                "com.atproto.identity.resolveHandle",
+                "com.atproto.repo.createRecord",
+                "com.atproto.server.createSession",
+                "com.atproto.repo.createRecord",
+                "com.atproto.repo.createRecord",
+                "com.atproto.repo.deleteRecord",
+            ]
+        );
+        let delete: serde_json::Value = serde_json::from_str(&requests[10].1).unwrap();
+        assert_eq!(
+            delete,
+            serde_json::json!({
+                "repo": "did:plc:times",
+                "collection": "app.bsky.feed.post",
+                "rkey": "1",
+            })
+        );
+        let record: serde_json::Value = serde_json::from_str(&requests[2].1).unwrap();
+        assert_eq!(record["repo"], "did:plc:times");
+        assert_eq!(record["record"]["text"], "hi @alice.bsky.social");
+        assert_eq!(
+            record["record"]["facets"],
+            serde_json::json!([{
+                "index": {"byteStart": 3, "byteEnd": 21},
+                "features": [{"$type": "app.bsky.richtext.facet#mention", "did": "did:plc:alice"}],
+            }])
+        );
+    }
+
+    // 受け取った DATA を記録するだけの SMTP server
+    async fn spawn_smtp_sink(requests: Requests) -> SocketAddr {
+        use ::tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            writer.write_all(b"220 localhost\r\n").await.unwrap();
+            let mut commands = vec![];
+            while let Ok(Some(line)) = lines.next_line().await {
+                let reply: &[u8] = match line.to_uppercase().as_str() {
+                    l if l.starts_with("EHLO") => b"250 localhost\r\n",
+                    "DATA" => {
+                        writer.write_all(b"354 go ahead\r\n").await.unwrap();
+                        let mut data = vec![];
+                        while let Ok(Some(line)) = lines.next_line().await {
+                            if line == "." {
+                                break;
+                            }
+                            data.push(line);
+                        }
+                        requests
+                            .lock()
+                            .unwrap()
+                            .push((commands.join("\n"), data.join("\n")));
+                        b"250 queued\r\n"
+                    }
+                    "QUIT" => {
+                        writer.write_all(b"221 bye\r\n").await.unwrap();
+                        break;
+                    }
+                    _ => {
+                        commands.push(line.clone());
+                        b"250 ok\r\n"
+                    }
+                };
+                writer.write_all(reply).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn email_sender_sends_multipart() {
+        let requests = Requests::default();
+        let addr = spawn_smtp_sink(requests.clone()).await;
+
+        let ws = workspace(
+            WorkspaceType::Email,
+            "",
+            serde_json::json!({
+                "host": addr.ip().to_string(),
+                "port": addr.port(),
+                "tls": "none",
+                "from": "times-hub <times@example.com>",
+                "to": ["alice@example.com", "bob@example.com"],
+            }),
+        );
+        let sender = get_sender(&ws, "daily times\n1 < 2", &DeliveryConfig::default()).unwrap();
+        sender.send().await.expect("failed to send");
+
+        let requests = requests.lock().unwrap();
+        let (commands, data) = &requests[0];
+        assert_eq!(
+            commands,
+            "MAIL FROM:<times@example.com>\nRCPT TO:<alice@example.com>\nRCPT TO:<bob@example.com>"
+        );
+        assert!(data.contains("Subject: daily times"));
+        assert!(data.contains("multipart/alternative"));
+        assert!(data.contains("Content-Type: text/plain"));
+        assert!(data.contains("Content-Type: text/html"));
+        assert!(data.contains("<p>daily times<br />"));
+        assert!(data.contains("1 &lt; 2</p>"));
+
+        let ws = workspace(
+            WorkspaceType::Email,
+            "",
+            serde_json::json!({"host": "localhost", "from": "times@example.com", "to": ["not an address"]}),
+        );
+        assert!(matches!(
+            get_sender(&ws, "", &DeliveryConfig::default()),
+            Err(MessageError::InvalidConfig(WorkspaceType::Email, _))
+        ));
+    }
+
+    #[test]
+    fn generic_body_escapes_by_content_type() {
+        let context = GenericTemplateContext {
+            text: "say \"hi\" <b>".to_string(),
+            workspace: GenericTemplateWorkspace {
+                id: 1,
+                name: "times".to_string(),
+            },
+            sent_at: "2023-07-01T00:00:00Z".to_string(),
+        };
+        let body = render_generic_body(
+            r#"{"message": {{ text }}, "title": {{ workspace.name }}, "at": {{ sent_at }}}"#,
+            "application/json",
+            &context,
+        )
+        .unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({"message": "say \"hi\" <b>", "title": "times", "at": "2023-07-01T00:00:00Z"})
+        );
+
+        let body = render_generic_body("{{ text }}", "text/plain", &context).unwrap();
+        assert_eq!(body, "say \"hi\" <b>");
+
+        assert!(render_generic_body("{{ text ", "text/plain", &context).is_err());
+    }
+
+    async fn generic_hook(
+        Extension(requests): Extension<Requests>,
+        Path(topic): Path<String>,
+        headers: HeaderMap,
+        body: String,
+    ) -> StatusCode {
+        let title = headers["title"].to_str().unwrap();
+        let content_type = headers[http::header::CONTENT_TYPE].to_str().unwrap();
+        requests
+            .lock()
+            .unwrap()
+            .push((format!("{} {} {}", topic, title, content_type), body));
+        StatusCode::OK
+    }
+
+    #[tokio::test]
+    async fn generic_sender_sends_rendered_body() {
+        let requests = Requests::default();
+        let app = Router::new()
+            .route("/:topic", put(generic_hook))
+            .layer(Extension(requests.clone()));
+        let addr = spawn_server(app).await;
+
+        // ntfy 風の endpoint
+        let ws = workspace(
+            WorkspaceType::Generic,
+            &format!("http://{}/times", addr),
+            serde_json::json!({
+                "method": "put",
+                "headers": {"Title": "times-hub"},
+                "body_template": "[{{ workspace.name }}] {{ text }}",
+            }),
+        );
+        let sender = get_sender(&ws, "hello", &DeliveryConfig::default()).unwrap();
+        sender.send().await.expect("failed to send");
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(
+            requests[0],
+            (
+                "times times-hub text/plain; charset=utf-8".to_string(),
+                "[test workspace] hello".to_string()
+            )
+        );
+
+        let ws = workspace(
+            WorkspaceType::Generic,
+            "http://localhost",
+            serde_json::json!({"headers": {"bad header": "x"}}),
+        );
+        assert!(matches!(
+            get_sender(&ws, "", &DeliveryConfig::default()),
+            Err(MessageError::InvalidConfig(WorkspaceType::Generic, _))
+        ));
+    }
+
+    async fn slack_post_message(
+        Extension(requests): Extension<Requests>,
+        headers: HeaderMap,
+        Json(payload): Json<SlackPostMessagePayload>,
+    ) -> Json<serde_json::Value> {
+        let auth = headers[http::header::AUTHORIZATION].to_str().unwrap();
+        requests
+            .lock()
+            .unwrap()
+            .push((auth.to_string(), serde_json::to_string(&payload).unwrap()));
+        if payload.channel == "C0UNKNOWN" {
+            Json(serde_json::json!({"ok": false, "error": "channel_not_found"}))
+        } else {
+            Json(
+                serde_json::json!({"ok": true, "channel": payload.channel, "ts": "1688000000.000100"}),
+            )
+        }
+    }
+
+    #[tokio::test]
+    async fn slack_bot_sender_posts_message() {
+        let requests = Requests::default();
+        let app = Router::new()
+            .route("/chat.postMessage", post(slack_post_message))
+            .layer(Extension(requests.clone()));
+        let addr = spawn_server(app).await;
+
+        let mut config = serde_json::json!({
+            "bot_token": "xoxb-secret",
+            "channel": "C0123456789",
+            "icon_emoji": ":bee:",
+            "api_url": format!("http://{}/", addr),
+        });
+        let ws = workspace(WorkspaceType::SlackBot, "", config.clone());
+        let sender = get_sender(&ws, "hello", &DeliveryConfig::default()).unwrap();
+        sender.send().await.expect("failed to send");
+        assert_eq!(
+            requests.lock().unwrap()[0],
+            (
+                "Bearer xoxb-secret".to_string(),
+                r#"{"channel":"C0123456789","text":"hello","icon_emoji":":bee:"}"#.to_string()
+            )
+        );
+
+        config["channel"] = "C0UNKNOWN".into();
+        let ws = workspace(WorkspaceType::SlackBot, "", config);
+        let sender = get_sender(&ws, "hello", &DeliveryConfig::default()).unwrap();
+        let e = sender
+            .send()
+            .await
+            .expect_err("unknown channel should fail");
+        assert!(matches!(
+            e.downcast_ref::<MessageError>(),
+            Some(MessageError::Api(error)) if error == "channel_not_found"
+        ));
+    }
+
+    // Discord の webhook. 送信した message は id 100 になる
+    async fn discord_webhook(
+        Extension(requests): Extension<Requests>,
+        method: Method,
+        RawQuery(query): RawQuery,
+        body: String,
+    ) -> (StatusCode, Json<serde_json::Value>) {
+        requests
+            .lock()
+            .unwrap()
+            .push((format!("{} {}", method, query.unwrap_or_default()), body));
+        (
+            StatusCode::OK,
+            Json(serde_json::json!({"id": "100", "channel_id": "10"})),
+        )
+    }
+
+    #[tokio::test]
+    async fn edit_and_delete_sent_message() {
+        use crate::message::repository::test_utils::MessageRepositoryForMemory;
+        use crate::workspace::repository::test_utils::WorkspaceRepositoryForMemory;
+        use crate::workspace::service::CreateWorkspacePayload;
+
+        let requests = Requests::default();
+        let app = Router::new()
+            .route("/webhooks/1/token", post(discord_webhook))
+            .route(
+                "/webhooks/1/token/messages/:id",
+                patch(discord_webhook).delete(discord_webhook),
+            )
+            .layer(Extension(requests.clone()));
+        let addr = spawn_server(app).await;
+
+        let repo = Arc::new(WorkspaceRepositoryForMemory::new());
+        let outbox = Arc::new(MessageRepositoryForMemory::new());
+        for (ws_type, webhook_url) in [
+            ("discord", format!("http://{}/webhooks/1/token", addr)),
+            // 送信に失敗し, message の参照も残らない
+            ("teams", format!("http://{}/teams", addr)),
+        ] {
+            repo.create(CreateWorkspacePayload {
+                name: ws_type.to_string(),
+                ws_type: ws_type.to_string(),
+                webhook_url,
+                config: serde_json::json!({}),
+                display_name: None,
+                avatar_url: None,
+            })
+            .await
+            .unwrap();
+        }
+        let config = DeliveryConfig::default();
+
+        let res = send_message(
+            repo.clone(),
+            outbox.clone(),
+            &config,
+            vec![1, 2],
+            NewMessage::new("helo"),
+        )
+        .await
+        .unwrap();
+        let message_id = entity::MessageId::new(res.message_id);
+        let message = find_message(outbox.clone(), message_id.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            message.deliveries[0].remote,
+            Some(RemoteMessages {
+                chunks: vec![Some(RemoteMessage::Discord {
+                    id: "100".to_string(),
+                    thread_id: None,
+                })],
+                files: vec![],
+            })
+        );
+
+        let res = edit_message(
+            repo.clone(),
+            outbox.clone(),
+            &config,
+            message_id.clone(),
+            "hello",
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status_code(), StatusCode::OK);
+        assert_eq!(
+            res.results,
+            vec![
+                RemoteOperationResult {
+                    target: 1,
+                    status: RemoteOperationStatus::Done,
+                },
+                RemoteOperationResult {
+                    target: 2,
+                    status: RemoteOperationStatus::NotDelivered,
+                },
+            ]
+        );
+
+        let res = delete_message(repo.clone(), outbox.clone(), &config, message_id.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.results[0].status, RemoteOperationStatus::Done);
+
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![
+                (
+                    "POST wait=true".to_string(),
+                    serde_json::to_string(&DiscordMessagePayload::new(
+                        "helo",
+                        &entity::Profile::default()
+                    ))
+                    .unwrap()
+                ),
+                ("PATCH ".to_string(), r#"{"content":"hello"}"#.to_string()),
+                ("DELETE ".to_string(), "".to_string()),
+            ]
+        );
+
+        let message = find_message(outbox.clone(), message_id.clone())
+            .await
+            .unwrap();
+        assert_eq!(message.text, "hello");
+        assert!(message.edited_at.is_some());
+        assert!(message.deleted_at.is_some());
+
+        // 削除済みの message は編集できない
+        let e = edit_message(repo, outbox, &config, message_id, "hello!")
+            .await
+            .expect_err("deleted message should not be edited");
+        assert!(matches!(
+            e.downcast_ref::<MessageRepositoryError>(),
+            Some(MessageRepositoryError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn send_message_status_code_by_results() {
+        use crate::message::repository::test_utils::MessageRepositoryForMemory;
+        use crate::workspace::repository::test_utils::WorkspaceRepositoryForMemory;
+        use crate::workspace::service::CreateWorkspacePayload;
+
+        let requests = Requests::default();
+        let app = Router::new()
+            .route("/webhooks/1/token", post(discord_webhook))
+            .layer(Extension(requests.clone()));
+        let addr = spawn_server(app).await;
+
+        let repo = Arc::new(WorkspaceRepositoryForMemory::new());
+        let outbox = Arc::new(MessageRepositoryForMemory::new());
+        for webhook_url in [
+            format!("http://{}/webhooks/1/token", addr),
+            // 404 で拒否され, 再送もされない
+            format!("http://{}/webhooks/2/token", addr),
+        ] {
+            repo.create(CreateWorkspacePayload {
+                name: "discord".to_string(),
+                ws_type: "discord".to_string(),
+                webhook_url,
+                config: serde_json::json!({}),
+                display_name: None,
+                avatar_url: None,
+            })
+            .await
+            .unwrap();
+        }
+        let config = DeliveryConfig::default();
+
+        // 全ての target に送れた場合
+        let res = send_message(
+            repo.clone(),
+            outbox.clone(),
+            &config,
+            vec![1],
+            NewMessage::new("hello"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status_code(), StatusCode::OK);
+
+        // 一部の target にだけ送れた場合
+        let res = send_message(
+            repo.clone(),
+            outbox.clone(),
+            &config,
+            vec![1, 2],
+            NewMessage::new("hello"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            res.results,
+            vec![
+                DeliveryResult {
+                    target: 1,
+                    status: DeliveryStatus::Sent,
+                    will_retry: false,
+                },
+                DeliveryResult {
+                    target: 2,
+                    status: DeliveryStatus::Rejected { http_status: 404 },
+                    will_retry: false,
+                },
+            ]
+        );
+        assert_eq!(res.status_code(), StatusCode::MULTI_STATUS);
+
+        // どの target にも送れなかった場合
+        let res = send_message(repo, outbox, &config, vec![2], NewMessage::new("hello"))
+            .await
+            .unwrap();
+        assert_eq!(res.status_code(), StatusCode::BAD_GATEWAY);
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    // 同時に処理中のリクエスト数と, その最大値を記録する Discord の webhook
+    async fn slow_discord_webhook(
+        Extension(counts): Extension<Arc<(AtomicUsize, AtomicUsize)>>,
+        Path(id): Path<String>,
+    ) -> (StatusCode, Json<serde_json::Value>) {
+        let (in_flight, max) = counts.as_ref();
+        let n = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        max.fetch_max(n, Ordering::SeqCst);
+        // webhook 2 は送信のタイムアウトより遅い
+        let wait = if id == "2" { 500 } else { 50 };
+        tokio::time::sleep(Duration::from_millis(wait)).await;
+        in_flight.fetch_sub(1, Ordering::SeqCst);
+        (
+            StatusCode::OK,
+            Json(serde_json::json!({"id": "100", "channel_id": "10"})),
+        )
+    }
+
+    #[tokio::test]
+    async fn send_message_limits_concurrency_and_times_out_per_target() {
+        use crate::message::repository::test_utils::MessageRepositoryForMemory;
+        use crate::workspace::repository::test_utils::WorkspaceRepositoryForMemory;
+        use crate::workspace::service::CreateWorkspacePayload;
+
+        let counts = Arc::new((AtomicUsize::new(0), AtomicUsize::new(0)));
+        let app = Router::new()
+            .route("/webhooks/:id/token", post(slow_discord_webhook))
+            .layer(Extension(counts.clone()));
+        let addr = spawn_server(app).await;
+
+        let repo = Arc::new(WorkspaceRepositoryForMemory::new());
+        let outbox = Arc::new(MessageRepositoryForMemory::new());
+        // workspace 5 だけが遅い webhook 2 に送る
+        for id in [1, 1, 1, 1, 2] {
+            repo.create(CreateWorkspacePayload {
+                name: "discord".to_string(),
+                ws_type: "discord".to_string(),
+                webhook_url: format!("http://{}/webhooks/{}/token", addr, id),
+                config: serde_json::json!({}),
+                display_name: None,
+                avatar_url: None,
+            })
+            .await
+            .unwrap();
+        }
+        let config = DeliveryConfig {
+            concurrency: 2,
+            timeout: Duration::from_millis(200),
+            ..DeliveryConfig::default()
+        };
+
+        // 遅い送信先の target だけがタイムアウトする
+        let res = send_message(
+            repo,
+            outbox,
+            &config,
+            vec![1, 2, 3, 4, 5],
+            NewMessage::new("hello"),
+        )
+        .await
+        .unwrap();
+        let statuses = res
+            .results
+            .iter()
+            .map(|r| (r.target, r.status.clone(), r.will_retry))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            vec![
+                (1, DeliveryStatus::Sent, false),
+                (2, DeliveryStatus::Sent, false),
+                (3, DeliveryStatus::Sent, false),
+                (4, DeliveryStatus::Sent, false),
+                (5, DeliveryStatus::Timeout, true),
+            ]
+        );
+        assert_eq!(res.status_code(), StatusCode::MULTI_STATUS);
+        // 同時に送るのは concurrency 個まで
+        assert_eq!(counts.1.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn changes_while_sending_are_applied_after_send() {
+        use crate::message::repository::test_utils::MessageRepositoryForMemory;
+        use crate::workspace::repository::test_utils::WorkspaceRepositoryForMemory;
+        use crate::workspace::service::CreateWorkspacePayload;
+
+        let requests = Requests::default();
+        let app = Router::new()
+            .route(
+                "/webhooks/1/token/messages/:id",
+                patch(discord_webhook).delete(discord_webhook),
+            )
+            .layer(Extension(requests.clone()));
+        let addr = spawn_server(app).await;
+
+        let repo = WorkspaceRepositoryForMemory::new();
+        repo.create(CreateWorkspacePayload {
+            name: "discord".to_string(),
+            ws_type: "discord".to_string(),
+            webhook_url: format!("http://{}/webhooks/1/token", addr),
+            config: serde_json::json!({}),
+            display_name: None,
+            avatar_url: None,
+        })
+        .await
+        .unwrap();
+        let outbox = MessageRepositoryForMemory::new();
+        let config = DeliveryConfig::default();
+        let targets = [entity::WorkspaceId::new(1)];
+        let remote = RemoteMessages {
+            chunks: vec![Some(RemoteMessage::Discord {
+                id: "100".to_string(),
+                thread_id: None,
+            })],
+            files: vec![],
+        };
+
+        // 送信中に編集された message は, 送信後に送信先でも編集する
+        let (edited, deliveries) = outbox
+            .enqueue(&NewMessage::new("helo"), &targets, Duration::ZERO)
+            .await
+            .unwrap();
+        outbox.update_text(&edited, "hello").await.unwrap();
+        let sent = DeliveryStatus::Sent;
+        outbox::record(&repo, &outbox, &config, &deliveries[0], &sent, &remote).await;
+
+        // 送信中に削除された message は, 送信後に送信先からも削除し, 取り消しのままにする
+        let (deleted, deliveries) = outbox
+            .enqueue(&NewMessage::new("bye"), &targets, Duration::ZERO)
+            .await
+            .unwrap();
+        let cancelled = serde_json::to_value(DeliveryStatus::Cancelled).unwrap();
+        outbox.mark_deleted(&deleted, &cancelled).await.unwrap();
+        outbox::record(&repo, &outbox, &config, &deliveries[0], &sent, &remote).await;
+
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![
+                ("PATCH ".to_string(), r#"{"content":"hello"}"#.to_string()),
+                ("DELETE ".to_string(), "".to_string()),
+            ]
+        );
+        let message = outbox.find(edited).await.unwrap();
+        assert_eq!(message.deliveries[0].state, entity::DeliveryState::Sent);
+        let message = outbox.find(deleted).await.unwrap();
+        assert_eq!(message.deliveries[0].result, Some(cancelled));
+    }
+
+    #[test]
+    fn quote_reply_quotes_each_line() {
+        assert_eq!(quote_reply("foo\nbar", "reply"), "> foo\n> bar\n\nreply");
+        let long = "あ".repeat(150);
+        assert_eq!(
+            quote_reply(&long, "reply"),
+            format!("> {}…\n\nreply", "あ".repeat(99))
+        );
+    }
+
+    #[tokio::test]
+    async fn reply_in_thread_or_quote_parent() {
+        use crate::message::repository::test_utils::MessageRepositoryForMemory;
+        use crate::workspace::repository::test_utils::WorkspaceRepositoryForMemory;
+        use crate::workspace::service::CreateWorkspacePayload;
+
+        let requests = Requests::default();
+        let app = Router::new()
+            .route("/slack/chat.postMessage", post(slack_post_message))
+            .route("/webhooks/1/token", post(discord_webhook))
+            .layer(Extension(requests.clone()));
+        let addr = spawn_server(app).await;
+
+        let repo = Arc::new(WorkspaceRepositoryForMemory::new());
+        let outbox = Arc::new(MessageRepositoryForMemory::new());
+        for (ws_type, webhook_url, config) in [
+            (
+                "slack_bot",
+                "".to_string(),
+                serde_json::json!({
+                    "bot_token": "xoxb-secret",
+                    "channel": "C0123456789",
+                    "api_url": format!("http://{}/slack/", addr),
+                }),
+            ),
+            // forum ではない channel の webhook は thread に返信できない
+            (
+                "discord",
+                format!("http://{}/webhooks/1/token", addr),
+                serde_json::json!({}),
+            ),
+            // thread に送る webhook は, 同じ thread に返信する
+            (
+                "discord",
+                format!("http://{}/webhooks/1/token?thread_id=10", addr),
+                serde_json::json!({}),
+            ),
+        ] {
+            repo.create(CreateWorkspacePayload {
+                name: ws_type.to_string(),
+                ws_type: ws_type.to_string(),
+                webhook_url,
+                config,
+                display_name: None,
+                avatar_url: None,
+            })
+            .await
+            .unwrap();
+        }
+        let config = DeliveryConfig::default();
+
+        let res = send_message(
+            repo.clone(),
+            outbox.clone(),
+            &config,
+            vec![1, 2, 3],
+            NewMessage::new("root"),
+        )
+        .await
+        .unwrap();
+        let root = entity::MessageId::new(res.message_id);
+        let message = outbox.find(root.clone()).await.unwrap();
+        assert_eq!(
+            RemoteMessages::from_value(message.deliveries[2].remote.clone()).first(),
+            Some(&RemoteMessage::Discord {
+                id: "100".to_string(),
+                thread_id: Some("10".to_string()),
+            })
+        );
+        let res = reply_message(
+            repo.clone(),
+            outbox.clone(),
+            &config,
+            root,
+            NewMessage::new("reply"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status_code(), StatusCode::OK);
+
+        let requests = requests.lock().unwrap();
+        let replies = requests[3..]
+            .iter()
+            .map(|(query, body)| {
+                let body = serde_json::from_str::<serde_json::Value>(body).unwrap();
+                (query.as_str(), body)
+            })
+            .collect::<Vec<_>>();
+        assert!(replies.iter().any(|(_, b)| b
+            == &serde_json::json!({
+                "channel": "C0123456789",
+                "text": "reply",
+                "thread_ts": "1688000000.000100",
+            })));
+        assert!(replies
+            .iter()
+            .any(|(query, b)| *query == "POST wait=true" && b["content"] == "> root\n\nreply"));
+        assert!(replies
+            .iter()
+            .any(|(query, b)| *query == "POST wait=true&thread_id=10" && b["content"] == "reply"));
+    }
+
+    #[test]
+    fn slack_bot_payload_prefers_profile() {
+        let config: SlackBotConfig = serde_json::from_value(serde_json::json!({
+            "bot_token": "xoxb-secret",
+            "channel": "C0123456789",
+            "username": "times-hub",
+            "icon_emoji": ":bee:",
+        }))
+        .unwrap();
+        let payload = SlackPostMessagePayload::new(&config, &entity::Profile::default(), "hello");
+        assert_eq!(payload.username.as_deref(), Some("times-hub"));
+        assert_eq!(payload.icon_emoji.as_deref(), Some(":bee:"));
+
+        let profile = entity::Profile {
+            display_name: Some("pollenJP".to_string()),
+            avatar_url: Some("https://example.com/pollen.png".to_string()),
+        };
+        let payload = SlackPostMessagePayload::new(&config, &profile, "hello");
+        assert_eq!(payload.username.as_deref(), Some("pollenJP"));
+        assert_eq!(
+            payload.icon_url.as_deref(),
+            Some("https://example.com/pollen.png")
+        );
+        assert_eq!(payload.icon_emoji, None);
+    }
+
+    #[tokio::test]
+    async fn message_profile_overrides_workspace_profile() {
+        use crate::message::repository::test_utils::MessageRepositoryForMemory;
+        use crate::workspace::repository::test_utils::WorkspaceRepositoryForMemory;
+        use crate::workspace::service::CreateWorkspacePayload;
+
+        let requests = Requests::default();
+        let app = Router::new()
+            .route("/webhooks/1/token", post(discord_webhook))
+            .layer(Extension(requests.clone()));
+        let addr = spawn_server(app).await;
+
+        let repo = Arc::new(WorkspaceRepositoryForMemory::new());
+        let outbox = Arc::new(MessageRepositoryForMemory::new());
+        repo.create(CreateWorkspacePayload {
+            name: "discord".to_string(),
+            ws_type: "discord".to_string(),
+            webhook_url: format!("http://{}/webhooks/1/token", addr),
+            config: serde_json::json!({}),
+            display_name: Some("times-hub".to_string()),
+            avatar_url: Some("https://example.com/times-hub.png".to_string()),
+        })
+        .await
+        .unwrap();
+        let config = DeliveryConfig::default();
+
+        send_message(
+            repo.clone(),
+            outbox.clone(),
+            &config,
+            vec![1],
+            NewMessage::new("hello"),
+        )
+        .await
+        .unwrap();
+        // 指定されていない avatar_url は workspace の設定を使う
+        let message = NewMessage {
+            profile: entity::Profile {
+                display_name: Some("pollenJP".to_string()),
+                avatar_url: None,
+            },
+            ..NewMessage::new("hello")
+        };
+        send_message(repo, outbox, &config, vec![1], message)
+            .await
+            .unwrap();
+
+        let bodies = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, body)| serde_json::from_str::<serde_json::Value>(body).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            bodies,
+            vec![
+                serde_json::json!({
+                    "content": "hello",
+                    "username": "times-hub",
+                    "avatar_url": "https://example.com/times-hub.png",
+                }),
+                serde_json::json!({
+                    "content": "hello",
+                    "username": "pollenJP",
+                    "avatar_url": "https://example.com/times-hub.png",
+                }),
+            ]
+        );
+    }
+
+    // multipart で送られた Discord の webhook. part ごとに "name filename" を記録する
+    async fn discord_webhook_multipart(
+        Extension(requests): Extension<Requests>,
+        mut multipart: axum::extract::Multipart,
+    ) -> Json<serde_json::Value> {
+        while let Some(field) = multipart.next_field().await.unwrap() {
+            let name = format!(
+                "{} {}",
+                field.name().unwrap_or_default(),
+                field.file_name().unwrap_or_default()
+            );
+            let body = field.text().await.unwrap();
+            requests.lock().unwrap().push((name, body));
+        }
+        Json(serde_json::json!({"id": "100", "channel_id": "10"}))
+    }
+
+    async fn slack_webhook(
+        Extension(requests): Extension<Requests>,
+        Json(payload): Json<SlackMessagePayload>,
+    ) -> StatusCode {
+        requests
+            .lock()
+            .unwrap()
+            .push(("slack".to_string(), payload.text));
+        StatusCode::OK
+    }
+
+    #[tokio::test]
+    async fn long_text_is_split_or_rejected() {
+        use crate::message::repository::test_utils::MessageRepositoryForMemory;
+        use crate::workspace::repository::test_utils::WorkspaceRepositoryForMemory;
+        use crate::workspace::service::CreateWorkspacePayload;
+
+        let requests = Requests::default();
+        let app = Router::new()
+            .route("/webhooks/1/token", post(discord_webhook))
+            .layer(Extension(requests.clone()));
+        let addr = spawn_server(app).await;
+
+        let repo = Arc::new(WorkspaceRepositoryForMemory::new());
+        let outbox = Arc::new(MessageRepositoryForMemory::new());
+        for config in [
+            serde_json::json!({}),
+            serde_json::json!({"long_text": "reject"}),
+        ] {
+            repo.create(CreateWorkspacePayload {
+                name: "discord".to_string(),
+                ws_type: "discord".to_string(),
+                webhook_url: format!("http://{}/webhooks/1/token", addr),
+                config,
+                display_name: None,
+                avatar_url: None,
+            })
+            .await
+            .unwrap();
+        }
+        let config = DeliveryConfig::default();
+        let text = format!("{}\n\n{}", "あ".repeat(1500), "い".repeat(1500));
+
+        let res = send_message(
+            repo.clone(),
+            outbox.clone(),
+            &config,
+            vec![1],
+            NewMessage::new(&text),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status_code(), StatusCode::OK);
+        let contents = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, body)| {
+                serde_json::from_str::<serde_json::Value>(body).unwrap()["content"].clone()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(contents, vec!["あ".repeat(1500), "い".repeat(1500)]);
+
+        // 分割しない workspace が含まれる場合は, どこにも送らずに拒否する
+        let err = send_message(
+            repo.clone(),
+            outbox.clone(),
+            &config,
+            vec![1, 2],
+            NewMessage::new(&text),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<MessageError>(),
+            Some(MessageError::TooLong(_))
+        ));
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        assert_eq!(outbox.all(10, None).await.unwrap().len(), 1);
+
+        // 短い text はそのまま送る
+        let res = send_message(repo, outbox, &config, vec![1, 2], NewMessage::new("short"))
+            .await
+            .unwrap();
+        assert_eq!(res.status_code(), StatusCode::OK);
+        assert_eq!(requests.lock().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn scheduled_text_is_checked_against_targets() {
+        use crate::message::repository::test_utils::MessageRepositoryForMemory;
+        use crate::workspace::repository::test_utils::WorkspaceRepositoryForMemory;
+        use crate::workspace::service::CreateWorkspacePayload;
+
+        let repo = Arc::new(WorkspaceRepositoryForMemory::new());
+        repo.create(CreateWorkspacePayload {
+            name: "discord".to_string(),
+            ws_type: "discord".to_string(),
+            webhook_url: "http://127.0.0.1:9/webhooks/1/token".to_string(),
+            config: serde_json::json!({"long_text": "reject"}),
+            display_name: None,
+            avatar_url: None,
+        })
+        .await
+        .unwrap();
+        let outbox = Arc::new(MessageRepositoryForMemory::new());
+        let config = DeliveryConfig::default();
+        let scheduled_at = Utc::now() + chrono::Duration::hours(1);
+        let message = NewMessage {
+            send_at: Some(scheduled_at),
+            ..NewMessage::new("good night")
+        };
+        let (id, _) = outbox
+            .enqueue(&message, &[entity::WorkspaceId::new(1)], Duration::ZERO)
+            .await
+            .unwrap();
+
+        // 分割しない target に収まらない text は, 送信時刻も変えずに拒否する
+        let send_at = Utc::now() + chrono::Duration::hours(2);
+        let e = update_scheduled(
+            repo.clone(),
+            outbox.clone(),
+            &config,
+            id.clone(),
+            Some(send_at),
+            Some(&"あ".repeat(2001)),
+        )
+        .await
+        .expect_err("too long text should be rejected");
+        assert!(matches!(
+            e.downcast_ref::<MessageError>(),
+            Some(MessageError::TooLong(_))
+        ));
+        let message = outbox.find(id.clone()).await.unwrap();
+        assert_eq!(message.text, "good night");
+        assert_eq!(message.send_at, Some(scheduled_at));
+
+        let updated = update_scheduled(repo, outbox, &config, id, Some(send_at), Some("おやすみ"))
+            .await
+            .unwrap();
+        assert_eq!(updated.text, "おやすみ");
+        assert_eq!(updated.send_at, Some(send_at));
+        assert!(updated.edited_at.is_none());
+    }
+
+    #[test]
+    fn layout_text_counts_toward_limit() {
+        let config = DeliveryConfig::default();
+        let layout = entity::Layout {
+            title: Some("デプロイ".repeat(30)),
+            ..Default::default()
+        };
+        let text = "あ".repeat(4000);
+        // layout を text に加えて送る target では, 加えた後の長さで確かめる
+        let telegram = workspace(
+            WorkspaceType::Telegram,
+            "",
+            serde_json::json!({"bot_token": "token", "chat_id": 1, "long_text": "reject"}),
+        );
+        check_targets(
+            std::slice::from_ref(&telegram),
+            &config,
+            vec![1],
+            &text,
+            None,
+        )
+        .unwrap();
+        let e = check_targets(&[telegram], &config, vec![1], &text, Some(&layout))
+            .expect_err("text with layout should be too long");
+        assert!(matches!(
+            e.downcast_ref::<MessageError>(),
+            Some(MessageError::TooLong(_))
+        ));
+
+        // layout を別に送る target では text だけで確かめる
+        let slack = workspace(
+            WorkspaceType::Slack,
+            "http://127.0.0.1:9/",
+            serde_json::json!({"long_text": "reject"}),
+        );
+        let text = "あ".repeat(39_990);
+        check_targets(&[slack], &config, vec![1], &text, Some(&layout)).unwrap();
+        assert!(supports_layout(&WorkspaceType::Discord));
+        assert!(!supports_layout(&WorkspaceType::Telegram));
+    }
+
+    // 3 回目の呼び出しだけ失敗する Telegram Bot API
+    async fn telegram_api_flaky(
+        Extension(requests): Extension<Requests>,
+        Path(method): Path<String>,
+        _body: axum::body::Bytes,
+    ) -> (StatusCode, Json<serde_json::Value>) {
+        let mut requests = requests.lock().unwrap();
+        requests.push((method, String::new()));
+        let n = requests.len();
+        let status = match n {
+            3 => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::OK,
+        };
+        (
+            status,
+            Json(serde_json::json!({"ok": true, "result": {"message_id": n, "chat": {"id": 10}}})),
+        )
+    }
+
+    #[tokio::test]
+    async fn attachments_sent_before_failure_are_not_sent_again() {
+        use crate::message::repository::test_utils::MessageRepositoryForMemory;
+        use crate::workspace::repository::test_utils::WorkspaceRepositoryForMemory;
+        use crate::workspace::service::CreateWorkspacePayload;
+
+        let requests = Requests::default();
+        let app = Router::new()
+            .route("/bottoken/:method", post(telegram_api_flaky))
+            .layer(Extension(requests.clone()));
+        let addr = spawn_server(app).await;
+
+        let repo = Arc::new(WorkspaceRepositoryForMemory::new());
+        repo.create(CreateWorkspacePayload {
+            name: "telegram".to_string(),
+            ws_type: "telegram".to_string(),
+            webhook_url: String::new(),
+            config: serde_json::json!({
+                "bot_token": "token",
+                "chat_id": 10,
+                "api_url": format!("http://{}", addr),
+            }),
+            display_name: None,
+            avatar_url: None,
+        })
+        .await
+        .unwrap();
+        let outbox = Arc::new(MessageRepositoryForMemory::new());
+        let mut config = DeliveryConfig::default();
+        config.retry.max_retries = 0;
+        config.outbox.retry.base_backoff = Duration::ZERO;
+        let attachment = |key: &str| entity::Attachment {
+            key: key.to_string(),
+            filename: format!("{}.txt", key),
+            content_type: "text/plain".to_string(),
+            data: bytes::Bytes::from_static(b"memo"),
+        };
+        let message = NewMessage {
+            attachments: vec![attachment("a"), attachment("b")],
+            ..NewMessage::new("hello")
+        };
+
+        // 2 つ目のファイルで失敗した再送は, text と 1 つ目のファイルを送り直さない
+        let res = send_message(repo.clone(), outbox.clone(), &config, vec![1], message)
+            .await
+            .unwrap();
+        assert!(res.results[0].will_retry);
+        outbox::drain(repo.as_ref(), outbox.as_ref(), &config)
+            .await
+            .unwrap();
+
+        let methods = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(method, _)| method.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            methods,
+            vec![
+                "sendMessage",
+                "sendDocument",
+                "sendDocument",
+                "sendDocument"
+            ]
+        );
+        let message = find_message(outbox, entity::MessageId::new(res.message_id))
+            .await
+            .unwrap();
+        assert_eq!(message.deliveries[0].state, "sent");
+        let telegram = |message_id| {
+            Some(RemoteMessage::Telegram {
+                chat_id: 10,
+                message_id,
+            })
+        };
+        assert_eq!(
+            message.deliveries[0].remote,
+            Some(RemoteMessages {
+                chunks: vec![telegram(1)],
+                files: vec![telegram(2), telegram(4)],
+            })
+        );
+    }
+
+    // 2 回目の投稿だけ失敗し, 投稿ごとに別の id を返す Discord
+    async fn discord_webhook_flaky(
+        Extension(requests): Extension<Requests>,
+        method: Method,
+        uri: axum::http::Uri,
+        body: String,
+    ) -> (StatusCode, Json<serde_json::Value>) {
+        let mut requests = requests.lock().unwrap();
+        requests.push((format!("{} {}", method, uri.path()), body));
+        let n = requests.len();
+        let status = match n {
+            2 => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::OK,
+        };
+        (
+            status,
+            Json(serde_json::json!({"id": n.to_string(), "channel_id": "10"})),
+        )
+    }
+
+    #[tokio::test]
+    async fn split_message_is_resumed_edited_and_deleted_by_chunk() {
+        use crate::message::repository::test_utils::MessageRepositoryForMemory;
+        use crate::workspace::repository::test_utils::WorkspaceRepositoryForMemory;
+        use crate::workspace::service::CreateWorkspacePayload;
+
+        let requests = Requests::default();
+        let app = Router::new()
+            .route("/webhooks/1/token", post(discord_webhook_flaky))
+            .route(
+                "/webhooks/1/token/messages/:id",
+                patch(discord_webhook_flaky).delete(discord_webhook_flaky),
+            )
+            .layer(Extension(requests.clone()));
+        let addr = spawn_server(app).await;
+
+        let repo = Arc::new(WorkspaceRepositoryForMemory::new());
+        repo.create(CreateWorkspacePayload {
+            name: "discord".to_string(),
+            ws_type: "discord".to_string(),
+            webhook_url: format!("http://{}/webhooks/1/token", addr),
+            config: serde_json::json!({}),
+            display_name: None,
+            avatar_url: None,
+        })
+        .await
+        .unwrap();
+        let outbox = Arc::new(MessageRepositoryForMemory::new());
+        let mut config = DeliveryConfig::default();
+        config.retry.max_retries = 0;
+        config.outbox.retry.base_backoff = Duration::ZERO;
+        let text = format!("{}\n\n{}", "あ".repeat(1500), "い".repeat(1500));
+
+        // 2 つ目の chunk で失敗した再送は, 2 つ目の chunk から送る
+        let res = send_message(
+            repo.clone(),
+            outbox.clone(),
+            &config,
+            vec![1],
+            NewMessage::new(&text),
+        )
+        .await
+        .unwrap();
+        assert!(res.results[0].will_retry);
+        let n = outbox::drain(repo.as_ref(), outbox.as_ref(), &config)
+            .await
+            .unwrap();
+        assert_eq!(n, 1);
+
+        let message_id = entity::MessageId::new(res.message_id);
+        let message = find_message(outbox.clone(), message_id.clone())
+            .await
+            .unwrap();
+        assert_eq!(message.deliveries[0].state, "sent");
+        let discord = |id: &str| {
+            Some(RemoteMessage::Discord {
+                id: id.to_string(),
+                thread_id: None,
+            })
+        };
+        assert_eq!(
+            message.deliveries[0].remote,
+            Some(RemoteMessages {
+                chunks: vec![discord("1"), discord("3")],
+                files: vec![],
+            })
+        );
+
+        // 短くした text は最初の chunk に入れ, 余った chunk は削除する
+        let res = edit_message(
+            repo.clone(),
+            outbox.clone(),
+            &config,
+            message_id.clone(),
+            "short",
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.results[0].status, RemoteOperationStatus::Done);
+
+        // 送信済みの chunk より多くは分けられない
+        let res = edit_message(
+            repo.clone(),
+            outbox.clone(),
+            &config,
+            message_id.clone(),
+            &text,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status_code(), StatusCode::BAD_GATEWAY);
+
+        let res = delete_message(repo.clone(), outbox.clone(), &config, message_id.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.results[0].status, RemoteOperationStatus::Done);
+
+        let requests = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(request, body)| {
+                let content = serde_json::from_str::<serde_json::Value>(body)
+                    .map(|v| v["content"].as_str().unwrap_or_default().to_string())
+                    .unwrap_or_default();
+                (request.clone(), content.chars().next())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            requests,
+            vec![
+                ("POST /webhooks/1/token".to_string(), Some('あ')),
+                ("POST /webhooks/1/token".to_string(), Some('い')),
+                ("POST /webhooks/1/token".to_string(), Some('い')),
+                ("PATCH /webhooks/1/token/messages/1".to_string(), Some('s')),
+                ("DELETE /webhooks/1/token/messages/3".to_string(), None),
+                ("DELETE /webhooks/1/token/messages/1".to_string(), None),
+            ]
+        );
+    }
+
+    fn layout() -> entity::Layout {
+        entity::Layout {
+            title: Some("Deploy".to_string()),
+            fields: vec![entity::LayoutField {
+                name: "env".to_string(),
+                value: "a < b".to_string(),
+            }],
+            color: Some("#36a64f".to_string()),
+            footer: Some("times-hub".to_string()),
+            image_url: Some("https://example.com/graph.png".to_string()),
+            buttons: vec![entity::LayoutButton {
+                label: "Open".to_string(),
+                url: "https://example.com/deploys/1".to_string(),
+            }],
+        }
+    }
+
+    #[test]
+    fn layout_payloads() {
+        let slack = SlackLayout::new("hello", Some(&layout()));
+        assert_eq!(
+            slack.blocks,
+            vec![
+                serde_json::json!({"type": "section", "text": {"type": "mrkdwn", "text": "hello"}})
+            ]
+        );
+        assert_eq!(
+            slack.attachments,
+            vec![serde_json::json!({
+                "color": "#36a64f",
+                "blocks": [
+                    {"type": "header", "text": {"type": "plain_text", "text": "Deploy"}},
+                    {"type": "section", "fields": [{"type": "mrkdwn", "text": "*env*\na &lt; b"}]},
+                    {"type": "image", "image_url": "https://example.com/graph.png", "alt_text": "Deploy"},
+                    {"type": "actions", "elements": [{
+                        "type": "button",
+                        "text": {"type": "plain_text", "text": "Open"},
+                        "url": "https://example.com/deploys/1",
+                    }]},
+                    {"type": "context", "elements": [{"type": "mrkdwn", "text": "times-hub"}]},
+                ],
+            })]
+        );
+        // color がなければ blocks に続ける
+        let slack = SlackLayout::new(
+            "",
+            Some(&entity::Layout {
+                color: None,
+                ..layout()
+            }),
+        );
+        assert_eq!(slack.blocks.len(), 5);
+        assert!(slack.attachments.is_empty());
+        assert_eq!(SlackLayout::new("hello", None), SlackLayout::default());
+
+        assert_eq!(
+            discord_embed(&layout()),
+            serde_json::json!({
+                "title": "Deploy",
+                "color": 0x36a64f,
+                "fields": [{"name": "env", "value": "a < b", "inline": true}],
+                "description": "[Open](https://example.com/deploys/1)",
+                "image": {"url": "https://example.com/graph.png"},
+                "footer": {"text": "times-hub"},
+            })
+        );
+
+        assert_eq!(
+            layout_text("hello", &layout()),
+            "hello\n\n**Deploy**\n**env**: a \\< b\n[Open](https://example.com/deploys/1)\n<https://example.com/graph.png>\ntimes-hub"
+        );
+
+        // link の label と URL は [..](..) を閉じないように escape する
+        let layout = entity::Layout {
+            buttons: vec![entity::LayoutButton {
+                label: "[draft]".to_string(),
+                url: "https://example.com/wiki/Foo_(bar)".to_string(),
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            discord_embed(&layout)["description"],
+            "[\\[draft\\]](https://example.com/wiki/Foo_%28bar%29)"
+        );
+        assert_eq!(
+            layout_text("", &layout),
+            "[\\[draft\\]](https://example.com/wiki/Foo_%28bar%29)"
+        );
+    }
+
+    #[tokio::test]
+    async fn layout_is_sent_or_appended_to_text() {
+        use crate::message::repository::test_utils::MessageRepositoryForMemory;
+        use crate::workspace::repository::test_utils::WorkspaceRepositoryForMemory;
+        use crate::workspace::service::CreateWorkspacePayload;
+
+        let requests = Requests::default();
+        let app = Router::new()
+            .route("/webhooks/1/token", post(discord_webhook))
+            .route("/slack", post(slack_webhook))
+            .layer(Extension(requests.clone()));
+        let addr = spawn_server(app).await;
+
+        let repo = Arc::new(WorkspaceRepositoryForMemory::new());
+        let outbox = Arc::new(MessageRepositoryForMemory::new());
+        for (ws_type, webhook_url) in [
+            ("discord", format!("http://{}/webhooks/1/token", addr)),
+            ("mattermost", format!("http://{}/slack", addr)),
+        ] {
+            repo.create(CreateWorkspacePayload {
+                name: ws_type.to_string(),
+                ws_type: ws_type.to_string(),
+                webhook_url,
+                config: serde_json::json!({}),
+                display_name: None,
+                avatar_url: None,
+            })
+            .await
+            .unwrap();
+        }
+        let layout = entity::Layout {
+            title: Some("Deploy".to_string()),
+            color: Some("#ff0000".to_string()),
+            ..entity::Layout::default()
+        };
+        let message = NewMessage {
+            layout: Some(layout.clone()),
+            ..NewMessage::new("hello")
+        };
+        let res = send_message(
+            repo,
+            outbox.clone(),
+            &DeliveryConfig::default(),
+            vec![1, 2],
+            message,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status_code(), StatusCode::OK);
+
+        let mut requests = requests.lock().unwrap().clone();
+        requests.sort();
+        let discord = serde_json::from_str::<serde_json::Value>(&requests[0].1).unwrap();
+        assert_eq!(discord["content"], "hello");
+        assert_eq!(
+            discord["embeds"],
+            serde_json::json!([{"title": "Deploy", "color": 0xff0000}])
+        );
+        // mattermost には layout を text にして送る
+        assert_eq!(
+            requests[1],
+            ("slack".to_string(), "hello\n\n**Deploy**".to_string())
+        );
+        let messages = outbox.all(10, None).await.unwrap();
+        assert_eq!(messages[0].layout, Some(layout));
+    }
+
+    #[tokio::test]
+    async fn attachments_are_uploaded_or_linked() {
+        use crate::message::repository::test_utils::MessageRepositoryForMemory;
+        use crate::workspace::repository::test_utils::WorkspaceRepositoryForMemory;
+        use crate::workspace::service::CreateWorkspacePayload;
+
+        let requests = Requests::default();
+        let app = Router::new()
+            .route("/webhooks/1/token", post(discord_webhook_multipart))
+            .route("/slack", post(slack_webhook))
+            .layer(Extension(requests.clone()));
+        let addr = spawn_server(app).await;
+
+        let repo = Arc::new(WorkspaceRepositoryForMemory::new());
+        let outbox = Arc::new(MessageRepositoryForMemory::new());
+        for (ws_type, webhook_url) in [
+            ("discord", format!("http://{}/webhooks/1/token", addr)),
+            ("slack", format!("http://{}/slack", addr)),
+        ] {
+            repo.create(CreateWorkspacePayload {
+                name: ws_type.to_string(),
+                ws_type: ws_type.to_string(),
+                webhook_url,
+                config: serde_json::json!({}),
+                display_name: None,
+                avatar_url: None,
+            })
+            .await
+            .unwrap();
+        }
+        let config = DeliveryConfig {
+            public_url: Some("https://times-hub.example.com/".to_string()),
+            ..DeliveryConfig::default()
+        };
+        let attachment = entity::Attachment {
+            key: "key1".to_string(),
+            filename: "memo.txt".to_string(),
+            content_type: "text/plain".to_string(),
+            data: bytes::Bytes::from_static(b"memo"),
+        };
+
+        let message = NewMessage {
+            attachments: vec![attachment.clone()],
+            ..NewMessage::new("hello")
+        };
+        let res = send_message(repo.clone(), outbox.clone(), &config, vec![1, 2], message)
+            .await
+            .unwrap();
+        assert_eq!(res.status_code(), StatusCode::OK);
+
+        let mut requests = requests.lock().unwrap().clone();
+        requests.sort();
+        assert_eq!(
+            requests,
+            vec![
+                ("files[0] memo.txt".to_string(), "memo".to_string(),),
+                (
+                    "payload_json ".to_string(),
+                    serde_json::to_string(&DiscordMessagePayload::new(
+                        "hello",
+                        &entity::Profile::default()
+                    ))
+                    .unwrap(),
+                ),
+                (
+                    "slack".to_string(),
+                    "hello\n📎 memo.txt https://times-hub.example.com/attachments/key1".to_string(),
+                ),
+            ]
+        );
+        assert_eq!(outbox.find_attachment("key1").await.unwrap(), attachment);
+
+        // Discord の上限を超える添付は再送せずに失敗とする
+        let message = NewMessage {
+            attachments: vec![entity::Attachment {
+                data: vec![0; DiscordSender::MAX_ATTACHMENT_BYTES + 1].into(),
+                ..attachment
+            }],
+            ..NewMessage::new("hello")
+        };
+        let res = send_message(repo, outbox, &config, vec![1], message)
+            .await
+            .unwrap();
+        assert!(matches!(
+            res.results[0].status,
+            DeliveryStatus::AttachmentLimit { .. }
+        ));
+        assert!(!res.results[0].will_retry);
     }
 }
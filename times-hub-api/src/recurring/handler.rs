@@ -0,0 +1,301 @@
+use crate::entity;
+use crate::message::handler::message_error_to_status_code;
+use crate::message::service::DeliveryConfig;
+use crate::recurring::repository::{RecurringRepository, RecurringRepositoryError};
+use crate::recurring::service::{self, RecurringError, RecurringPayload, UpdateRecurringPayload};
+use crate::workspace::handler::ValidatedJson;
+use crate::workspace::repository::WorkspaceRepository;
+
+use ::axum::extract::Extension;
+use ::axum::extract::Path;
+use ::axum::extract::Query;
+use ::axum::http::StatusCode;
+use ::axum::response::IntoResponse;
+use ::axum::Json;
+use ::serde::Deserialize;
+use ::serde::Serialize;
+use ::std::sync::Arc;
+
+pub async fn create_recurring<T, R>(
+    Extension(repo): Extension<Arc<T>>,
+    Extension(recurring): Extension<Arc<R>>,
+    Extension(config): Extension<Arc<DeliveryConfig>>,
+    ValidatedJson(payload): ValidatedJson<RecurringPayload>,
+) -> Result<impl IntoResponse, StatusCode>
+where
+    T: WorkspaceRepository,
+    R: RecurringRepository,
+{
+    let message = service::create_recurring(repo, recurring, &config, payload)
+        .await
+        .map_err(recurring_error_to_status_code)?;
+    Ok((StatusCode::CREATED, Json(message)))
+}
+
+pub async fn all_recurring<R>(
+    Extension(repo): Extension<Arc<R>>,
+) -> Result<impl IntoResponse, StatusCode>
+where
+    R: RecurringRepository,
+{
+    let messages = service::all_recurring(repo)
+        .await
+        .map_err(recurring_error_to_status_code)?;
+    Ok((StatusCode::OK, Json(messages)))
+}
+
+pub async fn find_recurring<R>(
+    Extension(repo): Extension<Arc<R>>,
+    Path(id): Path<entity::RecurringMessageIdTypeAlias>,
+) -> Result<impl IntoResponse, StatusCode>
+where
+    R: RecurringRepository,
+{
+    let id = entity::RecurringMessageId::new(id);
+    let message = service::find_recurring(repo, id)
+        .await
+        .map_err(recurring_error_to_status_code)?;
+    Ok((StatusCode::OK, Json(message)))
+}
+
+pub async fn update_recurring<T, R>(
+    Extension(repo): Extension<Arc<T>>,
+    Extension(recurring): Extension<Arc<R>>,
+    Extension(config): Extension<Arc<DeliveryConfig>>,
+    Path(id): Path<entity::RecurringMessageIdTypeAlias>,
+    ValidatedJson(payload): ValidatedJson<UpdateRecurringPayload>,
+) -> Result<impl IntoResponse, StatusCode>
+where
+    T: WorkspaceRepository,
+    R: RecurringRepository,
+{
+    let id = entity::RecurringMessageId::new(id);
+    let message = service::update_recurring(repo, recurring, &config, id, payload)
+        .await
+        .map_err(recurring_error_to_status_code)?;
+    Ok((StatusCode::OK, Json(message)))
+}
+
+pub async fn delete_recurring<R>(
+    Extension(repo): Extension<Arc<R>>,
+    Path(id): Path<entity::RecurringMessageIdTypeAlias>,
+) -> StatusCode
+where
+    R: RecurringRepository,
+{
+    let id = entity::RecurringMessageId::new(id);
+    service::delete_recurring(repo, id)
+        .await
+        .map(|_| StatusCode::NO_CONTENT)
+        .map_err(recurring_error_to_status_code)
+        .unwrap_or_else(|e| e)
+}
+
+// GET /recurring/:id/runs の query
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListRunsQuery {
+    pub limit: Option<i64>,
+}
+
+pub async fn recurring_runs<R>(
+    Extension(repo): Extension<Arc<R>>,
+    Path(id): Path<entity::RecurringMessageIdTypeAlias>,
+    Query(query): Query<ListRunsQuery>,
+) -> Result<impl IntoResponse, StatusCode>
+where
+    R: RecurringRepository,
+{
+    let id = entity::RecurringMessageId::new(id);
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let runs = service::recurring_runs(repo, id, limit)
+        .await
+        .map_err(recurring_error_to_status_code)?;
+    Ok((StatusCode::OK, Json(runs)))
+}
+
+/// 定期送信の error を status code にする. それ以外は message の error として扱う
+fn recurring_error_to_status_code(e: anyhow::Error) -> StatusCode {
+    let status = match (
+        e.downcast_ref::<RecurringError>(),
+        e.downcast_ref::<RecurringRepositoryError>(),
+    ) {
+        (Some(_), _) => StatusCode::BAD_REQUEST,
+        (_, Some(RecurringRepositoryError::NotFound(_))) => StatusCode::NOT_FOUND,
+        _ => return message_error_to_status_code(e),
+    };
+    tracing::error!("error: {}", e);
+    status
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::recurring::repository::test_utils::RecurringRepositoryForMemory;
+    use crate::recurring::repository::NewRecurringRun;
+    use crate::workspace::repository::test_utils::WorkspaceRepositoryForMemory;
+    use crate::workspace::service::CreateWorkspacePayload;
+
+    use ::axum::body::Body;
+    use ::axum::routing::{get, post};
+    use ::axum::Router;
+    use ::chrono::Utc;
+    use ::http::Request;
+    use ::hyper::header::CONTENT_TYPE;
+    use ::tower::ServiceExt;
+
+    type T = WorkspaceRepositoryForMemory;
+    type R = RecurringRepositoryForMemory;
+
+    fn create_app(workspaces: T, repo: R) -> Router {
+        Router::new()
+            .route(
+                "/recurring",
+                post(create_recurring::<T, R>).get(all_recurring::<R>),
+            )
+            .route(
+                "/recurring/:id",
+                get(find_recurring::<R>)
+                    .patch(update_recurring::<T, R>)
+                    .delete(delete_recurring::<R>),
+            )
+            .route("/recurring/:id/runs", get(recurring_runs::<R>))
+            .layer(Extension(Arc::new(workspaces)))
+            .layer(Extension(Arc::new(repo)))
+            .layer(Extension(Arc::new(DeliveryConfig::default())))
+    }
+
+    async fn call(app: &Router, method: &str, uri: &str, body: &str) -> (StatusCode, Vec<u8>) {
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        let status = res.status();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (status, body.to_vec())
+    }
+
+    #[tokio::test]
+    async fn crud_recurring_and_list_runs() {
+        let workspaces = WorkspaceRepositoryForMemory::new();
+        workspaces
+            .create(CreateWorkspacePayload {
+                name: "discord".to_string(),
+                ws_type: "discord".to_string(),
+                webhook_url: "http://127.0.0.1:9/webhooks/1/token".to_string(),
+                config: serde_json::json!({"long_text": "reject"}),
+                display_name: None,
+                avatar_url: None,
+            })
+            .await
+            .unwrap();
+        let repo = RecurringRepositoryForMemory::new();
+        let app = create_app(workspaces, repo.clone());
+
+        // targets の重複は除く
+        let (status, body) = call(
+            &app,
+            "POST",
+            "/recurring",
+            r#"{"name": "standup", "cron": "0 9 * * 1-5", "timezone": "Asia/Tokyo", "targets": [1, 2, 1], "text": "standup time"}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let created: service::ResponseRecurring = serde_json::from_slice(&body).unwrap();
+        assert_eq!(created.targets, vec![1, 2]);
+        assert_eq!(created.missed_runs, entity::MissedRunPolicy::Skip);
+        assert!(created.enabled);
+        assert!(created.next_run_at > Utc::now());
+
+        // cron 式と timezone は登録時に検証する
+        for body in [
+            r#"{"name": "a", "cron": "0 9 * *", "targets": [1], "text": "a"}"#,
+            r#"{"name": "a", "cron": "0 9 * * *", "timezone": "Mars/Olympus", "targets": [1], "text": "a"}"#,
+            r#"{"name": "a", "cron": "0 9 * * *", "targets": [], "text": "a"}"#,
+            r#"{"name": "a", "cron": "0 9 * * *", "targets": [1], "text": ""}"#,
+        ] {
+            let (status, _) = call(&app, "POST", "/recurring", body).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+        }
+        // 分割しない target に収まらない text は, 送る時ではなく登録時に拒否する
+        let body = serde_json::json!({
+            "name": "a",
+            "cron": "0 9 * * *",
+            "targets": [1],
+            "text": "a".repeat(2001),
+        })
+        .to_string();
+        let (status, _) = call(&app, "POST", "/recurring", &body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let uri = format!("/recurring/{}", created.id);
+        let (status, body) = call(
+            &app,
+            "PATCH",
+            &uri,
+            r#"{"name": "weekly", "cron": "0 17 * * FRI", "targets": [1], "text": "what did you do this week?", "display_name": "bot", "missed_runs": "catch_up", "enabled": false}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let updated: service::ResponseRecurring = serde_json::from_slice(&body).unwrap();
+        assert_eq!(updated.name, "weekly");
+        assert_eq!(updated.missed_runs, entity::MissedRunPolicy::CatchUp);
+        assert!(!updated.enabled);
+        // 省略した項目は今のまま
+        assert_eq!(updated.timezone, "Asia/Tokyo");
+        assert_eq!(updated.display_name.as_deref(), Some("bot"));
+
+        // null で消す
+        let (status, body) = call(&app, "PATCH", &uri, r#"{"display_name": null}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        let updated: service::ResponseRecurring = serde_json::from_slice(&body).unwrap();
+        assert_eq!(updated.display_name, None);
+        assert_eq!(updated.text, "what did you do this week?");
+        for body in [
+            r#"{"display_name": ""}"#,
+            r#"{"text": ""}"#,
+            r#"{"targets": []}"#,
+            r#"{"timezone": "Mars/Olympus"}"#,
+        ] {
+            let (status, _) = call(&app, "PATCH", &uri, body).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+        }
+        let (status, _) = call(&app, "PATCH", "/recurring/999", r#"{"enabled": true}"#).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (_, body) = call(&app, "GET", "/recurring", "").await;
+        let list: Vec<service::ResponseRecurring> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(list, vec![updated.clone()]);
+
+        // 実行履歴は新しい順
+        let id = entity::RecurringMessageId::new(created.id);
+        let runs = [entity::RunState::Skipped, entity::RunState::Sent]
+            .into_iter()
+            .enumerate()
+            .map(|(i, state)| NewRecurringRun {
+                scheduled_at: Utc::now() - chrono::Duration::days(2 - i as i64),
+                state,
+                message_id: None,
+                error: None,
+            })
+            .collect::<Vec<_>>();
+        let message = repo.find(id).await.unwrap();
+        repo.start(&message, &runs, Some(updated.next_run_at))
+            .await
+            .unwrap();
+        let (status, body) = call(&app, "GET", &format!("{}/runs?limit=1", uri), "").await;
+        assert_eq!(status, StatusCode::OK);
+        let runs: Vec<service::ResponseRecurringRun> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].state, "sent");
+
+        let (status, _) = call(&app, "DELETE", &uri, "").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = call(&app, "GET", &uri, "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = call(&app, "GET", &format!("{}/runs", uri), "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,3 @@
+pub(crate) mod handler;
+pub(crate) mod repository;
+pub(crate) mod service;
@@ -0,0 +1,663 @@
+use crate::entity;
+
+use ::anyhow::Result;
+use ::axum::async_trait;
+use ::chrono::{DateTime, Utc};
+use ::sqlx::postgres::PgPool;
+use ::sqlx::FromRow;
+use ::std::str::FromStr;
+use ::std::time::Duration;
+use ::thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum RecurringRepositoryError {
+    #[error("Unexpected Error: {0}")]
+    Unexpected(String),
+    #[error("Recurring message NotFound! ID is {0}")]
+    NotFound(entity::RecurringMessageId),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+pub struct RecurringMessageDBRow {
+    pub id: entity::RecurringMessageIdTypeAlias,
+    pub name: String,
+    pub cron: String,
+    pub timezone: String,
+    pub targets: Vec<entity::WorkspaceIdTypeAlias>,
+    pub text: String,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub layout: Option<serde_json::Value>,
+    pub missed_runs: String,
+    pub enabled: bool,
+    pub next_run_at: DateTime<Utc>,
+    pub version: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+impl TryFrom<RecurringMessageDBRow> for entity::RecurringMessage {
+    type Error = anyhow::Error;
+
+    fn try_from(row: RecurringMessageDBRow) -> Result<Self> {
+        Ok(Self {
+            id: entity::RecurringMessageId::new(row.id),
+            name: row.name,
+            cron: row.cron,
+            timezone: row.timezone,
+            targets: row
+                .targets
+                .into_iter()
+                .map(entity::WorkspaceId::new)
+                .collect(),
+            text: row.text,
+            profile: entity::Profile {
+                display_name: row.display_name,
+                avatar_url: row.avatar_url,
+            },
+            layout: row.layout.map(serde_json::from_value).transpose()?,
+            missed_runs: entity::MissedRunPolicy::from_str(row.missed_runs.as_str())?,
+            enabled: row.enabled,
+            next_run_at: row.next_run_at,
+            version: row.version,
+            created_at: row.created_at,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+pub struct RecurringRunDBRow {
+    pub scheduled_at: DateTime<Utc>,
+    pub status: String,
+    pub message_id: Option<entity::MessageIdTypeAlias>,
+    pub error: Option<String>,
+    pub ran_at: DateTime<Utc>,
+}
+
+impl TryFrom<RecurringRunDBRow> for entity::RecurringRun {
+    type Error = anyhow::Error;
+
+    fn try_from(row: RecurringRunDBRow) -> Result<Self> {
+        Ok(Self {
+            scheduled_at: row.scheduled_at,
+            state: entity::RunState::from_str(row.status.as_str())?,
+            message_id: row.message_id.map(entity::MessageId::new),
+            error: row.error,
+            ran_at: row.ran_at,
+        })
+    }
+}
+
+// 登録・更新する定期送信. next_run_at は service が cron 式から求める
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewRecurringMessage {
+    pub name: String,
+    pub cron: String,
+    pub timezone: String,
+    pub targets: Vec<entity::WorkspaceId>,
+    pub text: String,
+    pub profile: entity::Profile,
+    pub layout: Option<entity::Layout>,
+    pub missed_runs: entity::MissedRunPolicy,
+    pub enabled: bool,
+    pub next_run_at: DateTime<Utc>,
+}
+
+// 記録する実行結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewRecurringRun {
+    pub scheduled_at: DateTime<Utc>,
+    pub state: entity::RunState,
+    pub message_id: Option<entity::MessageId>,
+    pub error: Option<String>,
+}
+
+#[async_trait]
+pub trait RecurringRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
+    async fn create(&self, message: &NewRecurringMessage) -> Result<entity::RecurringMessage>;
+
+    async fn all(&self) -> Result<Vec<entity::RecurringMessage>>;
+
+    async fn find(&self, id: entity::RecurringMessageId) -> Result<entity::RecurringMessage>;
+
+    async fn update(
+        &self,
+        id: entity::RecurringMessageId,
+        message: &NewRecurringMessage,
+    ) -> Result<entity::RecurringMessage>;
+
+    async fn delete(&self, id: entity::RecurringMessageId) -> Result<()>;
+
+    /// 送信時刻を過ぎた有効な定期送信を最大 `limit` 件取り出し, `lease` の間は再度取り出されないようにする
+    async fn claim(&self, limit: i64, lease: Duration) -> Result<Vec<entity::RecurringMessage>>;
+
+    /// 送信する前に実行結果を記録し, 次の送信時刻を設定する. `next_run_at` が None の場合は無効にする.
+    /// 同じ送信時刻の実行結果が既にあれば記録せず, 記録できた送信時刻を返す.
+    /// `message` を取り出した後に更新されていた場合は, 更新を優先して何も記録しない
+    async fn start(
+        &self,
+        message: &entity::RecurringMessage,
+        runs: &[NewRecurringRun],
+        next_run_at: Option<DateTime<Utc>>,
+    ) -> Result<Vec<DateTime<Utc>>>;
+
+    /// 送信中として記録した実行結果を, 送信の結果で置き換える
+    async fn finish(&self, id: &entity::RecurringMessageId, run: &NewRecurringRun) -> Result<()>;
+
+    /// 新しい順に最大 `limit` 件の実行結果を返す
+    async fn runs(
+        &self,
+        id: entity::RecurringMessageId,
+        limit: i64,
+    ) -> Result<Vec<entity::RecurringRun>>;
+}
+
+pub mod pg {
+    use super::*;
+    use axum::async_trait;
+
+    #[derive(Debug, Clone)]
+    pub struct RecurringRepositoryForDB {
+        pool: PgPool,
+    }
+
+    impl RecurringRepositoryForDB {
+        pub fn new(pool: PgPool) -> Self {
+            Self { pool }
+        }
+    }
+
+    #[async_trait]
+    impl RecurringRepository for RecurringRepositoryForDB {
+        async fn create(&self, message: &NewRecurringMessage) -> Result<entity::RecurringMessage> {
+            let row = sqlx::query_as::<_, RecurringMessageDBRow>(
+                r#"
+INSERT INTO recurring_messages
+    (name, cron, timezone, targets, text, display_name, avatar_url, layout, missed_runs, enabled, next_run_at)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+RETURNING id, name, cron, timezone, targets, text, display_name, avatar_url, layout,
+    missed_runs, enabled, next_run_at, version, created_at
+            "#,
+            )
+            .bind(message.name.as_str())
+            .bind(message.cron.as_str())
+            .bind(message.timezone.as_str())
+            .bind(message.targets.iter().map(|id| id.to_raw()).collect::<Vec<_>>())
+            .bind(message.text.as_str())
+            .bind(message.profile.display_name.as_deref())
+            .bind(message.profile.avatar_url.as_deref())
+            .bind(
+                message
+                    .layout
+                    .as_ref()
+                    .map(serde_json::to_value)
+                    .transpose()?,
+            )
+            .bind(message.missed_runs.to_string())
+            .bind(message.enabled)
+            .bind(message.next_run_at)
+            .fetch_one(&self.pool)
+            .await?;
+            entity::RecurringMessage::try_from(row)
+        }
+
+        async fn all(&self) -> Result<Vec<entity::RecurringMessage>> {
+            let rows = sqlx::query_as::<_, RecurringMessageDBRow>(
+                r#"
+SELECT id, name, cron, timezone, targets, text, display_name, avatar_url, layout,
+    missed_runs, enabled, next_run_at, version, created_at
+FROM recurring_messages
+ORDER BY id
+            "#,
+            )
+            .fetch_all(&self.pool)
+            .await?;
+            rows.into_iter()
+                .map(entity::RecurringMessage::try_from)
+                .collect()
+        }
+
+        async fn find(&self, id: entity::RecurringMessageId) -> Result<entity::RecurringMessage> {
+            let row = sqlx::query_as::<_, RecurringMessageDBRow>(
+                r#"
+SELECT id, name, cron, timezone, targets, text, display_name, avatar_url, layout,
+    missed_runs, enabled, next_run_at, version, created_at
+FROM recurring_messages
+WHERE id = $1
+            "#,
+            )
+            .bind(id.to_raw())
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => RecurringRepositoryError::NotFound(id),
+                _ => RecurringRepositoryError::Unexpected(e.to_string()),
+            })?;
+            entity::RecurringMessage::try_from(row)
+        }
+
+        async fn update(
+            &self,
+            id: entity::RecurringMessageId,
+            message: &NewRecurringMessage,
+        ) -> Result<entity::RecurringMessage> {
+            let row = sqlx::query_as::<_, RecurringMessageDBRow>(
+                r#"
+UPDATE recurring_messages
+SET name = $2, cron = $3, timezone = $4, targets = $5, text = $6, display_name = $7,
+    avatar_url = $8, layout = $9, missed_runs = $10, enabled = $11, next_run_at = $12,
+    version = version + 1, locked_until = NULL
+WHERE id = $1
+RETURNING id, name, cron, timezone, targets, text, display_name, avatar_url, layout,
+    missed_runs, enabled, next_run_at, version, created_at
+            "#,
+            )
+            .bind(id.to_raw())
+            .bind(message.name.as_str())
+            .bind(message.cron.as_str())
+            .bind(message.timezone.as_str())
+            .bind(
+                message
+                    .targets
+                    .iter()
+                    .map(|id| id.to_raw())
+                    .collect::<Vec<_>>(),
+            )
+            .bind(message.text.as_str())
+            .bind(message.profile.display_name.as_deref())
+            .bind(message.profile.avatar_url.as_deref())
+            .bind(
+                message
+                    .layout
+                    .as_ref()
+                    .map(serde_json::to_value)
+                    .transpose()?,
+            )
+            .bind(message.missed_runs.to_string())
+            .bind(message.enabled)
+            .bind(message.next_run_at)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => RecurringRepositoryError::NotFound(id),
+                _ => RecurringRepositoryError::Unexpected(e.to_string()),
+            })?;
+            entity::RecurringMessage::try_from(row)
+        }
+
+        async fn delete(&self, id: entity::RecurringMessageId) -> Result<()> {
+            // recurring_runs は ON DELETE CASCADE で消える
+            let res = sqlx::query(
+                r#"
+DELETE FROM recurring_messages
+WHERE id = $1
+            "#,
+            )
+            .bind(id.to_raw())
+            .execute(&self.pool)
+            .await?;
+            if res.rows_affected() == 0 {
+                return Err(RecurringRepositoryError::NotFound(id).into());
+            }
+            Ok(())
+        }
+
+        async fn claim(
+            &self,
+            limit: i64,
+            lease: Duration,
+        ) -> Result<Vec<entity::RecurringMessage>> {
+            let rows = sqlx::query_as::<_, RecurringMessageDBRow>(
+                r#"
+UPDATE recurring_messages
+SET locked_until = now() + make_interval(secs => $2)
+WHERE id IN (
+    SELECT id FROM recurring_messages
+    WHERE enabled AND next_run_at <= now() AND (locked_until IS NULL OR locked_until <= now())
+    ORDER BY next_run_at
+    LIMIT $1
+    FOR UPDATE SKIP LOCKED
+)
+RETURNING id, name, cron, timezone, targets, text, display_name, avatar_url, layout,
+    missed_runs, enabled, next_run_at, version, created_at
+            "#,
+            )
+            .bind(limit)
+            .bind(lease.as_secs_f64())
+            .fetch_all(&self.pool)
+            .await?;
+            rows.into_iter()
+                .map(entity::RecurringMessage::try_from)
+                .collect()
+        }
+
+        async fn start(
+            &self,
+            message: &entity::RecurringMessage,
+            runs: &[NewRecurringRun],
+            next_run_at: Option<DateTime<Utc>>,
+        ) -> Result<Vec<DateTime<Utc>>> {
+            let mut tx = self.pool.begin().await?;
+
+            let res = sqlx::query(
+                r#"
+UPDATE recurring_messages
+SET next_run_at = COALESCE($2, next_run_at),
+    enabled = enabled AND $2::TIMESTAMPTZ IS NOT NULL,
+    locked_until = NULL
+WHERE id = $1 AND version = $3
+            "#,
+            )
+            .bind(message.id.to_raw())
+            .bind(next_run_at)
+            .bind(message.version)
+            .execute(&mut tx)
+            .await?;
+            if res.rows_affected() == 0 {
+                return Ok(vec![]);
+            }
+
+            let mut started = vec![];
+            for run in runs {
+                let res = sqlx::query(
+                    r#"
+INSERT INTO recurring_runs (recurring_id, scheduled_at, status, message_id, error)
+VALUES ($1, $2, $3, $4, $5)
+ON CONFLICT (recurring_id, scheduled_at) DO NOTHING
+                "#,
+                )
+                .bind(message.id.to_raw())
+                .bind(run.scheduled_at)
+                .bind(run.state.to_string())
+                .bind(run.message_id.as_ref().map(|id| id.to_raw()))
+                .bind(run.error.as_deref())
+                .execute(&mut tx)
+                .await?;
+                if res.rows_affected() > 0 {
+                    started.push(run.scheduled_at);
+                }
+            }
+
+            tx.commit().await?;
+            Ok(started)
+        }
+
+        async fn finish(
+            &self,
+            id: &entity::RecurringMessageId,
+            run: &NewRecurringRun,
+        ) -> Result<()> {
+            sqlx::query(
+                r#"
+UPDATE recurring_runs
+SET status = $3, message_id = $4, error = $5
+WHERE recurring_id = $1 AND scheduled_at = $2
+            "#,
+            )
+            .bind(id.to_raw())
+            .bind(run.scheduled_at)
+            .bind(run.state.to_string())
+            .bind(run.message_id.as_ref().map(|id| id.to_raw()))
+            .bind(run.error.as_deref())
+            .execute(&self.pool)
+            .await?;
+            Ok(())
+        }
+
+        async fn runs(
+            &self,
+            id: entity::RecurringMessageId,
+            limit: i64,
+        ) -> Result<Vec<entity::RecurringRun>> {
+            let rows = sqlx::query_as::<_, RecurringRunDBRow>(
+                r#"
+SELECT scheduled_at, status, message_id, error, ran_at
+FROM recurring_runs
+WHERE recurring_id = $1
+ORDER BY id DESC
+LIMIT $2
+            "#,
+            )
+            .bind(id.to_raw())
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
+            rows.into_iter()
+                .map(entity::RecurringRun::try_from)
+                .collect()
+        }
+    }
+}
+
+// #[cfg(test)]
+pub mod test_utils {
+    use super::*;
+    use axum::async_trait;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::sync::RwLock;
+    use std::sync::RwLockReadGuard;
+    use std::sync::RwLockWriteGuard;
+
+    #[derive(Debug, Clone)]
+    struct RecurringOnMemory {
+        message: entity::RecurringMessage,
+        locked_until: Option<DateTime<Utc>>,
+        // 古い順
+        runs: Vec<entity::RecurringRun>,
+    }
+
+    type RecurringDBOnMemory = HashMap<entity::RecurringMessageId, RecurringOnMemory>;
+
+    // オンメモリの定期送信
+    #[derive(Clone, Debug)]
+    pub struct RecurringRepositoryForMemory {
+        store: Arc<RwLock<RecurringDBOnMemory>>,
+    }
+
+    impl RecurringRepositoryForMemory {
+        pub fn new() -> Self {
+            Self {
+                store: Arc::default(),
+            }
+        }
+
+        fn write_store_ref(&self) -> RwLockWriteGuard<'_, RecurringDBOnMemory> {
+            self.store.write().unwrap()
+        }
+
+        fn read_store_ref(&self) -> RwLockReadGuard<'_, RecurringDBOnMemory> {
+            self.store.read().unwrap()
+        }
+    }
+
+    fn to_entity(
+        id: entity::RecurringMessageId,
+        message: &NewRecurringMessage,
+        version: i32,
+        created_at: DateTime<Utc>,
+    ) -> entity::RecurringMessage {
+        entity::RecurringMessage {
+            id,
+            name: message.name.clone(),
+            cron: message.cron.clone(),
+            timezone: message.timezone.clone(),
+            targets: message.targets.clone(),
+            text: message.text.clone(),
+            profile: message.profile.clone(),
+            layout: message.layout.clone(),
+            missed_runs: message.missed_runs.clone(),
+            enabled: message.enabled,
+            next_run_at: message.next_run_at,
+            version,
+            created_at,
+        }
+    }
+
+    #[async_trait]
+    impl RecurringRepository for RecurringRepositoryForMemory {
+        async fn create(&self, message: &NewRecurringMessage) -> Result<entity::RecurringMessage> {
+            let mut store = self.write_store_ref();
+            let id = entity::RecurringMessageId::new(
+                store.keys().map(|id| id.to_raw()).max().unwrap_or(0) + 1,
+            );
+            let message = to_entity(id.clone(), message, 0, Utc::now());
+            store.insert(
+                id,
+                RecurringOnMemory {
+                    message: message.clone(),
+                    locked_until: None,
+                    runs: vec![],
+                },
+            );
+            Ok(message)
+        }
+
+        async fn all(&self) -> Result<Vec<entity::RecurringMessage>> {
+            let store = self.read_store_ref();
+            let mut messages = store
+                .values()
+                .map(|r| r.message.clone())
+                .collect::<Vec<_>>();
+            messages.sort_by_key(|m| m.id.clone());
+            Ok(messages)
+        }
+
+        async fn find(&self, id: entity::RecurringMessageId) -> Result<entity::RecurringMessage> {
+            let store = self.read_store_ref();
+            let recurring = store
+                .get(&id)
+                .ok_or(RecurringRepositoryError::NotFound(id))?;
+            Ok(recurring.message.clone())
+        }
+
+        async fn update(
+            &self,
+            id: entity::RecurringMessageId,
+            message: &NewRecurringMessage,
+        ) -> Result<entity::RecurringMessage> {
+            let mut store = self.write_store_ref();
+            let recurring = store
+                .get_mut(&id)
+                .ok_or(RecurringRepositoryError::NotFound(id.clone()))?;
+            let old = &recurring.message;
+            recurring.message = to_entity(id, message, old.version + 1, old.created_at);
+            recurring.locked_until = None;
+            Ok(recurring.message.clone())
+        }
+
+        async fn delete(&self, id: entity::RecurringMessageId) -> Result<()> {
+            let mut store = self.write_store_ref();
+            store
+                .remove(&id)
+                .ok_or(RecurringRepositoryError::NotFound(id))?;
+            Ok(())
+        }
+
+        async fn claim(
+            &self,
+            limit: i64,
+            lease: Duration,
+        ) -> Result<Vec<entity::RecurringMessage>> {
+            let mut store = self.write_store_ref();
+            let now = Utc::now();
+            let mut due = store
+                .values_mut()
+                .filter(|r| {
+                    r.message.enabled
+                        && r.message.next_run_at <= now
+                        && r.locked_until.is_none_or(|t| t <= now)
+                })
+                .collect::<Vec<_>>();
+            due.sort_by_key(|r| r.message.next_run_at);
+
+            Ok(due
+                .into_iter()
+                .take(limit.max(0) as usize)
+                .map(|r| {
+                    r.locked_until =
+                        Some(now + chrono::Duration::from_std(lease).unwrap_or_default());
+                    r.message.clone()
+                })
+                .collect())
+        }
+
+        async fn start(
+            &self,
+            message: &entity::RecurringMessage,
+            runs: &[NewRecurringRun],
+            next_run_at: Option<DateTime<Utc>>,
+        ) -> Result<Vec<DateTime<Utc>>> {
+            let mut store = self.write_store_ref();
+            let recurring = store
+                .get_mut(&message.id)
+                .ok_or(RecurringRepositoryError::NotFound(message.id.clone()))?;
+            if recurring.message.version != message.version {
+                return Ok(vec![]);
+            }
+            let now = Utc::now();
+            let mut started = vec![];
+            for run in runs {
+                if recurring
+                    .runs
+                    .iter()
+                    .any(|r| r.scheduled_at == run.scheduled_at)
+                {
+                    continue;
+                }
+                recurring.runs.push(entity::RecurringRun {
+                    scheduled_at: run.scheduled_at,
+                    state: run.state.clone(),
+                    message_id: run.message_id.clone(),
+                    error: run.error.clone(),
+                    ran_at: now,
+                });
+                started.push(run.scheduled_at);
+            }
+            match next_run_at {
+                Some(next_run_at) => recurring.message.next_run_at = next_run_at,
+                None => recurring.message.enabled = false,
+            }
+            recurring.locked_until = None;
+            Ok(started)
+        }
+
+        async fn finish(
+            &self,
+            id: &entity::RecurringMessageId,
+            run: &NewRecurringRun,
+        ) -> Result<()> {
+            let mut store = self.write_store_ref();
+            let recurring = store
+                .get_mut(id)
+                .ok_or(RecurringRepositoryError::NotFound(id.clone()))?;
+            if let Some(r) = recurring
+                .runs
+                .iter_mut()
+                .find(|r| r.scheduled_at == run.scheduled_at)
+            {
+                r.state = run.state.clone();
+                r.message_id = run.message_id.clone();
+                r.error = run.error.clone();
+            }
+            Ok(())
+        }
+
+        async fn runs(
+            &self,
+            id: entity::RecurringMessageId,
+            limit: i64,
+        ) -> Result<Vec<entity::RecurringRun>> {
+            let store = self.read_store_ref();
+            let recurring = store
+                .get(&id)
+                .ok_or(RecurringRepositoryError::NotFound(id))?;
+            Ok(recurring
+                .runs
+                .iter()
+                .rev()
+                .take(limit.max(0) as usize)
+                .cloned()
+                .collect())
+        }
+    }
+}
@@ -0,0 +1,816 @@
+use crate::entity;
+use crate::message::outbox;
+use crate::message::repository::{MessageRepository, NewMessage};
+use crate::message::service::{check_targets, send_message, DeliveryConfig};
+use crate::recurring::repository::{NewRecurringMessage, NewRecurringRun, RecurringRepository};
+use crate::workspace::repository::WorkspaceRepository;
+
+use ::anyhow::Result;
+use ::chrono::{DateTime, Utc};
+use ::chrono_tz::Tz;
+use ::futures::stream;
+use ::futures::StreamExt;
+use ::serde::{Deserialize, Serialize};
+use ::std::env;
+use ::std::str::FromStr;
+use ::std::sync::Arc;
+use ::std::time::Duration;
+use ::thiserror::Error;
+use ::tokio::task::JoinHandle;
+use ::validator::Validate;
+
+#[derive(Debug, Error)]
+pub enum RecurringError {
+    #[error("Invalid cron expression: {0}")]
+    InvalidCron(String),
+    #[error("Invalid timezone: {0}")]
+    InvalidTimezone(String),
+}
+
+// 定期送信の scheduler の設定
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecurringConfig {
+    pub poll_interval: Duration,
+    pub batch_size: i64,
+    // 送信時刻からこれ以上遅れた送信は, API が止まっていた間に過ぎたものとして missed_runs に従う
+    pub grace: Duration,
+}
+
+impl Default for RecurringConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(10),
+            batch_size: 32,
+            grace: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+// 1 回の実行で記録する, 過ぎた送信時刻の最大数
+const MAX_DUE_RUNS: usize = 100;
+
+/// 5 つの field (分 時 日 月 曜日) の cron 式か, `@daily` などの略記を parse する.
+/// cron crate の式は秒の field を持ち, 曜日を 1 (日曜日) から 7 (土曜日) で数えるので変換する
+pub fn parse_cron(expr: &str) -> Result<cron::Schedule, RecurringError> {
+    let invalid = |reason: String| RecurringError::InvalidCron(format!("{} ({})", expr, reason));
+    let expr = expr.trim();
+    if expr.starts_with('@') {
+        return cron::Schedule::from_str(expr).map_err(|e| invalid(e.to_string()));
+    }
+    let fields = expr.split_whitespace().collect::<Vec<_>>();
+    let [minute, hour, day, month, weekday] = fields.as_slice() else {
+        return Err(invalid(
+            "expected 5 fields: minute hour day month weekday".to_string(),
+        ));
+    };
+    let weekday = weekday
+        .split(',')
+        .map(convert_weekday)
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| invalid(format!("invalid weekday {}", weekday)))?
+        .join(",");
+    let expr = format!("0 {} {} {} {} {}", minute, hour, day, month, weekday);
+    cron::Schedule::from_str(&expr).map_err(|e| invalid(e.to_string()))
+}
+
+// 曜日の field の 1 項目を, 0 と 7 を日曜日とする数え方から cron crate の数え方にする.
+// MON などの名前と `*` はそのまま
+fn convert_weekday(item: &str) -> Option<String> {
+    let (range, step) = match item.split_once('/') {
+        Some((range, step)) => (range, Some(step)),
+        None => (item, None),
+    };
+    let day = |s: &str| match s.parse::<u32>() {
+        Ok(n) if n <= 7 => Some((n % 7 + 1).to_string()),
+        Ok(_) => None,
+        Err(_) => Some(s.to_string()),
+    };
+    let range = match range.split_once('-') {
+        // 日曜日 (7) で終わる範囲は, 土曜日までの範囲と日曜日に分ける
+        Some((start, "7")) if step.is_none() => format!("{}-7,1", day(start)?),
+        Some((start, end)) => format!("{}-{}", day(start)?, day(end)?),
+        None => day(range)?,
+    };
+    Some(match step {
+        Some(step) => format!("{}/{}", range, step),
+        None => range,
+    })
+}
+
+pub fn parse_timezone(timezone: &str) -> Result<Tz, RecurringError> {
+    Tz::from_str(timezone).map_err(|_| RecurringError::InvalidTimezone(timezone.to_string()))
+}
+
+/// `after` より後の最初の送信時刻
+pub fn next_run(schedule: &cron::Schedule, tz: Tz, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    schedule
+        .after(&after.with_timezone(&tz))
+        .next()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// `from` 以降 `now` までに過ぎた送信時刻を, 新しい順に最大 `MAX_DUE_RUNS` 件返す
+fn due_runs(
+    schedule: &cron::Schedule,
+    tz: Tz,
+    from: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> Vec<DateTime<Utc>> {
+    // after は指定した時刻を含まないので, now ちょうどの送信時刻も含むように 1 秒進める
+    let until = (now + chrono::Duration::seconds(1)).with_timezone(&tz);
+    schedule
+        .after(&until)
+        .rev()
+        .map(|t| t.with_timezone(&Utc))
+        .take_while(|t| *t >= from)
+        .take(MAX_DUE_RUNS)
+        .collect()
+}
+
+// 環境変数 TZ の timezone. 未設定か不正な場合は UTC
+pub fn default_timezone() -> String {
+    env::var("TZ")
+        .ok()
+        .filter(|tz| parse_timezone(tz).is_ok())
+        .unwrap_or("UTC".to_string())
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+// 定期送信の作成の request body
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Validate)]
+pub struct RecurringPayload {
+    #[validate(length(min = 1, max = 100, message = "name must be 1 to 100 characters"))]
+    pub name: String,
+    pub cron: String,
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    #[validate(length(min = 1, message = "targets can not be empty"))]
+    pub targets: Vec<entity::WorkspaceIdTypeAlias>,
+    #[validate(length(min = 1, message = "text can not be empty"))]
+    pub text: String,
+    #[validate(length(min = 1, max = 80, message = "display_name must be 1 to 80 characters"))]
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[validate(url(message = "avatar_url must be a URL"))]
+    #[serde(default)]
+    pub avatar_url: Option<String>,
+    #[validate]
+    #[serde(default)]
+    pub layout: Option<entity::Layout>,
+    #[serde(default)]
+    pub missed_runs: entity::MissedRunPolicy,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+impl RecurringPayload {
+    /// cron 式と timezone を検証し, `now` より後の最初の送信時刻を求める.
+    /// targets は `check_targets` で重複を除いたもの
+    fn to_new(
+        &self,
+        targets: Vec<entity::WorkspaceId>,
+        now: DateTime<Utc>,
+    ) -> Result<NewRecurringMessage, RecurringError> {
+        let schedule = parse_cron(&self.cron)?;
+        let tz = parse_timezone(&self.timezone)?;
+        let next_run_at = next_run(&schedule, tz, now)
+            .ok_or_else(|| RecurringError::InvalidCron(format!("{} (never runs)", self.cron)))?;
+        Ok(NewRecurringMessage {
+            name: self.name.clone(),
+            cron: self.cron.clone(),
+            timezone: self.timezone.clone(),
+            targets,
+            text: self.text.clone(),
+            profile: entity::Profile {
+                display_name: self.display_name.clone(),
+                avatar_url: self.avatar_url.clone(),
+            },
+            layout: self.layout.clone(),
+            missed_runs: self.missed_runs.clone(),
+            enabled: self.enabled,
+            next_run_at,
+        })
+    }
+}
+
+// 省略した項目と null を区別する. 省略した場合は None, null の場合は Some(None)
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+// 定期送信の更新の PATCH request body. 省略した項目は今のまま.
+// display_name, avatar_url と layout は null で消す
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize, Validate)]
+pub struct UpdateRecurringPayload {
+    #[validate(length(min = 1, max = 100, message = "name must be 1 to 100 characters"))]
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub cron: Option<String>,
+    #[serde(default)]
+    pub timezone: Option<String>,
+    #[validate(length(min = 1, message = "targets can not be empty"))]
+    #[serde(default)]
+    pub targets: Option<Vec<entity::WorkspaceIdTypeAlias>>,
+    #[validate(length(min = 1, message = "text can not be empty"))]
+    #[serde(default)]
+    pub text: Option<String>,
+    #[validate(length(min = 1, max = 80, message = "display_name must be 1 to 80 characters"))]
+    #[serde(default, deserialize_with = "nullable")]
+    pub display_name: Option<Option<String>>,
+    #[validate(url(message = "avatar_url must be a URL"))]
+    #[serde(default, deserialize_with = "nullable")]
+    pub avatar_url: Option<Option<String>>,
+    #[validate]
+    #[serde(default, deserialize_with = "nullable")]
+    pub layout: Option<Option<entity::Layout>>,
+    #[serde(default)]
+    pub missed_runs: Option<entity::MissedRunPolicy>,
+    #[serde(default)]
+    pub enabled: Option<bool>,
+}
+
+impl UpdateRecurringPayload {
+    /// 今の定期送信に, 指定した項目だけを当てる
+    fn apply(self, current: entity::RecurringMessage) -> RecurringPayload {
+        RecurringPayload {
+            name: self.name.unwrap_or(current.name),
+            cron: self.cron.unwrap_or(current.cron),
+            timezone: self.timezone.unwrap_or(current.timezone),
+            targets: self
+                .targets
+                .unwrap_or_else(|| current.targets.iter().map(|id| id.to_raw()).collect()),
+            text: self.text.unwrap_or(current.text),
+            display_name: self.display_name.unwrap_or(current.profile.display_name),
+            avatar_url: self.avatar_url.unwrap_or(current.profile.avatar_url),
+            layout: self.layout.unwrap_or(current.layout),
+            missed_runs: self.missed_runs.unwrap_or(current.missed_runs),
+            enabled: self.enabled.unwrap_or(current.enabled),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResponseRecurring {
+    pub id: entity::RecurringMessageIdTypeAlias,
+    pub name: String,
+    pub cron: String,
+    pub timezone: String,
+    pub targets: Vec<entity::WorkspaceIdTypeAlias>,
+    pub text: String,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub layout: Option<entity::Layout>,
+    pub missed_runs: entity::MissedRunPolicy,
+    pub enabled: bool,
+    pub next_run_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<entity::RecurringMessage> for ResponseRecurring {
+    fn from(message: entity::RecurringMessage) -> Self {
+        Self {
+            id: message.id.to_raw(),
+            name: message.name,
+            cron: message.cron,
+            timezone: message.timezone,
+            targets: message.targets.iter().map(|id| id.to_raw()).collect(),
+            text: message.text,
+            display_name: message.profile.display_name,
+            avatar_url: message.profile.avatar_url,
+            layout: message.layout,
+            missed_runs: message.missed_runs,
+            enabled: message.enabled,
+            next_run_at: message.next_run_at,
+            created_at: message.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResponseRecurringRun {
+    pub scheduled_at: DateTime<Utc>,
+    // sending | sent | skipped | failed
+    pub state: String,
+    // 送った message の ID (GET /messages/:id で送信先ごとの結果を見られる)
+    pub message_id: Option<entity::MessageIdTypeAlias>,
+    pub error: Option<String>,
+    pub ran_at: DateTime<Utc>,
+}
+
+impl From<entity::RecurringRun> for ResponseRecurringRun {
+    fn from(run: entity::RecurringRun) -> Self {
+        Self {
+            scheduled_at: run.scheduled_at,
+            state: run.state.to_string(),
+            message_id: run.message_id.map(|id| id.to_raw()),
+            error: run.error,
+            ran_at: run.ran_at,
+        }
+    }
+}
+
+/// 送信する時と同じように, targets の重複を除き, 分割しない target に収まらない text を拒否する
+async fn check_payload<T>(
+    repo: &T,
+    config: &DeliveryConfig,
+    payload: &RecurringPayload,
+) -> Result<NewRecurringMessage>
+where
+    T: WorkspaceRepository,
+{
+    let targets = check_targets(
+        &repo.all().await?,
+        config,
+        payload.targets.clone(),
+        &payload.text,
+        payload.layout.as_ref(),
+    )?;
+    Ok(payload.to_new(targets, Utc::now())?)
+}
+
+pub async fn create_recurring<T, R>(
+    repo: Arc<T>,
+    recurring: Arc<R>,
+    config: &DeliveryConfig,
+    payload: RecurringPayload,
+) -> Result<ResponseRecurring>
+where
+    T: WorkspaceRepository,
+    R: RecurringRepository,
+{
+    let message = check_payload(repo.as_ref(), config, &payload).await?;
+    let message = recurring.create(&message).await?;
+    Ok(ResponseRecurring::from(message))
+}
+
+pub async fn all_recurring<R>(repo: Arc<R>) -> Result<Vec<ResponseRecurring>>
+where
+    R: RecurringRepository,
+{
+    let messages = repo.all().await?;
+    Ok(messages.into_iter().map(ResponseRecurring::from).collect())
+}
+
+pub async fn find_recurring<R>(
+    repo: Arc<R>,
+    id: entity::RecurringMessageId,
+) -> Result<ResponseRecurring>
+where
+    R: RecurringRepository,
+{
+    let message = repo.find(id).await?;
+    Ok(ResponseRecurring::from(message))
+}
+
+/// 定期送信の指定した項目を変える. 次の送信時刻は現在から求め直す
+pub async fn update_recurring<T, R>(
+    repo: Arc<T>,
+    recurring: Arc<R>,
+    config: &DeliveryConfig,
+    id: entity::RecurringMessageId,
+    payload: UpdateRecurringPayload,
+) -> Result<ResponseRecurring>
+where
+    T: WorkspaceRepository,
+    R: RecurringRepository,
+{
+    let payload = payload.apply(recurring.find(id.clone()).await?);
+    let message = check_payload(repo.as_ref(), config, &payload).await?;
+    let message = recurring.update(id, &message).await?;
+    Ok(ResponseRecurring::from(message))
+}
+
+pub async fn delete_recurring<R>(repo: Arc<R>, id: entity::RecurringMessageId) -> Result<()>
+where
+    R: RecurringRepository,
+{
+    repo.delete(id).await
+}
+
+pub async fn recurring_runs<R>(
+    repo: Arc<R>,
+    id: entity::RecurringMessageId,
+    limit: i64,
+) -> Result<Vec<ResponseRecurringRun>>
+where
+    R: RecurringRepository,
+{
+    // 存在しない定期送信は NotFound にする
+    repo.find(id.clone()).await?;
+    let runs = repo.runs(id, limit).await?;
+    Ok(runs.into_iter().map(ResponseRecurringRun::from).collect())
+}
+
+/// 送信時刻を過ぎた定期送信を送り続ける scheduler を起動する
+pub fn spawn_scheduler<T, M, R>(
+    repo: Arc<T>,
+    outbox: Arc<M>,
+    recurring: Arc<R>,
+    delivery: DeliveryConfig,
+    config: RecurringConfig,
+) -> JoinHandle<()>
+where
+    T: WorkspaceRepository,
+    M: MessageRepository,
+    R: RecurringRepository,
+{
+    tokio::spawn(async move {
+        loop {
+            match run_due(
+                repo.clone(),
+                outbox.clone(),
+                recurring.as_ref(),
+                &delivery,
+                &config,
+            )
+            .await
+            {
+                // 取り出した件数が batch_size に満たなければしばらく待つ
+                Ok(n) if n as i64 >= config.batch_size => continue,
+                Ok(_) => {}
+                Err(e) => tracing::error!("failed to run recurring messages: {}", e),
+            }
+            tokio::time::sleep(config.poll_interval).await;
+        }
+    })
+}
+
+/// 送信時刻を過ぎた定期送信を 1 batch 分送り, 取り出した件数を返す
+pub async fn run_due<T, M, R>(
+    repo: Arc<T>,
+    outbox: Arc<M>,
+    recurring: &R,
+    delivery: &DeliveryConfig,
+    config: &RecurringConfig,
+) -> Result<usize>
+where
+    T: WorkspaceRepository,
+    M: MessageRepository,
+    R: RecurringRepository,
+{
+    let messages = recurring
+        .claim(config.batch_size, outbox::lease(delivery))
+        .await?;
+    let n = messages.len();
+
+    stream::iter(messages)
+        .for_each_concurrent(delivery.concurrency.max(1), |message| {
+            let (repo, outbox) = (repo.clone(), outbox.clone());
+            async move {
+                let id = message.id.clone();
+                if let Err(e) = run(repo, outbox, recurring, delivery, config, message).await {
+                    tracing::error!("failed to run recurring message {}: {}", id, e);
+                }
+            }
+        })
+        .await;
+
+    Ok(n)
+}
+
+/// 過ぎた送信時刻のうち最新のものだけを送る.
+/// 最新の送信時刻から `grace` 以上遅れた場合は, missed_runs が catch_up の場合だけ送る.
+/// 2 回送らないように, 送る前に実行結果と次の送信時刻を記録する
+async fn run<T, M, R>(
+    repo: Arc<T>,
+    outbox: Arc<M>,
+    recurring: &R,
+    delivery: &DeliveryConfig,
+    config: &RecurringConfig,
+    message: entity::RecurringMessage,
+) -> Result<()>
+where
+    T: WorkspaceRepository,
+    M: MessageRepository,
+    R: RecurringRepository,
+{
+    let now = Utc::now();
+    let (schedule, tz) = match parse_cron(&message.cron)
+        .and_then(|schedule| Ok((schedule, parse_timezone(&message.timezone)?)))
+    {
+        Ok(v) => v,
+        // 登録時に検証しているので通常は起きないが, 起きた場合は無効にする
+        Err(e) => {
+            let run = NewRecurringRun {
+                scheduled_at: message.next_run_at,
+                state: entity::RunState::Failed,
+                message_id: None,
+                error: Some(e.to_string()),
+            };
+            recurring.start(&message, &[run], None).await?;
+            return Ok(());
+        }
+    };
+
+    let grace = chrono::Duration::from_std(config.grace).unwrap_or_default();
+    let mut runs = vec![];
+    let mut send_at = None;
+    for (i, scheduled_at) in due_runs(&schedule, tz, message.next_run_at, now)
+        .into_iter()
+        .enumerate()
+    {
+        let on_time = now - scheduled_at <= grace;
+        let state =
+            if i == 0 && (on_time || message.missed_runs == entity::MissedRunPolicy::CatchUp) {
+                send_at = Some(scheduled_at);
+                entity::RunState::Sending
+            } else {
+                entity::RunState::Skipped
+            };
+        runs.push(NewRecurringRun {
+            scheduled_at,
+            state,
+            message_id: None,
+            error: None,
+        });
+    }
+    // 古い順に記録する
+    runs.reverse();
+    let started = recurring
+        .start(&message, &runs, next_run(&schedule, tz, now))
+        .await?;
+    // 他の scheduler が既に記録した送信時刻は送らない
+    let Some(scheduled_at) = send_at.filter(|t| started.contains(t)) else {
+        return Ok(());
+    };
+
+    tracing::info!("send recurring message {}", message.id);
+    let new_message = NewMessage {
+        profile: message.profile.clone(),
+        layout: message.layout.clone(),
+        ..NewMessage::new(&message.text)
+    };
+    let targets = message.targets.iter().map(|id| id.to_raw()).collect();
+    let run = match send_message(repo, outbox, delivery, targets, new_message).await {
+        Ok(res) => NewRecurringRun {
+            scheduled_at,
+            state: entity::RunState::Sent,
+            message_id: Some(entity::MessageId::new(res.message_id)),
+            error: None,
+        },
+        Err(e) => NewRecurringRun {
+            scheduled_at,
+            state: entity::RunState::Failed,
+            message_id: None,
+            error: Some(e.to_string()),
+        },
+    };
+    recurring.finish(&message.id, &run).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message::repository::test_utils::MessageRepositoryForMemory;
+    use crate::recurring::repository::test_utils::RecurringRepositoryForMemory;
+    use crate::workspace::repository::test_utils::WorkspaceRepositoryForMemory;
+    use ::chrono::DurationRound;
+
+    fn tokyo(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn standup_payload(cron: &str, missed_runs: entity::MissedRunPolicy) -> RecurringPayload {
+        RecurringPayload {
+            name: "standup".to_string(),
+            cron: cron.to_string(),
+            timezone: "Asia/Tokyo".to_string(),
+            targets: vec![1],
+            text: "standup".to_string(),
+            display_name: None,
+            avatar_url: None,
+            layout: None,
+            missed_runs,
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn cron_uses_standard_fields_and_timezone() {
+        let tz = parse_timezone("Asia/Tokyo").unwrap();
+        // 平日の 9:30 (2023-08-04 は金曜日)
+        let schedule = parse_cron("30 9 * * 1-5").unwrap();
+        let after = tokyo("2023-08-04T10:00:00+09:00");
+        assert_eq!(
+            next_run(&schedule, tz, after),
+            Some(tokyo("2023-08-07T09:30:00+09:00"))
+        );
+
+        // 0 と 7 は日曜日
+        for expr in ["0 18 * * 0", "0 18 * * 7", "0 18 * * SUN", "0 18 * * 6-7"] {
+            let schedule = parse_cron(expr).unwrap();
+            let next = next_run(&schedule, tz, tokyo("2023-08-05T19:00:00+09:00"));
+            assert_eq!(next, Some(tokyo("2023-08-06T18:00:00+09:00")), "{}", expr);
+        }
+        assert!(parse_cron("@daily").is_ok());
+
+        assert!(matches!(
+            parse_cron("0 9 * *"),
+            Err(RecurringError::InvalidCron(_))
+        ));
+        assert!(matches!(
+            parse_cron("0 9 * * 8"),
+            Err(RecurringError::InvalidCron(_))
+        ));
+        assert!(matches!(
+            parse_timezone("Asia/Edo"),
+            Err(RecurringError::InvalidTimezone(_))
+        ));
+    }
+
+    #[test]
+    fn due_runs_are_newest_first() {
+        let tz = parse_timezone("Asia/Tokyo").unwrap();
+        let schedule = parse_cron("0 9 * * *").unwrap();
+        let runs = due_runs(
+            &schedule,
+            tz,
+            tokyo("2023-08-01T09:00:00+09:00"),
+            tokyo("2023-08-03T09:00:00+09:00"),
+        );
+        assert_eq!(
+            runs,
+            vec![
+                tokyo("2023-08-03T09:00:00+09:00"),
+                tokyo("2023-08-02T09:00:00+09:00"),
+                tokyo("2023-08-01T09:00:00+09:00"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn missed_runs_are_skipped_or_caught_up() {
+        let repo = Arc::new(WorkspaceRepositoryForMemory::new());
+        let outbox = Arc::new(MessageRepositoryForMemory::new());
+        let recurring = RecurringRepositoryForMemory::new();
+        let delivery = DeliveryConfig::default();
+        let config = RecurringConfig::default();
+
+        // 毎時の送信時刻を 3 回過ぎるまで止まっていたことにする
+        let now = Utc::now();
+        let hour = now.duration_trunc(chrono::Duration::hours(1)).unwrap();
+        let stopped_at = hour - chrono::Duration::minutes(150);
+        for missed_runs in [
+            entity::MissedRunPolicy::Skip,
+            entity::MissedRunPolicy::CatchUp,
+        ] {
+            let payload = standup_payload("0 * * * *", missed_runs);
+            let message = recurring
+                .create(
+                    &payload
+                        .to_new(vec![entity::WorkspaceId::new(1)], stopped_at)
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert!(message.next_run_at <= now);
+        }
+
+        // 猶予を 0 にすると, 過ぎた送信時刻は全て遅れたものになる
+        let config = RecurringConfig {
+            grace: Duration::ZERO,
+            ..config
+        };
+        let n = run_due(repo.clone(), outbox.clone(), &recurring, &delivery, &config)
+            .await
+            .unwrap();
+        assert_eq!(n, 2);
+
+        let skip = recurring
+            .runs(entity::RecurringMessageId::new(1), 10)
+            .await
+            .unwrap();
+        assert_eq!(skip.len(), 3);
+        assert!(skip.iter().all(|r| r.state == entity::RunState::Skipped));
+
+        // catch_up は最新の送信時刻の分だけ送る
+        let catch_up = recurring
+            .runs(entity::RecurringMessageId::new(2), 10)
+            .await
+            .unwrap();
+        assert_eq!(catch_up.len(), 3);
+        assert_eq!(catch_up[0].state, entity::RunState::Sent);
+        assert_eq!(catch_up[0].scheduled_at, hour);
+        assert!(catch_up[1..]
+            .iter()
+            .all(|r| r.state == entity::RunState::Skipped));
+        let message = outbox
+            .find(catch_up[0].message_id.clone().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(message.text, "standup");
+
+        // 次の送信時刻まで取り出されない
+        for message in recurring.all().await.unwrap() {
+            assert!(message.next_run_at > now);
+        }
+        let n = run_due(repo, outbox, &recurring, &delivery, &config)
+            .await
+            .unwrap();
+        assert_eq!(n, 0);
+    }
+
+    #[tokio::test]
+    async fn recorded_runs_are_not_sent_again() {
+        let repo = Arc::new(WorkspaceRepositoryForMemory::new());
+        let outbox = Arc::new(MessageRepositoryForMemory::new());
+        let recurring = RecurringRepositoryForMemory::new();
+        let delivery = DeliveryConfig::default();
+        let config = RecurringConfig::default();
+
+        let payload = standup_payload("* * * * *", entity::MissedRunPolicy::CatchUp);
+        let message = recurring
+            .create(
+                &payload
+                    .to_new(
+                        vec![entity::WorkspaceId::new(1)],
+                        Utc::now() - chrono::Duration::minutes(1),
+                    )
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // 他の scheduler が送信中として記録したが, 次の送信時刻はまだ進めていない
+        let run = NewRecurringRun {
+            scheduled_at: message.next_run_at,
+            state: entity::RunState::Sending,
+            message_id: None,
+            error: None,
+        };
+        let started = recurring
+            .start(
+                &message,
+                std::slice::from_ref(&run),
+                Some(message.next_run_at),
+            )
+            .await
+            .unwrap();
+        assert_eq!(started, vec![message.next_run_at]);
+
+        let n = run_due(repo, outbox.clone(), &recurring, &delivery, &config)
+            .await
+            .unwrap();
+        assert_eq!(n, 1);
+        assert!(outbox.all(10, None).await.unwrap().is_empty());
+        let runs = recurring.runs(message.id.clone(), 10).await.unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].state, entity::RunState::Sending);
+        assert!(recurring.find(message.id).await.unwrap().next_run_at > Utc::now());
+    }
+
+    #[tokio::test]
+    async fn update_while_running_is_kept() {
+        let recurring = RecurringRepositoryForMemory::new();
+        let payload = standup_payload("* * * * *", entity::MissedRunPolicy::Skip);
+        let now = Utc::now();
+        recurring
+            .create(
+                &payload
+                    .to_new(
+                        vec![entity::WorkspaceId::new(1)],
+                        now - chrono::Duration::minutes(1),
+                    )
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let claimed = recurring.claim(10, Duration::from_secs(60)).await.unwrap();
+        assert_eq!(claimed.len(), 1);
+
+        // 送信中に cron 式を変えた場合は, 更新前の cron 式で求めた次の送信時刻で上書きしない
+        let payload = standup_payload("0 9 * * *", entity::MissedRunPolicy::Skip);
+        let updated = recurring
+            .update(
+                claimed[0].id.clone(),
+                &payload
+                    .to_new(vec![entity::WorkspaceId::new(1)], now)
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let run = NewRecurringRun {
+            scheduled_at: claimed[0].next_run_at,
+            state: entity::RunState::Sending,
+            message_id: None,
+            error: None,
+        };
+        let next_run_at = now + chrono::Duration::minutes(1);
+        let started = recurring
+            .start(&claimed[0], &[run], Some(next_run_at))
+            .await
+            .unwrap();
+        assert!(started.is_empty());
+        let message = recurring.find(updated.id.clone()).await.unwrap();
+        assert_eq!(message.next_run_at, updated.next_run_at);
+        assert!(recurring.runs(updated.id, 10).await.unwrap().is_empty());
+    }
+}
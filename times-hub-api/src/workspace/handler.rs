@@ -14,31 +14,18 @@ use ::axum::BoxError;
 use ::axum::Json;
 use ::http::Request;
 use ::serde::de::DeserializeOwned;
-use ::serde::Deserialize;
-use ::serde::Serialize;
-use ::std::str::FromStr;
 use ::std::sync::Arc;
 use ::validator::Validate;
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Validate)]
-pub struct UpdateWorkspacePayload {
-    #[validate(length(min = 1, message = "text can not be empty"))]
-    #[validate(length(max = 100, message = "text can not be longer than 100 characters"))]
-    pub name: String,
-    #[validate(length(min = 1, message = "text can not be empty"))]
-    pub ws_type: String,
-    #[validate(length(min = 1, message = "text can not be empty"))]
-    pub webhook_url: String,
-}
-
 pub async fn create_workspace<T>(
     Extension(repo): Extension<Arc<T>>,
+    Extension(validate): Extension<service::ValidateConfig>,
     ValidatedJson(payload): ValidatedJson<service::CreateWorkspacePayload>,
 ) -> Result<impl IntoResponse, StatusCode>
 where
     T: WorkspaceRepository,
 {
-    let ws_vec = service::create_workspace(repo, payload)
+    let ws_vec = service::create_workspace(repo, validate, payload)
         .await
         .map_err(repository_error_to_status_code)?;
     Ok((StatusCode::CREATED, Json(ws_vec)))
@@ -73,29 +60,17 @@ where
 
 pub async fn update_workspace<T>(
     Extension(repo): Extension<Arc<T>>,
+    Extension(validate): Extension<service::ValidateConfig>,
     Path(id): Path<i32>,
-    ValidatedJson(payload): ValidatedJson<UpdateWorkspacePayload>,
+    ValidatedJson(payload): ValidatedJson<service::UpdateWorkspacePayload>,
 ) -> Result<impl IntoResponse, StatusCode>
 where
     T: WorkspaceRepository,
 {
     let id = entity::WorkspaceId::new(id);
-    let ws = entity::Workspace {
-        id,
-        name: payload.name,
-        ws_type: entity::WorkspaceType::from_str(payload.ws_type.as_str()).map_err(|_| {
-            tracing::warn!("error: invalid workspace type: {}", payload.ws_type);
-            StatusCode::BAD_REQUEST
-        })?,
-        webhook_url: payload.webhook_url,
-    };
-    let ws = service::update_workspace(repo, ws).await.map_err(|e| {
-        tracing::error!("error: {}", e);
-        match e.downcast_ref::<RepositoryError>() {
-            Some(RepositoryError::NotFound(_)) => StatusCode::NOT_FOUND,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        }
-    })?;
+    let ws = service::update_workspace(repo, validate, id, payload)
+        .await
+        .map_err(repository_error_to_status_code)?;
     Ok((StatusCode::CREATED, Json(ws)))
 }
 
@@ -116,6 +91,9 @@ where
 
 pub fn repository_error_to_status_code(e: anyhow::Error) -> StatusCode {
     tracing::error!("error: {}", e);
+    if e.downcast_ref::<service::WorkspaceError>().is_some() {
+        return StatusCode::BAD_REQUEST;
+    }
     match e.downcast_ref::<RepositoryError>() {
         Some(RepositoryError::NotFound(_)) => StatusCode::NOT_FOUND,
         _ => StatusCode::INTERNAL_SERVER_ERROR,
@@ -23,6 +23,18 @@ pub struct WorkspaceDBRow {
     pub name: String,
     pub ws_type: String,
     pub webhook_url: String,
+    pub config: serde_json::Value,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
+impl WorkspaceDBRow {
+    fn profile(&self) -> entity::Profile {
+        entity::Profile {
+            display_name: self.display_name.clone(),
+            avatar_url: self.avatar_url.clone(),
+        }
+    }
 }
 
 #[async_trait]
@@ -62,26 +74,36 @@ pub mod pg {
                 name: payload.name.clone(),
                 ws_type: entity::WorkspaceType::from_str(payload.ws_type.as_str())?,
                 webhook_url: payload.webhook_url.clone(),
+                config: payload.config.clone(),
+                profile: entity::Profile {
+                    display_name: payload.display_name.clone(),
+                    avatar_url: payload.avatar_url.clone(),
+                },
             };
 
             let ws = sqlx::query_as::<_, WorkspaceDBRow>(
                 r#"
-INSERT INTO workspaces (name, ws_type, webhook_url)
-VALUES ($1, $2, $3)
-RETURNING id, name, ws_type, webhook_url
+INSERT INTO workspaces (name, ws_type, webhook_url, config, display_name, avatar_url)
+VALUES ($1, $2, $3, $4, $5, $6)
+RETURNING id, name, ws_type, webhook_url, config, display_name, avatar_url
             "#,
             )
             .bind(ws.name)
             .bind(ws.ws_type.to_string())
             .bind(ws.webhook_url)
+            .bind(ws.config)
+            .bind(ws.profile.display_name)
+            .bind(ws.profile.avatar_url)
             .fetch_one(&self.pool)
             .await?;
 
             Ok(entity::Workspace {
                 id: entity::WorkspaceId::new(ws.id),
+                profile: ws.profile(),
                 name: ws.name,
                 ws_type: entity::WorkspaceType::from_str(ws.ws_type.as_str())?,
                 webhook_url: ws.webhook_url,
+                config: ws.config,
             })
         }
 
@@ -103,9 +125,11 @@ RETURNING id, name, ws_type, webhook_url
 
             Ok(entity::Workspace {
                 id: entity::WorkspaceId::new(ws.id),
+                profile: ws.profile(),
                 name: ws.name,
                 ws_type: entity::WorkspaceType::from_str(ws.ws_type.as_str())?,
                 webhook_url: ws.webhook_url,
+                config: ws.config,
             })
         }
 
@@ -122,12 +146,13 @@ SELECT * FROM workspaces ORDER BY id DESC
                 .into_iter()
                 .map(|ws| entity::Workspace {
                     id: entity::WorkspaceId::new(ws.id),
+                    profile: ws.profile(),
                     name: ws.name,
-                    ws_type: entity::WorkspaceType::from_str(ws.ws_type.as_str()).expect(
-                        format!("failed to unwrap WorkspaceType from DBRow: {}", ws.ws_type)
-                            .as_str(),
+                    ws_type: entity::WorkspaceType::from_str(ws.ws_type.as_str()).unwrap_or_else(
+                        |_| panic!("failed to unwrap WorkspaceType from DBRow: {}", ws.ws_type),
                     ),
                     webhook_url: ws.webhook_url,
+                    config: ws.config,
                 })
                 .collect())
         }
@@ -136,23 +161,28 @@ SELECT * FROM workspaces ORDER BY id DESC
             let ws_row = sqlx::query_as::<_, WorkspaceDBRow>(
                 r#"
 UPDATE workspaces
-SET name = $1, ws_type = $2, webhook_url = $3
-WHERE id = $4
+SET name = $1, ws_type = $2, webhook_url = $3, config = $4, display_name = $5, avatar_url = $6
+WHERE id = $7
 RETURNING *
             "#,
             )
             .bind(payload.name)
             .bind(payload.ws_type.to_string())
             .bind(payload.webhook_url)
+            .bind(payload.config)
+            .bind(payload.profile.display_name)
+            .bind(payload.profile.avatar_url)
             .bind(payload.id.to_raw())
             .fetch_one(&self.pool)
             .await?;
 
             Ok(entity::Workspace {
                 id: entity::WorkspaceId::new(ws_row.id),
+                profile: ws_row.profile(),
                 name: ws_row.name,
                 ws_type: entity::WorkspaceType::from_str(ws_row.ws_type.as_str())?,
                 webhook_url: ws_row.webhook_url,
+                config: ws_row.config,
             })
         }
 
@@ -193,12 +223,15 @@ pub mod test_utils {
             name: String,
             ws_type: entity::WorkspaceType,
             webhook_url: String,
+            config: serde_json::Value,
         ) -> Self {
             Self {
                 id,
                 name,
                 ws_type,
                 webhook_url,
+                config,
+                profile: entity::Profile::default(),
             }
         }
     }
@@ -218,11 +251,11 @@ pub mod test_utils {
             }
         }
 
-        fn write_store_ref(&self) -> RwLockWriteGuard<WorkspaceDBOnMemory> {
+        fn write_store_ref(&self) -> RwLockWriteGuard<'_, WorkspaceDBOnMemory> {
             self.store.write().unwrap()
         }
 
-        fn read_store_ref(&self) -> RwLockReadGuard<WorkspaceDBOnMemory> {
+        fn read_store_ref(&self) -> RwLockReadGuard<'_, WorkspaceDBOnMemory> {
             self.store.read().unwrap()
         }
     }
@@ -233,14 +266,26 @@ pub mod test_utils {
             let mut store = self.write_store_ref();
             let id = entity::WorkspaceId::new(store.len() as entity::WorkspaceIdTypeAlias + 1);
             let ws_type = entity::WorkspaceType::from_str(payload.ws_type.as_str())?;
-            let ws = entity::Workspace::new(id, payload.name, ws_type, payload.webhook_url);
+            let ws = entity::Workspace {
+                profile: entity::Profile {
+                    display_name: payload.display_name,
+                    avatar_url: payload.avatar_url,
+                },
+                ..entity::Workspace::new(
+                    id,
+                    payload.name,
+                    ws_type,
+                    payload.webhook_url,
+                    payload.config,
+                )
+            };
             store.insert(ws.id.clone(), ws.clone());
             Ok(ws)
         }
 
         async fn all(&self) -> Result<Vec<entity::Workspace>> {
             let store = self.read_store_ref();
-            let ws_vec = store.values().map(|ws| ws.clone()).collect();
+            let ws_vec = store.values().cloned().collect();
             Ok(ws_vec)
         }
 
@@ -296,12 +341,14 @@ pub mod test_utils {
                     "test workspace 1".to_string(),
                     entity::WorkspaceType::Slack,
                     "https://example.com".to_string(),
+                    serde_json::json!({}),
                 ),
                 entity::Workspace::new(
                     entity::WorkspaceId::new(2),
                     "test workspace 2".to_string(),
                     entity::WorkspaceType::Slack,
                     "https://example.com".to_string(),
+                    serde_json::json!({}),
                 ),
             ];
 
@@ -317,11 +364,15 @@ pub mod test_utils {
                 "test workspace 3".to_string(),
                 entity::WorkspaceType::Slack,
                 "https://example.com".to_string(),
+                serde_json::json!({}),
             );
             let payload = CreateWorkspacePayload {
                 name: manipulate_target_data.name.clone(),
                 ws_type: manipulate_target_data.ws_type.to_string(),
                 webhook_url: manipulate_target_data.webhook_url.clone(),
+                config: manipulate_target_data.config.clone(),
+                display_name: None,
+                avatar_url: None,
             };
             let ws = repo
                 .create(payload)
@@ -340,7 +391,9 @@ pub mod test_utils {
             let mut ws_vec = repo.all().await.expect("failed to get all workspace");
             let mut expected_ws_vec = init_ws_vec.clone();
             expected_ws_vec.push(manipulate_target_data.clone());
-            assert_eq!(ws_vec.sort(), expected_ws_vec.sort());
+            ws_vec.sort();
+            expected_ws_vec.sort();
+            assert_eq!(ws_vec, expected_ws_vec);
 
             /////////////////
             // test update //
@@ -363,7 +416,10 @@ pub mod test_utils {
                 .await
                 .expect("failed to delete workspace");
             let mut ws_vec = repo.all().await.expect("failed to get all workspace");
-            assert_eq!(ws_vec.sort(), init_ws_vec.clone().sort());
+            ws_vec.sort();
+            let mut expected_ws_vec = init_ws_vec.clone();
+            expected_ws_vec.sort();
+            assert_eq!(ws_vec, expected_ws_vec);
         }
     }
 }
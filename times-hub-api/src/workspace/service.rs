@@ -3,14 +3,53 @@ use crate::repository::WorkspaceRepository;
 use anyhow::Result;
 use serde::Deserialize;
 use serde::Serialize;
+use std::str::FromStr;
 use std::sync::Arc;
+use thiserror::Error;
 use validator::Validate;
 
+#[derive(Debug, Error)]
+pub enum WorkspaceError {
+    #[error("Invalid workspace type: {0}")]
+    InvalidType(String),
+    #[error("Invalid workspace config: {0}")]
+    InvalidConfig(String),
+}
+
+/// WorkspaceType に応じて webhook_url と config を検証する関数.
+/// 送信先ごとの設定は送信する側 (message) が知っているので, main から Extension で渡す
+pub type ValidateConfig = fn(&entity::Workspace) -> Result<()>;
+
+fn validate_workspace(
+    ws: &entity::Workspace,
+    validate: ValidateConfig,
+) -> Result<(), WorkspaceError> {
+    validate(ws).map_err(|e| WorkspaceError::InvalidConfig(e.to_string()))
+}
+
+pub fn default_config() -> serde_json::Value {
+    serde_json::json!({})
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct ResponseWorkspace {
     id: entity::WorkspaceIdTypeAlias,
     name: String,
     ws_type: String,
+    display_name: Option<String>,
+    avatar_url: Option<String>,
+}
+
+impl From<entity::Workspace> for ResponseWorkspace {
+    fn from(ws: entity::Workspace) -> Self {
+        Self {
+            id: ws.id.to_raw(),
+            name: ws.name,
+            ws_type: ws.ws_type.to_string(),
+            display_name: ws.profile.display_name,
+            avatar_url: ws.profile.avatar_url,
+        }
+    }
 }
 
 /////////////
@@ -25,23 +64,85 @@ pub struct CreateWorkspacePayload {
     pub name: String,
     #[validate(length(min = 1, message = "text can not be empty"))]
     pub ws_type: String,
-    #[validate(length(min = 1, message = "text can not be empty"))]
+    // 必須かどうかは ws_type による
+    #[serde(default)]
     pub webhook_url: String,
+    #[serde(default = "default_config")]
+    pub config: serde_json::Value,
+    // 送信先に表示される送信者の名前とアイコン (未設定の場合は送信先の既定のもの)
+    #[validate(length(min = 1, max = 80, message = "display_name must be 1 to 80 characters"))]
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[validate(url(message = "avatar_url must be a URL"))]
+    #[serde(default)]
+    pub avatar_url: Option<String>,
 }
 
 pub async fn create_workspace<T>(
     repo: Arc<T>,
+    validate: ValidateConfig,
     payload: CreateWorkspacePayload,
 ) -> Result<ResponseWorkspace>
 where
     T: WorkspaceRepository,
 {
+    let ws = entity::Workspace {
+        id: entity::WorkspaceId::default(),
+        name: payload.name.clone(),
+        ws_type: entity::WorkspaceType::from_str(payload.ws_type.as_str())
+            .map_err(|_| WorkspaceError::InvalidType(payload.ws_type.clone()))?,
+        webhook_url: payload.webhook_url.clone(),
+        config: payload.config.clone(),
+        profile: entity::Profile {
+            display_name: payload.display_name.clone(),
+            avatar_url: payload.avatar_url.clone(),
+        },
+    };
+    validate_workspace(&ws, validate)?;
+
     let ws = repo.create(payload).await?;
-    Ok(ResponseWorkspace {
-        id: ws.id.to_raw(),
-        name: ws.name,
-        ws_type: ws.ws_type.to_string(),
-    })
+    Ok(ResponseWorkspace::from(ws))
+}
+
+// workspace の更新の PATCH request body
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Validate)]
+pub struct UpdateWorkspacePayload {
+    #[validate(length(min = 1, message = "text can not be empty"))]
+    #[validate(length(max = 100, message = "text can not be longer than 100 characters"))]
+    pub name: String,
+    #[validate(length(min = 1, message = "text can not be empty"))]
+    pub ws_type: String,
+    // 省略した場合は今の webhook_url のまま
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    // 今の config に JSON Merge Patch (RFC 7396) として当てる. 省略した場合は今の config のまま
+    #[serde(default)]
+    pub config: Option<serde_json::Value>,
+    // 送信先に表示される送信者の名前とアイコン (未設定の場合は送信先の既定のもの)
+    #[validate(length(min = 1, max = 80, message = "display_name must be 1 to 80 characters"))]
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[validate(url(message = "avatar_url must be a URL"))]
+    #[serde(default)]
+    pub avatar_url: Option<String>,
+}
+
+/// `config` に JSON Merge Patch (RFC 7396) の `patch` を当てる. null の項目は削除する
+fn merge_config(config: serde_json::Value, patch: serde_json::Value) -> serde_json::Value {
+    match (config, patch) {
+        (serde_json::Value::Object(mut config), serde_json::Value::Object(patch)) => {
+            for (key, value) in patch {
+                if value.is_null() {
+                    config.remove(&key);
+                    continue;
+                }
+                let current = config.remove(&key).unwrap_or_default();
+                config.insert(key, merge_config(current, value));
+            }
+            serde_json::Value::Object(config)
+        }
+        (_, patch) => patch,
+    }
 }
 
 pub async fn all_workspaces<T>(repo: Arc<T>) -> Result<Vec<ResponseWorkspace>>
@@ -51,14 +152,7 @@ where
     let ws_vec = repo.all().await?;
 
     // convert Workspace to ResponseWorkspace
-    let ws_vec = ws_vec
-        .into_iter()
-        .map(|ws| ResponseWorkspace {
-            id: ws.id.to_raw(),
-            name: ws.name,
-            ws_type: ws.ws_type.to_string(),
-        })
-        .collect();
+    let ws_vec = ws_vec.into_iter().map(ResponseWorkspace::from).collect();
     Ok(ws_vec)
 }
 
@@ -67,23 +161,45 @@ where
     T: WorkspaceRepository,
 {
     let ws = repo.find(id).await?;
-    Ok(ResponseWorkspace {
-        id: ws.id.to_raw(),
-        name: ws.name,
-        ws_type: ws.ws_type.to_string(),
-    })
+    Ok(ResponseWorkspace::from(ws))
 }
 
-pub async fn update_workspace<T>(repo: Arc<T>, ws: entity::Workspace) -> Result<ResponseWorkspace>
+/// 省略した webhook_url と config は今の設定を残す.
+/// config の secret は GET で返さないので, 変える項目だけを送れるように今の config に merge する
+pub async fn update_workspace<T>(
+    repo: Arc<T>,
+    validate: ValidateConfig,
+    id: entity::WorkspaceId,
+    payload: UpdateWorkspacePayload,
+) -> Result<ResponseWorkspace>
 where
     T: WorkspaceRepository,
 {
+    let current = repo.find(id.clone()).await?;
+    let ws_type = entity::WorkspaceType::from_str(payload.ws_type.as_str())
+        .map_err(|_| WorkspaceError::InvalidType(payload.ws_type.clone()))?;
+    // ws_type を変える場合は, 別の WorkspaceType の webhook_url と config を引き継がない
+    let (current_url, current_config) = match ws_type == current.ws_type {
+        true => (current.webhook_url, current.config),
+        false => (String::new(), default_config()),
+    };
+    let ws = entity::Workspace {
+        id,
+        name: payload.name,
+        ws_type,
+        webhook_url: payload.webhook_url.unwrap_or(current_url),
+        config: match payload.config {
+            Some(patch) => merge_config(current_config, patch),
+            None => current_config,
+        },
+        profile: entity::Profile {
+            display_name: payload.display_name,
+            avatar_url: payload.avatar_url,
+        },
+    };
+    validate_workspace(&ws, validate)?;
     let ws = repo.update(ws).await?;
-    Ok(ResponseWorkspace {
-        id: ws.id.to_raw(),
-        name: ws.name,
-        ws_type: ws.ws_type.to_string(),
-    })
+    Ok(ResponseWorkspace::from(ws))
 }
 
 pub async fn delete_workspace<T>(repo: Arc<T>, id: entity::WorkspaceId) -> Result<()>
@@ -93,3 +209,98 @@ where
     repo.delete(id).await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message::service::validate_config;
+    use crate::workspace::repository::test_utils::WorkspaceRepositoryForMemory;
+
+    fn update_payload(ws_type: &str, config: Option<serde_json::Value>) -> UpdateWorkspacePayload {
+        UpdateWorkspacePayload {
+            name: "renamed".to_string(),
+            ws_type: ws_type.to_string(),
+            webhook_url: None,
+            config,
+            display_name: None,
+            avatar_url: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn update_keeps_omitted_config() {
+        let repo = Arc::new(WorkspaceRepositoryForMemory::new());
+        let created = create_workspace(
+            repo.clone(),
+            validate_config,
+            CreateWorkspacePayload {
+                name: "telegram".to_string(),
+                ws_type: "telegram".to_string(),
+                webhook_url: String::new(),
+                config: serde_json::json!({"bot_token": "secret", "chat_id": 1, "long_text": "reject"}),
+                display_name: None,
+                avatar_url: None,
+            },
+        )
+        .await
+        .unwrap();
+        let id = entity::WorkspaceId::new(created.id);
+
+        // config を省略した場合は secret を含めて今の config のまま
+        let updated = update_workspace(
+            repo.clone(),
+            validate_config,
+            id.clone(),
+            update_payload("telegram", None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(updated.name, "renamed");
+        let ws = repo.find(id.clone()).await.unwrap();
+        assert_eq!(
+            ws.config,
+            serde_json::json!({"bot_token": "secret", "chat_id": 1, "long_text": "reject"})
+        );
+
+        // 送った項目だけを変え, null の項目は削除する
+        let patch = serde_json::json!({"parse_mode": "HTML", "long_text": null});
+        update_workspace(
+            repo.clone(),
+            validate_config,
+            id.clone(),
+            update_payload("telegram", Some(patch)),
+        )
+        .await
+        .unwrap();
+        let ws = repo.find(id.clone()).await.unwrap();
+        assert_eq!(
+            ws.config,
+            serde_json::json!({"bot_token": "secret", "chat_id": 1, "parse_mode": "HTML"})
+        );
+
+        // 必須の項目を消す patch は検証で拒否し, 保存しない
+        let patch = serde_json::json!({"bot_token": null});
+        let e = update_workspace(
+            repo.clone(),
+            validate_config,
+            id.clone(),
+            update_payload("telegram", Some(patch)),
+        )
+        .await
+        .expect_err("config without bot_token should be rejected");
+        assert!(e.downcast_ref::<WorkspaceError>().is_some());
+
+        // ws_type を変える場合は以前の config を引き継がない
+        let e = update_workspace(
+            repo.clone(),
+            validate_config,
+            id.clone(),
+            update_payload("matrix", None),
+        )
+        .await
+        .expect_err("matrix without config should be rejected");
+        assert!(e.downcast_ref::<WorkspaceError>().is_some());
+        let ws = repo.find(id).await.unwrap();
+        assert_eq!(ws.ws_type, entity::WorkspaceType::Telegram);
+    }
+}